@@ -24,7 +24,7 @@ fn main() {
     let mut cur_chr = String::new();
     let mut pos: u64 = 1;
     for line in buf.lines() {
-        let parts = line.unwrap().split(" ").map(|s| s.to_string()).collect::<Vec<_>>();
+        let parts = line.unwrap().split_whitespace().map(|s| s.to_string()).collect::<Vec<_>>();
         
         // bed is 0-indexed and wiggle is 1-indexed, so we need to add 1 here
         let start = parts[1].parse::<u64>().unwrap() + 1;
@@ -37,7 +37,7 @@ fn main() {
         
         
         if cur_chr.is_empty() || cur_chr != parts[0] {
-            write!(out, "fixedStep  chrom={} start=1 step=1\n", parts[0]).unwrap();
+            writeln!(out, "fixedStep  chrom={} start=1 step=1", parts[0]).unwrap();
             
             pos = 1;
             cur_chr = parts[0].clone();
@@ -45,10 +45,10 @@ fn main() {
         
         while pos <= end {
             if pos < start {
-                write!(out, "0\n").unwrap();
+                writeln!(out, "0").unwrap();
             }
             else {
-                write!(out, "{}\n", parts[7]).unwrap();
+                writeln!(out, "{}", parts[7]).unwrap();
             }
             
             pos += 1;
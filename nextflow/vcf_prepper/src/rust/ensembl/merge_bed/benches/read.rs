@@ -0,0 +1,66 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Throughput of merging two inputs read through a buffer and through a memory map, reported in lines per second
+//!
+//! Run with `cargo bench --features mmap`; without the feature only the buffered reads are measured. The inputs are
+//! generated, sorted by id, and every other line of one has an id of the other.
+
+use std::{fs, path::Path, process::Command};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tempfile::TempDir;
+
+const LINES: usize = 200_000;
+
+fn write_input(path: &Path, offset: usize) {
+    let consequences = ["intron_variant", "missense_variant", "stop_gained", "upstream_gene_variant"];
+    let mut bed = String::new();
+    for i in 0..LINES {
+        let id = i * 2 - i % 2 * offset;
+        bed += &format!("1\t{}\t{}\trs{:09}\tSNV\tA\tT\t3\t{}\n", id * 10, id * 10 + 1, id, consequences[(i + offset) % 4]);
+    }
+    fs::write(path, bed).unwrap();
+}
+
+fn merge(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let inputs = [dir.path().join("a.bed"), dir.path().join("b.bed")];
+    write_input(&inputs[0], 0);
+    write_input(&inputs[1], 1);
+    let output = dir.path().join("merged.bed");
+
+    let mut readings = vec![("buffered", &[][..])];
+    if cfg!(feature = "mmap") {
+        readings.push(("mmap", &["--mmap"][..]));
+    }
+    let mut group = c.benchmark_group("merge");
+    group.throughput(Throughput::Elements(2 * LINES as u64));
+    group.sample_size(10);
+    for (mode, extra) in [("keep_all", &["--keep-all"][..]), ("sorted", &["--sorted"][..]), ("unsorted", &[][..])] {
+        for (reading, flag) in &readings {
+            group.bench_function(format!("{}/{}", mode, reading), |b| b.iter(|| {
+                let status = Command::new(env!("CARGO_BIN_EXE_merge_bed"))
+                    .args(extra).args(*flag).arg(&output).args(&inputs)
+                    .status().unwrap();
+                assert!(status.success());
+            }));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, merge);
+criterion_main!(benches);
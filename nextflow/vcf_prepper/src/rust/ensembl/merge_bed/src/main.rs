@@ -14,38 +14,555 @@
  * limitations under the License.
  */
  
-use std::{io::{BufReader, BufRead, Write}, fs::File, env, collections::{HashSet}};
-
-fn main() {
-    // read cli arguments
-    let args = env::args().collect::<Vec<_>>();
-    let mut out = File::create(&args[1]).unwrap();
-
-    let mut current_ids = HashSet::new();
-    let mut file_counter = 2;
-    while file_counter < args.len() {
-        let reader = BufReader::new(File::open(&args[file_counter]).unwrap());
-        for line in reader.lines() {
+use std::{borrow::Cow, io::{self, BufReader, BufRead, Write, BufWriter}, fs::File, collections::HashMap, process::ExitCode};
+use clap::Parser;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::{debug, error, warn};
+use vcf_to_bed::{SEVERITY, binary, read_severity, sort::{Ranking, Sorter, fai_order}};
+
+#[cfg(feature = "mmap")]
+mod mmap;
+
+/// Merge BED files, keeping the most severe line of each variant id
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Output BED file
+    output: String,
+
+    /// Gzip the output, implied by an output file name ending in .gz
+    #[arg(long)]
+    gzip: bool,
+
+    /// Separator between the fields of an output line, e.g. ' ' for the space separated output of older versions;
+    /// inputs may be separated by any whitespace
+    #[arg(long, default_value = "\t")]
+    delimiter: String,
+
+    /// Input BED files, for duplicate ids of the same severity the line from the earlier file is kept; without
+    /// --sorted or --keep-all they are read twice, so they cannot be pipes
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Format of the inputs, the output is BED either way
+    #[arg(long, value_enum, default_value_t = Format::Bed)]
+    format: Format,
+
+    /// Read the inputs through a memory map instead of a buffer, for builds with the mmap feature; the inputs have to
+    /// be regular files, which must not be truncated while they are read, and gzipped ones are still read through a buffer
+    #[arg(long)]
+    mmap: bool,
+
+    /// JSON or tab separated file mapping consequence terms to severity rank, used to compare the consequence column
+    /// [default: the built-in ranks of vcf_to_bed]; lines whose terms it does not rank are compared by variant group
+    #[arg(short, long)]
+    severity: Option<String>,
+
+    /// Inputs are each sorted by id in byte order (as by LC_ALL=C sort -k4,4), or by the --key-fields in turn -
+    /// merge them as a stream instead of holding every id in memory, failing on an input that is out of order
+    #[arg(long)]
+    sorted: bool,
+
+    /// Columns, numbered from 0, that together identify the variant of a line, comma separated; the id column by
+    /// default, e.g. 0,1,2 for the coordinates
+    #[arg(long, value_delimiter = ',', default_value = "3", value_parser = clap::value_parser!(u8).range(..FIELDS as i64))]
+    key_fields: Vec<u8>,
+
+    /// Keep every line, duplicate ids included, writing the inputs one after the other
+    #[arg(long, conflicts_with_all = ["sorted", "severity"])]
+    keep_all: bool,
+
+    /// Warn at the end about lines at the same chromosome, start and end as an earlier line of another id,
+    /// a sign of variants that were not normalised the same way
+    #[arg(long)]
+    warn_coord_collisions: bool,
+
+    /// Sort the merged lines by chromosome, start and end instead of writing them in the order their ids were met
+    #[arg(long)]
+    sort: bool,
+
+    /// FASTA index (.fai) whose contig order --sort uses instead of byte order, as bedToBigBed expects
+    #[arg(long, value_name = "FAI", requires = "sort")]
+    genome: Option<String>,
+
+    /// Number of lines --sort holds in memory before spilling sorted chunks to temporary files
+    #[arg(long, value_name = "LINES", default_value_t = 1_000_000)]
+    sort_buffer: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Format {
+    /// BED lines
+    Bed,
+    /// binary records written by vcf_to_bed --output-format bin
+    Bin
+}
+
+// ranks of the severity file, or the built-in ranks of vcf_to_bed - unranked lines are compared by variant group
+// as vcf_to_bed --dedup compares them
+fn load_ranking(severity_file: &Option<String>) -> io::Result<Ranking> {
+    let severity = match severity_file {
+        // plain or gzipped, JSON or tab separated, as vcf_to_bed reads it
+        Some(path) => read_severity(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => SEVERITY.iter().map(|(term, rank)| (term.to_string(), *rank)).collect()
+    };
+    Ok(Ranking::new(severity))
+}
+
+// the reader, decompressing it if it is gzipped
+fn decompressed(mut reader: impl BufRead + 'static) -> io::Result<Box<dyn BufRead>> {
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// a binary record as the tab separated BED line it was written from
+fn record_line(record: binary::Record) -> String {
+    let mut columns = vec![record.chromosome, record.start.to_string(), record.end.to_string()];
+    columns.extend(record.columns);
+    columns.join("\t")
+}
+
+// how the inputs are read
+#[derive(Clone, Copy)]
+struct Reading {
+    format: Format,
+    mmap: bool
+}
+
+type Lines<'a> = Box<dyn Iterator<Item = io::Result<Cow<'a, str>>> + 'a>;
+
+// an open input - the lines of a mapped file are read in place, those of a buffered or gzipped one are copied out
+enum Input {
+    #[cfg(feature = "mmap")]
+    Mapped(File, mmap::Mapped),
+    Buffered(Box<dyn BufRead>)
+}
+
+impl Input {
+    // a gzipped input is decompressed through a buffer whether or not --mmap asks for a map, and only regular
+    // files are mapped, as the map covers the length the file has when it is opened
+    fn open(path: &str, mmap: bool) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if !mmap {
+            return Ok(Input::Buffered(decompressed(BufReader::new(file))?));
+        }
+        #[cfg(not(feature = "mmap"))]
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "--mmap needs merge_bed built with the mmap feature"));
+
+        #[cfg(feature = "mmap")]
+        {
+            if !file.metadata()?.is_file() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: --mmap only reads regular files", path)));
+            }
+            let mut reader = BufReader::new(file);
+            if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+                return Ok(Input::Buffered(decompressed(reader)?));
+            }
+            let file = reader.into_inner();
+            let map = mmap::Mapped::open(&file)?;
+            Ok(Input::Mapped(file, map))
+        }
+    }
+
+    // the lines of the input, read back from its binary records with --format bin
+    fn lines<'a>(&'a mut self, name: &'a str, format: Format) -> io::Result<Lines<'a>> {
+        match self {
+            #[cfg(feature = "mmap")]
+            Input::Mapped(file, map) => {
+                let bytes: &[u8] = (*map).as_ref();
+                let length = bytes.len() as u64;
+                // a file whose length changed while it was read is not the file that was mapped
+                let changed = std::iter::once_with(move || match file.metadata() {
+                    Ok(metadata) if metadata.len() == length => None,
+                    Ok(_) => Some(Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed while it was read", name)))),
+                    Err(e) => Some(Err(e))
+                }).flatten();
+                let lines: Lines = match format {
+                    // a file ending in a newline ends in an empty line, which is skipped as any other
+                    Format::Bed => Box::new(bytes.split(|byte| *byte == b'\n').map(move |line| {
+                        std::str::from_utf8(line).map(Cow::Borrowed)
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, e)))
+                    })),
+                    Format::Bin => records(name, bytes)?
+                };
+                Ok(Box::new(lines.chain(changed)))
+            },
+            Input::Buffered(reader) => match format {
+                Format::Bed => Ok(Box::new(reader.lines().map(|line| line.map(Cow::Owned)))),
+                Format::Bin => records(name, reader)
+            }
+        }
+    }
+}
+
+// the binary records of an input after its magic
+fn records<'a>(name: &'a str, mut reader: impl BufRead + 'a) -> io::Result<Lines<'a>> {
+    let in_input = move |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", name, e));
+    binary::read_magic(&mut reader).map_err(in_input)?;
+    Ok(Box::new(std::iter::from_fn(move || {
+        binary::read_record(&mut reader).map_err(in_input).map(|record| record.map(|record| Cow::Owned(record_line(record)))).transpose()
+    })))
+}
+
+// plain or gzipped output - finish has to be called to complete the gzip stream
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>)
+}
+
+impl Output {
+    fn create(path: &str, gzip: bool) -> io::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        if gzip || path.ends_with(".gz") {
+            Ok(Output::Gzip(GzEncoder::new(writer, Compression::default())))
+        } else {
+            Ok(Output::Plain(writer))
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush()
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush()
+        }
+    }
+}
+
+// fields of the layout written by vcf_to_bed, any after them are kept as they are
+const FIELDS: usize = 9;
+
+// a line of an input as it was read - tab separated, or separated by any whitespace as in the output of older
+// versions, so that a field can hold spaces only if the line is tab separated
+#[derive(Clone)]
+struct BedLine<'a>(Cow<'a, str>);
+
+impl<'a> BedLine<'a> {
+    // the line if it has at least the fields of the layout, empty lines give None - trailing whitespace, such as
+    // the \r of a line ending in \r\n, is left out
+    fn parse(line: Cow<'a, str>, name: &str, line_number: usize) -> io::Result<Option<Self>> {
+        let line = BedLine(match line {
+            Cow::Borrowed(line) => Cow::Borrowed(line.trim_end()),
+            Cow::Owned(mut line) => {
+                line.truncate(line.trim_end().len());
+                Cow::Owned(line)
+            }
+        });
+        match line.fields().len() {
+            0 => Ok(None),
+            n if n >= FIELDS => Ok(Some(line)),
+            n => Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "{} line {}: expected at least {} fields, found {}", name, line_number, FIELDS, n
+            )))
+        }
+    }
+
+    // the line, copied out of the input it was read from
+    fn into_owned(self) -> BedLine<'static> {
+        BedLine(Cow::Owned(self.0.into_owned()))
+    }
+
+    // parsed lines have every field, so they all have a rank
+    fn rank(&self, ranking: &Ranking) -> (u8, u8) {
+        ranking.rank(&self.fields()).unwrap_or((u8::MAX, u8::MAX))
+    }
+
+    fn tab_separated(&self) -> bool {
+        self.0.contains('\t')
+    }
+
+    fn fields(&self) -> Vec<&str> {
+        if self.tab_separated() {
+            self.0.split('\t').collect()
+        } else {
+            self.0.split_whitespace().collect()
+        }
+    }
+
+    fn id(&self) -> &str {
+        self.fields()[3]
+    }
+
+    // the values of the key fields, tab separated so that keys sort as their fields do one after the other
+    fn key(&self, key_fields: &[usize]) -> Cow<'_, str> {
+        let fields = self.fields();
+        match key_fields {
+            [field] => Cow::Borrowed(fields[*field]),
+            _ => Cow::Owned(key_fields.iter().map(|field| fields[*field]).collect::<Vec<_>>().join("\t"))
+        }
+    }
+
+    fn consequence(&self) -> &str {
+        self.fields()[8]
+    }
+
+    // the line as it was read, unless it has to be rejoined with another delimiter
+    fn write(&self, out: &mut dyn Write, delimiter: &str) -> io::Result<()> {
+        if self.tab_separated() && delimiter == "\t" {
+            writeln!(out, "{}", self.0)
+        } else {
+            writeln!(out, "{}", self.fields().join(delimiter))
+        }
+    }
+}
+
+// writes the merged lines, counting those at the coordinates of an earlier line of another id if asked to
+struct LineWriter<'a> {
+    out: &'a mut dyn Write,
+    delimiter: &'a str,
+    // id of the first line written at each chromosome, start and end
+    coordinates: Option<HashMap<(String, String, String), String>>,
+    collisions: u64
+}
+
+impl<'a> LineWriter<'a> {
+    fn new(out: &'a mut dyn Write, delimiter: &'a str, check_coordinates: bool) -> Self {
+        LineWriter { out, delimiter, coordinates: check_coordinates.then(HashMap::new), collisions: 0 }
+    }
+
+    fn write(&mut self, line: &BedLine) -> io::Result<()> {
+        if let Some(coordinates) = &mut self.coordinates {
+            let fields = line.fields();
+            let key = (fields[0].to_string(), fields[1].to_string(), fields[2].to_string());
+            match coordinates.get(&key) {
+                Some(id) if id != line.id() => {
+                    debug!("{} is at {}:{}-{} as {}", line.id(), key.0, key.1, key.2, id);
+                    self.collisions += 1;
+                },
+                Some(_) => {},
+                None => {
+                    coordinates.insert(key, line.id().to_string());
+                }
+            }
+        }
+        line.write(self.out, self.delimiter)
+    }
+
+    fn finish(self) {
+        if self.collisions > 0 {
+            warn!("{} lines are at the coordinates of an earlier line with another id", self.collisions);
+        }
+    }
+}
+
+// every line of the inputs in turn, checked and written with the delimiter as the other merges would
+fn concatenate(inputs: &[String], reading: Reading, out: &mut LineWriter) -> io::Result<()> {
+    for input in inputs {
+        for (index, line) in Input::open(input, reading.mmap)?.lines(input, reading.format)?.enumerate() {
+            if let Some(line) = BedLine::parse(line?, input, index + 1)? {
+                out.write(&line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// where a key was first seen, and the line that outranks it if a later one did
+struct Seen {
+    input: usize,
+    line_number: usize,
+    rank: (u8, u8),
+    replacement: Option<BedLine<'static>>
+}
+
+// inputs can be in any order, so they are read twice: first to find the most severe line of every key, keeping
+// only its rank and where the key was first seen, then to write each key where it was first seen - the text of
+// a line is held only when it replaces an earlier one, so inputs without duplicates are streamed
+fn merge_unsorted(inputs: &[String], reading: Reading, key_fields: &[usize], ranking: &Ranking, out: &mut LineWriter) -> io::Result<()> {
+    let mut seen: HashMap<String, Seen> = HashMap::new();
+    for (input_index, input) in inputs.iter().enumerate() {
+        for (index, line) in Input::open(input, reading.mmap)?.lines(input, reading.format)?.enumerate() {
             
-            let parts = line.unwrap().split(" ").map(|s| s.to_string()).collect::<Vec<_>>();
+            let Some(line) = BedLine::parse(line?, input, index + 1)? else { continue };
             
-            if !current_ids.contains(&parts[3]) {
-                write!(out, "{} {} {} {} {} {} {} {} {}\n",
-                    parts[0], 
-                    parts[1],
-                    parts[2],
-                    parts[3], 
-                    parts[4], 
-                    parts[5],
-                    parts[6],
-                    parts[7],
-                    parts[8]
-                ).unwrap();
-                
-                current_ids.insert(parts[3].clone());
+            let rank = line.rank(ranking);
+            match seen.get_mut(line.key(key_fields).as_ref()) {
+                None => {
+                    let first = Seen { input: input_index, line_number: index, rank, replacement: None };
+                    seen.insert(line.key(key_fields).to_string(), first);
+                },
+                Some(first) if rank < first.rank => {
+                    debug!("replacing {} with more severe {} from {}", line.id(), line.consequence(), input);
+                    first.rank = rank;
+                    first.replacement = Some(line.into_owned());
+                },
+                Some(_) => debug!("skipping {} from {} - already seen", line.id(), input)
+            }
+        }
+    }
+    
+    for (input_index, input) in inputs.iter().enumerate() {
+        for (index, line) in Input::open(input, reading.mmap)?.lines(input, reading.format)?.enumerate() {
+            let Some(line) = BedLine::parse(line?, input, index + 1)? else { continue };
+            let first = seen.get(line.key(key_fields).as_ref())
+                .filter(|first| first.input == input_index && first.line_number == index);
+            match first {
+                Some(Seen { replacement: Some(replacement), .. }) => out.write(replacement)?,
+                Some(_) => out.write(&line)?,
+                None => {}
             }
         }
+    }
+    Ok(())
+}
+
+// one of the inputs of the streaming merge, holding its next line
+struct SortedInput<'a> {
+    name: &'a str,
+    key_fields: &'a [usize],
+    lines: Lines<'a>,
+    line_number: usize,
+    current: Option<BedLine<'a>>
+}
+
+impl<'a> SortedInput<'a> {
+    fn open(name: &'a str, input: &'a mut Input, format: Format, key_fields: &'a [usize]) -> io::Result<Self> {
+        let mut input = SortedInput {
+            name,
+            key_fields,
+            lines: input.lines(name, format)?,
+            line_number: 0,
+            current: None
+        };
+        input.advance()?;
+        Ok(input)
+    }
+
+    fn key(&self) -> Option<Cow<'_, str>> {
+        self.current.as_ref().map(|line| line.key(self.key_fields))
+    }
+
+    // read the next line, failing if it is not sorted after the current one
+    fn advance(&mut self) -> io::Result<()> {
+        let next = loop {
+            let Some(line) = self.lines.next().transpose()? else { break None };
+            self.line_number += 1;
+            if let Some(line) = BedLine::parse(line, self.name, self.line_number)? {
+                break Some(line);
+            }
+        };
+
+        if let (Some(previous), Some(next)) = (&self.current, &next) {
+            if next.key(self.key_fields) < previous.key(self.key_fields) {
+                let by = if self.key_fields == [3] { "id" } else { "key" };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "{} is not sorted by {}: {} on line {} comes after {}",
+                    self.name, by, next.key(self.key_fields), self.line_number, previous.key(self.key_fields)
+                )));
+            }
+        }
+
+        self.current = next;
+        Ok(())
+    }
+}
+
+// k-way merge of inputs sorted by key, only the line of the current key is kept in memory
+fn merge_sorted(inputs: &[String], reading: Reading, key_fields: &[usize], ranking: &Ranking, out: &mut LineWriter) -> io::Result<()> {
+    let mut opened = inputs.iter()
+        .map(|name| Input::open(name, reading.mmap))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut inputs = inputs.iter().zip(&mut opened)
+        .map(|(name, input)| SortedInput::open(name, input, reading.format, key_fields))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut pending: Option<BedLine> = None;
+
+    loop {
+        // smallest key among the inputs - on ties the earlier input wins
+        let next = inputs.iter_mut()
+            .filter(|input| input.current.is_some())
+            .min_by(|a, b| a.key().cmp(&b.key()));
+        let Some(input) = next else { break };
         
-        file_counter += 1;
+        if let Some(line) = &input.current {
+            match &pending {
+                Some(current) if current.key(key_fields) == line.key(key_fields) => {
+                    if line.rank(ranking) < current.rank(ranking) {
+                        debug!("replacing {} with more severe {} from {}", line.id(), line.consequence(), input.name);
+                        pending = Some(line.clone());
+                    }
+                    else {
+                        debug!("skipping {} from {} - already seen", line.id(), input.name);
+                    }
+                },
+                _ => {
+                    if let Some(current) = pending.replace(line.clone()) {
+                        out.write(&current)?;
+                    }
+                }
+            }
+        }
+        input.advance()?;
+    }
+    
+    if let Some(current) = pending {
+        out.write(&current)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let ranking = load_ranking(&args.severity)?;
+    let mut out = Output::create(&args.output, args.gzip)?;
+    let mut sorter = match &args.genome {
+        Some(genome) => {
+            let contig_order = fai_order(genome).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", genome, e)))?;
+            Some(Sorter::new(&args.delimiter, args.sort_buffer).contig_order(contig_order))
+        },
+        None => args.sort.then(|| Sorter::new(&args.delimiter, args.sort_buffer))
+    };
+
+    let merged: &mut dyn Write = match &mut sorter {
+        Some(sorter) => sorter,
+        None => &mut out
+    };
+    let reading = Reading { format: args.format, mmap: args.mmap };
+    let key_fields = args.key_fields.iter().map(|field| *field as usize).collect::<Vec<_>>();
+    let mut writer = LineWriter::new(merged, &args.delimiter, args.warn_coord_collisions);
+    if args.keep_all {
+        concatenate(&args.inputs, reading, &mut writer)?;
+    }
+    else if args.sorted {
+        merge_sorted(&args.inputs, reading, &key_fields, &ranking, &mut writer)?;
+    }
+    else {
+        merge_unsorted(&args.inputs, reading, &key_fields, &ranking, &mut writer)?;
+    }
+    writer.finish();
+    if let Some(sorter) = sorter {
+        sorter.finish(&mut out)?;
     }
-}
\ No newline at end of file
+    
+    out.finish()
+}
@@ -0,0 +1,69 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Inputs read through a read-only memory map for --mmap
+//!
+//! The map is made with mmap(2) of libc, so this is for unix. The pages of the file are the bytes of the map, and
+//! its lines are read from them in place; a file that is truncated while it is mapped ends the process with SIGBUS
+//! when the missing pages are read, which is why --mmap is not the default and only maps regular files.
+
+use std::{fs::File, io, os::fd::AsRawFd, ptr, slice};
+
+/// The whole of a file, mapped read-only until it is dropped
+pub struct Mapped {
+    address: *mut libc::c_void,
+    length: usize
+}
+
+impl Mapped {
+    pub fn open(file: &File) -> io::Result<Self> {
+        let length = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        // mmap refuses an empty mapping
+        if length == 0 {
+            return Ok(Mapped { address: ptr::null_mut(), length });
+        }
+
+        // memmap2 unavailable offline, hence libc
+        // SAFETY: a new private read-only mapping of an open file, which only this Mapped refers to
+        let address = unsafe { libc::mmap(ptr::null_mut(), length, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: advice on the mapping just made, which does not change its contents
+        unsafe { libc::madvise(address, length, libc::MADV_SEQUENTIAL) };
+        Ok(Mapped { address, length })
+    }
+}
+
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        if self.length == 0 {
+            return &[];
+        }
+        // SAFETY: the mapping is readable for its length until it is unmapped in drop
+        unsafe { slice::from_raw_parts(self.address as *const u8, self.length) }
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        if self.length > 0 {
+            // SAFETY: the mapping made in open, which nothing refers to past this
+            unsafe { libc::munmap(self.address, self.length) };
+        }
+    }
+}
@@ -0,0 +1,532 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+ 
+use std::{fs, io::{Read, Write}, process::Command};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use tempfile::TempDir;
+use vcf_to_bed::binary;
+
+const RANK_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../assets/variation_consequnce_rank.json");
+
+fn merge_bed() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_merge_bed"))
+}
+
+// BED line in the layout written by vcf_to_bed
+fn bed_line(chromosome: &str, start: u64, id: &str, severity: &str) -> String {
+    format!("{}\t{}\t{}\t{}\tSNV\tA\tT\t3\t{}\n", chromosome, start, start + 1, id, severity)
+}
+
+// gzipped if the name ends in .gz
+fn write_bed(dir: &TempDir, name: &str, lines: &[String]) -> String {
+    let path = dir.path().join(name);
+    if name.ends_with(".gz") {
+        let mut encoder = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(lines.concat().as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+    else {
+        fs::write(&path, lines.concat()).unwrap();
+    }
+    path.to_str().unwrap().to_string()
+}
+
+// BED lines as the binary records of vcf_to_bed --output-format bin
+fn write_bin(dir: &TempDir, name: &str, lines: &[String]) -> String {
+    let mut content = binary::MAGIC.to_vec();
+    for line in lines {
+        let fields = line.trim_end().split('\t').collect::<Vec<_>>();
+        binary::write_record(&mut content, fields[0], fields[1].parse().unwrap(), fields[2].parse().unwrap(), &fields[3..]).unwrap();
+    }
+    let path = dir.path().join(name);
+    fs::write(&path, content).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+// run merge_bed on the inputs, returning the exit status, output BED and stderr
+fn merge(dir: &TempDir, inputs: &[String], extra: &[&str]) -> (bool, String, String) {
+    let output = dir.path().join("merged.bed");
+    let result = merge_bed()
+        .args(extra)
+        .arg(&output)
+        .args(inputs)
+        .output().unwrap();
+
+    (result.status.success(), fs::read_to_string(&output).unwrap_or_default(), String::from_utf8_lossy(&result.stderr).to_string())
+}
+
+#[test]
+fn duplicate_ids_keep_first_line() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs2", "intron_variant"), bed_line("1", 200, "rs1", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 110, "rs2", "intron_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+    let (success, bed, _) = merge(&dir, &inputs, &[]);
+
+    assert!(success);
+    assert_eq!(bed, [
+        bed_line("1", 100, "rs2", "intron_variant"),
+        bed_line("1", 200, "rs1", "intron_variant"),
+        bed_line("1", 300, "rs3", "intron_variant"),
+    ].concat());
+}
+
+#[test]
+fn first_seen_line_of_ids_across_files_is_kept() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        // rs1 twice within the same file
+        write_bed(&dir, "a.bed", &[
+            bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 150, "rs2", "intron_variant"),
+            bed_line("1", 120, "rs1", "intron_variant"),
+        ]),
+        write_bed(&dir, "empty.bed", &[]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 160, "rs2", "intron_variant"), bed_line("2", 100, "rs3", "intron_variant")]),
+        write_bed(&dir, "c.bed", &[bed_line("2", 110, "rs3", "intron_variant"), bed_line("1", 130, "rs1", "intron_variant"),
+            bed_line("3", 50, "rs4", "intron_variant")]),
+    ];
+    let (success, bed, _) = merge(&dir, &inputs, &[]);
+
+    assert!(success);
+    assert_eq!(bed, [
+        bed_line("1", 100, "rs1", "intron_variant"),
+        bed_line("1", 150, "rs2", "intron_variant"),
+        bed_line("2", 100, "rs3", "intron_variant"),
+        bed_line("3", 50, "rs4", "intron_variant"),
+    ].concat());
+}
+
+#[test]
+fn empty_inputs_give_empty_output() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [write_bed(&dir, "a.bed", &[]), write_bed(&dir, "b.bed.gz", &[]), write_bed(&dir, "c.bed", &["\n".to_string()])];
+
+    for extra in [&[] as &[&str], &["--sorted"], &["--keep-all"]] {
+        let (success, bed, _) = merge(&dir, &inputs, extra);
+        assert!(success);
+        assert_eq!(bed, "");
+    }
+}
+
+#[test]
+fn keep_all_writes_duplicates() {
+    let dir = TempDir::new().unwrap();
+    let a = [bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 120, "rs1", "intron_variant")];
+    let b = [bed_line("1", 100, "rs1", "missense_variant"), "\n".to_string(), bed_line("1", 300, "rs3", "intron_variant")];
+    let inputs = [write_bed(&dir, "a.bed", &a), write_bed(&dir, "b.bed.gz", &b)];
+
+    let (success, bed, _) = merge(&dir, &inputs, &["--keep-all"]);
+    assert!(success);
+    assert_eq!(bed, [&a[..], &[b[0].clone(), b[2].clone()]].concat().concat());
+
+    let (success, _, stderr) = merge(&dir, &inputs, &["--keep-all", "--sorted"]);
+    assert!(!success);
+    assert!(stderr.contains("cannot be used with"), "{}", stderr);
+}
+
+#[test]
+fn coordinate_collisions_are_counted() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 100, "rs9", "intron_variant")]),
+    ];
+
+    for extra in [&["--warn-coord-collisions"][..], &["--warn-coord-collisions", "--sorted"][..]] {
+        let (success, bed, stderr) = merge(&dir, &inputs, extra);
+        assert!(success);
+        assert_eq!(bed.lines().count(), 3);
+        assert!(stderr.contains("1 lines are at the coordinates of an earlier line with another id"), "{}", stderr);
+    }
+
+    let (success, _, stderr) = merge(&dir, &inputs, &[]);
+    assert!(success);
+    assert!(!stderr.contains("coordinates"), "{}", stderr);
+}
+
+#[test]
+fn sorted_inputs_are_merged_as_a_stream() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 200, "rs1", "intron_variant"), bed_line("1", 100, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 110, "rs2", "intron_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+    let (success, bed, _) = merge(&dir, &inputs, &["--sorted"]);
+
+    assert!(success);
+    assert_eq!(bed, [
+        bed_line("1", 200, "rs1", "intron_variant"),
+        bed_line("1", 100, "rs2", "intron_variant"),
+        bed_line("1", 300, "rs3", "intron_variant"),
+    ].concat());
+}
+
+#[test]
+fn sorted_mode_rejects_unsorted_input() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs2", "intron_variant"), bed_line("1", 200, "rs1", "intron_variant")]),
+    ];
+    let (success, _, stderr) = merge(&dir, &inputs, &["--sorted"]);
+
+    assert!(!success);
+    assert!(stderr.contains("is not sorted by id: rs1 on line 2 comes after rs2"), "{}", stderr);
+}
+
+#[test]
+fn more_severe_duplicate_is_kept() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "missense_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "missense_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+    ];
+    let expected = [
+        bed_line("1", 100, "rs1", "missense_variant"),
+        bed_line("1", 200, "rs2", "missense_variant"),
+    ].concat();
+
+    let (success, bed, _) = merge(&dir, &inputs, &["--severity", RANK_FILE]);
+    assert!(success);
+    assert_eq!(bed, expected);
+
+    let (success, bed, _) = merge(&dir, &inputs, &["--severity", RANK_FILE, "--sorted"]);
+    assert!(success);
+    assert_eq!(bed, expected);
+}
+
+#[test]
+fn severity_can_be_read_from_tsv() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "missense_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "missense_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+    ];
+    let tsv = dir.path().join("severity.tsv");
+    fs::write(&tsv, "# term\trank\nmissense_variant\t13\nintron_variant\t28\n").unwrap();
+
+    let (success, json_bed, _) = merge(&dir, &inputs, &["--severity", RANK_FILE]);
+    assert!(success);
+    let (success, bed, _) = merge(&dir, &inputs, &["--severity", tsv.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(bed, json_bed);
+
+    fs::write(&tsv, "missense_variant\t13\nintron_variant 28\n").unwrap();
+    let (success, _, stderr) = merge(&dir, &inputs, &["--severity", tsv.to_str().unwrap()]);
+    assert!(!success);
+    assert!(stderr.contains("line 2"), "{}", stderr);
+}
+
+#[test]
+fn gzipped_severity_file_is_read() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "missense_variant")]),
+    ];
+    // intron_variant ranked above missense_variant, the other way round from the built-in ranks
+    let severity = write_bed(&dir, "severity.json.gz", &[r#"{"intron_variant": "1", "missense_variant": "2"}"#.to_string()]);
+
+    let (success, bed, stderr) = merge(&dir, &inputs, &["--severity", &severity]);
+    assert!(success, "{}", stderr);
+    assert_eq!(bed, bed_line("1", 100, "rs1", "intron_variant"));
+}
+
+#[test]
+fn truncated_line_is_reported() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), "\n".to_string()]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 200, "rs2", "intron_variant"), "1 300 301 rs3\n".to_string()]),
+    ];
+
+    for extra in [&[][..], &["--sorted"][..]] {
+        let (success, _, stderr) = merge(&dir, &inputs, extra);
+        assert!(!success);
+        assert!(stderr.contains("b.bed line 2: expected at least 9 fields, found 4"), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+}
+
+#[test]
+fn extra_columns_are_written_as_read() {
+    let dir = TempDir::new().unwrap();
+    // a 10th column, and one with a space in it
+    let extra = bed_line("1", 100, "rs1", "intron_variant").replace('\n', "\t0.25\n");
+    let spaced = bed_line("1", 200, "rs2", "intron_variant").replace('\n', "\t0.5\tnot provided\n");
+    let inputs = [
+        write_bed(&dir, "a.bed", &[extra.clone(), spaced.clone()]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+
+    for extra_args in [&[][..], &["--sorted"][..]] {
+        let (success, bed, stderr) = merge(&dir, &inputs, extra_args);
+        assert!(success, "{}", stderr);
+        assert_eq!(bed, [extra.clone(), spaced.clone(), bed_line("1", 300, "rs3", "intron_variant")].concat());
+    }
+}
+
+#[test]
+fn crlf_and_trailing_whitespace_are_left_out() {
+    let dir = TempDir::new().unwrap();
+    let lines = [bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")];
+    let inputs = [
+        write_bed(&dir, "a.bed", &[lines[0].replace('\n', "\r\n"), "\r\n".to_string()]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs1", "missense_variant").replace('\n', " \t\r\n"), lines[1].replace('\n', "  \r\n")]),
+    ];
+    let tsv = dir.path().join("severity.tsv");
+    fs::write(&tsv, "missense_variant\t13\t\r\nintron_variant\t28\r\n").unwrap();
+
+    for extra in [&[][..], &["--sorted"][..], &["--severity", tsv.to_str().unwrap()][..]] {
+        let (success, bed, stderr) = merge(&dir, &inputs, extra);
+        assert!(success, "{}", stderr);
+        assert_eq!(bed, [bed_line("1", 100, "rs1", "missense_variant"), lines[1].clone()].concat());
+    }
+}
+
+#[test]
+fn gzipped_and_plain_inputs_are_merged() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed.gz", &[bed_line("1", 210, "rs2", "intron_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+    let expected = [
+        bed_line("1", 100, "rs1", "intron_variant"),
+        bed_line("1", 200, "rs2", "intron_variant"),
+        bed_line("1", 300, "rs3", "intron_variant"),
+    ].concat();
+
+    for extra in [&[][..], &["--sorted"][..]] {
+        let (success, bed, _) = merge(&dir, &inputs, extra);
+        assert!(success);
+        assert_eq!(bed, expected);
+    }
+}
+
+#[test]
+fn gzipped_output_matches_plain() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 200, "rs2", "missense_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+    let (_, plain, _) = merge(&dir, &inputs, &[]);
+
+    for (name, extra) in [("by_extension.bed.gz", &[][..]), ("by_flag.bed", &["--gzip"][..])] {
+        let output = dir.path().join(name);
+        let status = merge_bed().args(extra).arg(&output).args(&inputs).status().unwrap();
+        assert!(status.success());
+
+        let mut decoded = String::new();
+        MultiGzDecoder::new(fs::File::open(&output).unwrap()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, plain, "{}", name);
+    }
+}
+
+#[test]
+fn output_is_tab_delimited() {
+    let dir = TempDir::new().unwrap();
+    let lines = [bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")];
+    let spaced = lines.iter().map(|line| line.replace('\t', " ")).collect::<Vec<_>>();
+    let inputs = [
+        write_bed(&dir, "a.bed", &spaced[..1]),
+        write_bed(&dir, "b.bed", &lines[1..]),
+    ];
+
+    let (success, bed, _) = merge(&dir, &inputs, &[]);
+    assert!(success);
+    for line in bed.lines() {
+        assert_eq!(line.split('\t').count(), 9, "{}", line);
+    }
+
+    let (success, bed, _) = merge(&dir, &inputs, &["--delimiter", " "]);
+    assert!(success);
+    assert_eq!(bed, spaced.concat());
+}
+
+#[test]
+fn binary_inputs_merge_as_their_bed_lines() {
+    let dir = TempDir::new().unwrap();
+    let a = [bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")];
+    let b = [bed_line("1", 100, "rs1", "missense_variant"), bed_line("2", 300, "rs3", "stop_gained")];
+    let bed = [write_bed(&dir, "a.bed", &a), write_bed(&dir, "b.bed", &b)];
+    let bin = [write_bin(&dir, "a.bin", &a), write_bin(&dir, "b.bin", &b)];
+
+    for extra in [&[][..], &["--sorted"][..], &["--keep-all"][..], &["--severity", RANK_FILE][..]] {
+        let (success, expected, _) = merge(&dir, &bed, extra);
+        assert!(success);
+        let (success, merged, stderr) = merge(&dir, &bin, &[extra, &["--format", "bin"]].concat());
+        assert!(success, "{}", stderr);
+        assert_eq!(merged, expected);
+    }
+
+    let (success, _, stderr) = merge(&dir, &bed, &["--format", "bin"]);
+    assert!(!success);
+    assert!(stderr.contains("not binary BED records"), "{}", stderr);
+
+    // a corrupt length is an error, not an allocation of its size
+    let corrupt = dir.path().join("corrupt.bin");
+    fs::write(&corrupt, [&binary::MAGIC[..], &[0xff; 9], &[0x01]].concat()).unwrap();
+    let (success, _, stderr) = merge(&dir, &[corrupt.to_str().unwrap().to_string()], &["--format", "bin"]);
+    assert!(!success);
+    assert!(stderr.contains("corrupt.bin: binary BED record longer than the largest written"), "{}", stderr);
+}
+
+#[test]
+fn sorted_output_across_unsorted_inputs() {
+    let dir = TempDir::new().unwrap();
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("2", 50, "rs1", "intron_variant"), bed_line("1", 300, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "rs3", "intron_variant"), bed_line("10", 20, "rs4", "intron_variant"), bed_line("2", 60, "rs1", "intron_variant")]),
+    ];
+    let (_, unsorted, _) = merge(&dir, &inputs, &[]);
+    assert_eq!(unsorted.lines().next(), Some(bed_line("2", 50, "rs1", "intron_variant").trim_end()));
+
+    let expected = [
+        bed_line("1", 100, "rs3", "intron_variant"),
+        bed_line("1", 300, "rs2", "intron_variant"),
+        bed_line("10", 20, "rs4", "intron_variant"),
+        bed_line("2", 50, "rs1", "intron_variant"),
+    ].concat();
+    for sort_buffer in ["1000", "1"] {
+        let (success, bed, stderr) = merge(&dir, &inputs, &["--sort", "--sort-buffer", sort_buffer]);
+        assert!(success, "{}", stderr);
+        assert_eq!(bed, expected);
+    }
+
+    let fai = dir.path().join("genome.fa.fai");
+    fs::write(&fai, "2\t1000\t3\t60\t61\n1\t1000\t1023\t60\t61\n").unwrap();
+    let (success, bed, _) = merge(&dir, &inputs, &["--sort", "--genome", fai.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(bed, [
+        bed_line("2", 50, "rs1", "intron_variant"),
+        bed_line("1", 100, "rs3", "intron_variant"),
+        bed_line("1", 300, "rs2", "intron_variant"),
+        bed_line("10", 20, "rs4", "intron_variant"),
+    ].concat());
+    let (success, _, _) = merge(&dir, &inputs, &["--genome", fai.to_str().unwrap()]);
+    assert!(!success);
+}
+
+#[test]
+fn lines_are_deduplicated_on_the_key_fields() {
+    let dir = TempDir::new().unwrap();
+    // the same variant named differently in the two inputs, sorted by their coordinates
+    let inputs = [
+        write_bed(&dir, "a.bed", &[bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant")]),
+        write_bed(&dir, "b.bed", &[bed_line("1", 100, "1_101_A_T", "missense_variant"), bed_line("1", 300, "rs3", "intron_variant")]),
+    ];
+    let (success, bed, _) = merge(&dir, &inputs, &[]);
+    assert!(success);
+    assert_eq!(bed.lines().count(), 4);
+
+    let expected = [
+        bed_line("1", 100, "1_101_A_T", "missense_variant"),
+        bed_line("1", 200, "rs2", "intron_variant"),
+        bed_line("1", 300, "rs3", "intron_variant"),
+    ].concat();
+    for extra in [&[][..], &["--sorted"][..]] {
+        let (success, bed, stderr) = merge(&dir, &inputs, &[extra, &["--key-fields", "0,1,2", "--severity", RANK_FILE]].concat());
+        assert!(success, "{}", stderr);
+        assert_eq!(bed, expected);
+    }
+
+    let (success, _, _) = merge(&dir, &inputs, &["--key-fields", "3,9"]);
+    assert!(!success);
+}
+
+#[test]
+fn consequences_are_ranked_by_default_and_unranked_ones_by_group() {
+    let dir = TempDir::new().unwrap();
+    let group = |line: String, group: &str| line.replace("\t3\t", &format!("\t{}\t", group));
+    let inputs = [
+        write_bed(&dir, "a.bed", &[
+            group(bed_line("1", 100, "rs1", "missense_variant"), "1"),
+            group(bed_line("1", 200, "rs2", "SO:0001627"), "3"),
+            group(bed_line("1", 300, "rs3", "SO:0001583"), "0"),
+        ]),
+        write_bed(&dir, "b.bed", &[
+            group(bed_line("1", 100, "rs1", "stop_gained"), "1"),
+            group(bed_line("1", 200, "rs2", "SO:0001583"), "1"),
+            group(bed_line("1", 300, "rs3", "SO:0001627"), "3"),
+        ]),
+    ];
+    let expected = [
+        group(bed_line("1", 100, "rs1", "stop_gained"), "1"),
+        group(bed_line("1", 200, "rs2", "SO:0001583"), "1"),
+        group(bed_line("1", 300, "rs3", "SO:0001627"), "3"),
+    ].concat();
+
+    for extra in [&[][..], &["--sorted"][..]] {
+        let (success, bed, _) = merge(&dir, &inputs, extra);
+        assert!(success);
+        assert_eq!(bed, expected);
+    }
+}
+
+#[test]
+fn mmap_reads_the_inputs_as_the_buffer_does() {
+    let dir = TempDir::new().unwrap();
+    let a = [bed_line("1", 100, "rs1", "intron_variant"), bed_line("1", 200, "rs2", "intron_variant").replace('\n', "\r\n")];
+    let b = [bed_line("1", 100, "rs1", "missense_variant"), bed_line("2", 300, "rs3", "stop_gained")];
+    let bed = [write_bed(&dir, "a.bed", &a), write_bed(&dir, "b.bed.gz", &b), write_bed(&dir, "empty.bed", &[])];
+    let bin = [write_bin(&dir, "a.bin", &a), write_bin(&dir, "b.bin", &b)];
+
+    for (inputs, format) in [(&bed[..], "bed"), (&bin[..], "bin")] {
+        for extra in [&[][..], &["--sorted"][..], &["--keep-all"][..], &["--sort"][..]] {
+            let extra = [extra, &["--format", format]].concat();
+            let (success, expected, _) = merge(&dir, inputs, &extra);
+            assert!(success);
+            let (success, mapped, stderr) = merge(&dir, inputs, &[&extra[..], &["--mmap"]].concat());
+            if cfg!(feature = "mmap") {
+                assert!(success, "{}", stderr);
+                assert_eq!(mapped, expected);
+            } else {
+                assert!(!success);
+                assert!(stderr.contains("--mmap needs merge_bed built with the mmap feature"), "{}", stderr);
+            }
+        }
+    }
+
+    // a map covers the length a file has when it is opened, so anything but a regular file is refused
+    if cfg!(feature = "mmap") {
+        let (success, _, stderr) = merge(&dir, &["/dev/null".to_string()], &["--mmap"]);
+        assert!(!success);
+        assert!(stderr.contains("--mmap only reads regular files"), "{}", stderr);
+    }
+}
+
+// the address space of merge_bed is capped below the size of its input, which it could not hold in memory
+#[cfg(target_os = "linux")]
+#[test]
+fn unique_ids_are_merged_without_holding_their_lines() {
+    let dir = TempDir::new().unwrap();
+    let padding = "A".repeat(1000);
+    let lines = (0..48_000).map(|i| bed_line("1", i, &format!("rs{}", i), "intron_variant").replace("\tA\t", &format!("\t{}\t", padding)))
+        .collect::<Vec<_>>();
+    let input = write_bed(&dir, "large.bed", &lines);
+    let output = dir.path().join("merged.bed");
+
+    let result = Command::new("sh")
+        .arg("-c").arg(r#"ulimit -v 32768 && exec "$0" "$@""#)
+        .arg(env!("CARGO_BIN_EXE_merge_bed"))
+        .arg(&output).arg(&input)
+        .output().unwrap();
+
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(fs::read_to_string(&output).unwrap() == lines.concat());
+}
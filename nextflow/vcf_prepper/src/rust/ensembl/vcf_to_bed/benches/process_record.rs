@@ -0,0 +1,107 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Throughput of the per-record conversion, reported in records per second
+//!
+//! Run with `cargo bench`; the input is generated, cycling through an SNV, an indel, a multiallelic
+//! record and a structural variant, each with CSQ entries for a few transcripts.
+
+use std::{collections::HashMap, hint::black_box, io};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use vcf::{VCFReader, VCFRecord};
+use vcf_to_bed::{Converter, Line, Merger, Options, Skipped, SEVERITY, VARIANTGROUP, process_record};
+
+const RECORDS: usize = 10_000;
+
+// a CSQ entry in the default VEP layout, with VARIANT_CLASS at index 21
+fn csq(allele: &str, consequence: &str, class: &str) -> String {
+    format!("{}|{}{}{}", allele, consequence, "|".repeat(20), class)
+}
+
+// same consequences on three transcripts
+fn entries(allele: &str, consequences: &[&str], class: &str) -> Vec<String> {
+    (0..3).flat_map(|_| consequences.iter().map(|consequence| csq(allele, consequence, class))).collect()
+}
+
+fn synthetic_vcf(records: usize) -> String {
+    let mut vcf = String::from("##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+    for i in 0..records {
+        let position = 1000 + i * 10;
+        let (reference, alts, csqs, info) = match i % 4 {
+            0 => ("A", "G", entries("G", &["missense_variant", "intron_variant"], "SNV"), String::new()),
+            1 => ("AC", "A", entries("-", &["frameshift_variant&splice_region_variant"], "deletion"), String::new()),
+            2 => ("A", "G,T", [entries("G", &["synonymous_variant"], "SNV"), entries("T", &["stop_gained"], "SNV")].concat(), String::new()),
+            _ => ("A", "<DEL>", entries("deletion", &["transcript_ablation", "feature_truncation"], "deletion"), "SVTYPE=DEL;END=5000;".to_string())
+        };
+        vcf += &format!("1\t{}\trs{}\t{}\t{}\t.\t.\t{}CSQ={}\n", position, i, reference, alts, info, csqs.join(","));
+    }
+    vcf
+}
+
+fn converter() -> Converter {
+    Converter {
+        severity: SEVERITY.iter().map(|(term, rank)| (term.to_string(), *rank)).collect(),
+        variant_groups: VARIANTGROUP.iter().map(|(term, group)| (term.to_string(), *group)).collect::<HashMap<_, _>>(),
+        options: Options::default()
+    }
+}
+
+fn parse_records(vcf: &str) -> Vec<VCFRecord> {
+    let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+    let mut record = reader.empty_record();
+    let mut records = Vec::new();
+    while reader.next_record(&mut record).unwrap() {
+        records.push(record.clone());
+    }
+    records
+}
+
+fn process_records(c: &mut Criterion) {
+    let converter = converter();
+    let records = parse_records(&synthetic_vcf(RECORDS));
+    let lines = records.iter()
+        .flat_map(|record| process_record(record, &converter.severity, &converter.variant_groups, &converter.options, &mut Skipped::default()).unwrap())
+        .collect::<Vec<Line>>();
+
+    let mut group = c.benchmark_group("records");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+
+    group.bench_function("process_record", |b| b.iter(|| {
+        let mut skipped = Skipped::default();
+        for record in &records {
+            black_box(process_record(record, &converter.severity, &converter.variant_groups, &converter.options, &mut skipped).unwrap());
+        }
+    }));
+
+    group.bench_function("merge", |b| b.iter_batched(|| lines.clone(), |lines| {
+        let mut merger = Merger::default();
+        let mut out = io::sink();
+        for line in lines {
+            merger.add(line, &converter.options, &mut out).unwrap();
+        }
+        merger.finish(&converter.options, &mut out).unwrap();
+    }, criterion::BatchSize::LargeInput));
+
+    let vcf = synthetic_vcf(RECORDS);
+    group.bench_function("convert", |b| b.iter(|| {
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        converter.convert(&mut reader, "synthetic.vcf", &mut io::sink()).unwrap()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, process_records);
+criterion_main!(benches);
@@ -0,0 +1,322 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Minimal decoder of BCF (binary VCF) into VCF text
+//!
+//! A BCF starts with the magic `BCF\x02\x02` and the VCF header as text, followed by the records in a binary
+//! layout. The records are decoded back into VCF lines so that the rest of the conversion reads BCF and VCF alike.
+//! Only the site columns are decoded, CHROM to INFO: the genotypes are skipped and the sample names are left out of
+//! the #CHROM line, as the conversion only reads INFO.
+//!
+//! The values are typed: a byte holds the type in its lower 4 bits and the number of values in its upper 4 bits,
+//! 15 meaning that the number follows as a typed integer. Contigs are numbered by the order of the ##contig lines
+//! and FILTER, INFO and FORMAT keys share one dictionary starting with PASS, unless the header gives an IDX.
+
+use std::{collections::HashMap, fs::File, io::{self, BufRead, BufReader, Read}};
+use flate2::read::MultiGzDecoder;
+
+/// First bytes of a BCF once decompressed
+pub const MAGIC: &[u8] = b"BCF\x02";
+
+/// Largest length of the header text or of the site data of a record, so that a corrupt length is not allocated
+pub const MAX_LENGTH: u32 = 1 << 28;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid BCF: {}", message))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+// a length to allocate, at most MAX_LENGTH
+fn read_length(reader: &mut impl Read, what: &str) -> io::Result<usize> {
+    let length = read_u32(reader)?;
+    if length > MAX_LENGTH {
+        return Err(invalid(format!("{} of {} bytes is longer than {}", what, length, MAX_LENGTH)));
+    }
+    Ok(length as usize)
+}
+
+/// Whether the file (bgzipped or not) is a BCF
+pub fn is_bcf(path: &str) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let mut magic = Vec::new();
+        MultiGzDecoder::new(reader).take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+        return Ok(magic == MAGIC);
+    }
+    Ok(reader.fill_buf()?.starts_with(MAGIC))
+}
+
+/// VCF text of a BCF, header and then one line for each record
+pub struct BcfText<R> {
+    inner: R,
+    contigs: HashMap<i32, String>,
+    keys: HashMap<i32, String>,
+    line: Vec<u8>,
+    consumed: usize
+}
+
+impl<R: BufRead> BcfText<R> {
+    /// Decoder of the BCF read from inner, which begins with the magic
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0; 5];
+        inner.read_exact(&mut magic)?;
+        if !magic.starts_with(MAGIC) {
+            return Err(invalid("missing magic".to_string()));
+        }
+        let mut text = vec![0; read_length(&mut inner, "header")?];
+        inner.read_exact(&mut text)?;
+        let text = String::from_utf8_lossy(&text).trim_end_matches('\0').to_string();
+
+        let (contigs, keys) = dictionaries(&text);
+        let mut line = Vec::new();
+        for header in text.lines() {
+            if header.starts_with("#CHROM") {
+                line.extend(header.split('\t').take(8).collect::<Vec<_>>().join("\t").as_bytes());
+            } else {
+                line.extend(header.as_bytes());
+            }
+            line.push(b'\n');
+        }
+        Ok(BcfText { inner, contigs, keys, line, consumed: 0 })
+    }
+
+    // decode the next record into the line, leaving it empty at the end
+    fn next_line(&mut self) -> io::Result<()> {
+        self.line.clear();
+        self.consumed = 0;
+        if self.inner.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+
+        let shared = read_length(&mut self.inner, "record")?;
+        let individual = read_u32(&mut self.inner)? as u64;
+        let mut record = vec![0; shared];
+        self.inner.read_exact(&mut record)?;
+        io::copy(&mut (&mut self.inner).take(individual), &mut io::sink())?;
+
+        let text = self.record(&mut Values { data: &record, at: 0 })?;
+        self.line.extend(text.as_bytes());
+        self.line.push(b'\n');
+        Ok(())
+    }
+
+    fn record(&self, values: &mut Values) -> io::Result<String> {
+        let chromosome = values.i32()?;
+        let chromosome = self.contigs.get(&chromosome).ok_or_else(|| invalid(format!("contig {} is not in the header", chromosome)))?;
+        let position = values.i32()? as i64 + 1;
+        values.i32()?;
+        let quality = values.u32()?;
+        let quality = if quality == FLOAT_MISSING { ".".to_string() } else { f32::from_bits(quality).to_string() };
+        let counts = values.u32()?;
+        let (n_info, n_allele) = (counts & 0xffff, counts >> 16);
+        values.u32()?;
+
+        let id = values.typed()?.join(",");
+        let mut alleles = Vec::new();
+        for _ in 0..n_allele {
+            alleles.push(values.typed()?.join(","));
+        }
+        let filters = values.typed()?.iter().map(|key| self.key(key)).collect::<io::Result<Vec<_>>>()?;
+        let mut info = Vec::new();
+        for _ in 0..n_info {
+            let key = self.key(values.typed()?.first().map_or("", String::as_str))?;
+            let value = values.typed()?;
+            info.push(if value.is_empty() { key.to_string() } else { format!("{}={}", key, value.join(",")) });
+        }
+
+        let or_dot = |text: String| if text.is_empty() { ".".to_string() } else { text };
+        Ok([
+            chromosome.clone(),
+            position.to_string(),
+            or_dot(id),
+            or_dot(alleles.first().cloned().unwrap_or_default()),
+            or_dot(alleles.get(1..).unwrap_or_default().join(",")),
+            quality,
+            or_dot(filters.join(";")),
+            or_dot(info.join(";"))
+        ].join("\t"))
+    }
+
+    fn key(&self, index: &str) -> io::Result<&str> {
+        index.parse::<i32>().ok()
+            .and_then(|index| self.keys.get(&index))
+            .map(String::as_str)
+            .ok_or_else(|| invalid(format!("key {} is not in the header", index)))
+    }
+}
+
+impl<R: BufRead> Read for BcfText<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for BcfText<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.line.len() {
+            self.next_line()?;
+        }
+        Ok(&self.line[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+    }
+}
+
+// contig numbers and FILTER/INFO/FORMAT key numbers of the header
+fn dictionaries(text: &str) -> (HashMap<i32, String>, HashMap<i32, String>) {
+    let mut contigs = HashMap::new();
+    let mut keys = HashMap::from([(0, "PASS".to_string())]);
+    let mut seen = HashMap::from([("PASS".to_string(), 0)]);
+    for line in text.lines() {
+        let Some((kind, fields)) = line.strip_prefix("##").and_then(|line| line.split_once("=<")) else {
+            continue;
+        };
+        let field = |name: &str| fields.trim_end_matches('>').split(',')
+            .filter_map(|field| field.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string());
+        let (Some(id), idx) = (field("ID"), field("IDX").and_then(|idx| idx.parse::<i32>().ok())) else {
+            continue;
+        };
+        match kind {
+            "contig" => {
+                let index = idx.unwrap_or(contigs.len() as i32);
+                contigs.insert(index, id);
+            },
+            "FILTER" | "INFO" | "FORMAT" if !seen.contains_key(&id) => {
+                let index = idx.unwrap_or(keys.len() as i32);
+                seen.insert(id.clone(), index);
+                keys.insert(index, id);
+            },
+            _ => {}
+        }
+    }
+    (contigs, keys)
+}
+
+const FLOAT_MISSING: u32 = 0x7f80_0001;
+const FLOAT_END: u32 = 0x7f80_0002;
+
+// typed values of a record, read from the start
+struct Values<'a> {
+    data: &'a [u8],
+    at: usize
+}
+
+impl Values<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let bytes = self.data.get(self.at..self.at + n).ok_or_else(|| invalid("record ends early".to_string()))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    // the values of a typed vector as VCF text, . for a missing value and none after the end of the vector
+    fn typed(&mut self) -> io::Result<Vec<String>> {
+        let descriptor = self.take(1)?[0];
+        let (kind, mut count) = (descriptor & 0xf, (descriptor >> 4) as usize);
+        if count == 15 {
+            count = self.typed()?.first()
+                .and_then(|count| count.parse::<usize>().ok())
+                .ok_or_else(|| invalid("missing count of a vector".to_string()))?;
+        }
+
+        let size = match kind {
+            0 => return Ok(Vec::new()),
+            1 | 7 => 1,
+            2 => 2,
+            3 | 5 => 4,
+            _ => return Err(invalid(format!("unknown type {}", kind)))
+        };
+        let bytes = self.take(size * count)?;
+        if kind == 7 {
+            let text = String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string();
+            return Ok(if text.is_empty() { Vec::new() } else { vec![text] });
+        }
+
+        let mut values = Vec::new();
+        for value in bytes.chunks(size) {
+            let value = match kind {
+                1 => integer(value[0] as i8 as i32, i8::MIN as i32),
+                2 => integer(i16::from_le_bytes([value[0], value[1]]) as i32, i16::MIN as i32),
+                3 => integer(i32::from_le_bytes(value.try_into().unwrap()), i32::MIN),
+                _ => match u32::from_le_bytes(value.try_into().unwrap()) {
+                    FLOAT_END => None,
+                    FLOAT_MISSING => Some(".".to_string()),
+                    bits => Some(f32::from_bits(bits).to_string())
+                }
+            };
+            match value {
+                Some(value) => values.push(value),
+                None => break
+            }
+        }
+        Ok(values)
+    }
+}
+
+// an integer as VCF text, with the smallest value of its type standing for missing and the next for the end
+fn integer(value: i32, missing: i32) -> Option<String> {
+    match value {
+        _ if value == missing => Some(".".to_string()),
+        _ if value == missing + 1 => None,
+        _ => Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_values_are_decoded() {
+        // int8 [5, missing, end], int16 [300], float [0.5], char "AB\0"
+        let data = [0x31, 5, 0x80, 0x81, 0x12, 0x2c, 0x01, 0x15, 0, 0, 0, 0x3f, 0x37, b'A', b'B', 0];
+        let mut values = Values { data: &data, at: 0 };
+        assert_eq!(values.typed().unwrap(), ["5", "."]);
+        assert_eq!(values.typed().unwrap(), ["300"]);
+        assert_eq!(values.typed().unwrap(), ["0.5"]);
+        assert_eq!(values.typed().unwrap(), ["AB"]);
+        assert!(values.typed().is_err());
+    }
+
+    #[test]
+    fn lengths_past_the_largest_are_refused() {
+        let error = BcfText::new(&b"BCF\x02\x02\xff\xff\xff\xff"[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut bcf = BcfText::new(&b"BCF\x02\x02\x01\0\0\0\0\xff\xff\xff\xff\0\0\0\0"[..]).unwrap();
+        assert_eq!(bcf.next_line().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
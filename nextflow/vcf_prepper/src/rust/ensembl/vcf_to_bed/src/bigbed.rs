@@ -0,0 +1,380 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! BigBed writer for sorted BED lines, in place of bedToBigBed
+//!
+//! The file follows the UCSC bbi layout, version 4 without zoom levels: a 64 byte header, the number of records and
+//! the zlib compressed blocks of up to [`ITEMS_PER_BLOCK`] records of one chromosome, then the chromosome B+ tree,
+//! the R-tree index of the blocks, the autoSql of the columns and the magic once more. The blocks are written as the
+//! lines come and the header last, once the offsets are known, so only the bounds of the blocks are held in memory.
+//!
+//! A record is the chromosome id, start and end as 32-bit integers and the other columns tab separated and
+//! NUL terminated. Chromosome ids are given in the order the chromosomes come, which is the order of the lines,
+//! and the B+ tree maps the names (sorted) to the ids and sizes.
+
+use std::{collections::HashMap, fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}};
+use flate2::{write::ZlibEncoder, Compression};
+
+pub const MAGIC: u32 = 0x8789_f2eb;
+const TREE_MAGIC: u32 = 0x78ca_8c91;
+const INDEX_MAGIC: u32 = 0x2468_ace0;
+/// Records of a compressed block, as bedToBigBed
+pub const ITEMS_PER_BLOCK: usize = 512;
+// children of a node of the B+ tree and of the R-tree
+const NODE_SIZE: usize = 256;
+
+// the columns of the nine BED columns vcf_to_bed writes, others are named after their number
+const AUTO_SQL: &str = include_str!("../../../../../assets/vcf_prepper.as");
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("cannot write BigBed: {}", message))
+}
+
+// bounds of a compressed block and where it is in the file
+struct Block {
+    chromosome: u32,
+    start: u32,
+    end: u32,
+    offset: u64,
+    size: u64
+}
+
+/// BigBed written from the BED lines written to it, which need to be sorted by position within each chromosome and
+/// to have the lines of a chromosome together
+pub struct BigBedWriter {
+    out: BufWriter<File>,
+    offset: u64,
+    sizes: HashMap<String, u32>,
+    delimiter: String,
+    // in the order of their ids
+    chromosomes: Vec<(String, u32)>,
+    records: u64,
+    fields: usize,
+    block: Vec<u8>,
+    block_records: usize,
+    block_start: u32,
+    block_end: u32,
+    blocks: Vec<Block>,
+    largest_block: usize,
+    last_start: u32,
+    // bytes written after the last complete line
+    partial: Vec<u8>
+}
+
+impl BigBedWriter {
+    /// BigBed at path for chromosomes of these sizes
+    pub fn create(path: &str, sizes: HashMap<String, u32>, delimiter: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // the header and number of records, written once known
+        out.write_all(&[0; 72])?;
+        Ok(BigBedWriter {
+            out,
+            offset: 72,
+            sizes,
+            delimiter: delimiter.to_string(),
+            chromosomes: Vec::new(),
+            records: 0,
+            fields: 0,
+            block: Vec::new(),
+            block_records: 0,
+            block_start: 0,
+            block_end: 0,
+            blocks: Vec::new(),
+            largest_block: 0,
+            last_start: 0,
+            partial: Vec::new()
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        let fields = line.split(self.delimiter.as_str()).collect::<Vec<_>>();
+        if fields.len() < 3 {
+            return Err(invalid(format!("expected at least 3 columns: {}", line)));
+        }
+        let position = |field: &str| field.parse::<u32>().map_err(|_| invalid(format!("position is not a 32-bit number: {}", line)));
+        let (chromosome, start, end) = (fields[0], position(fields[1])?, position(fields[2])?);
+        let size = *self.sizes.get(chromosome).ok_or_else(|| invalid(format!("{} is not in the chromosome sizes", chromosome)))?;
+        if end > size || start > end {
+            return Err(invalid(format!("{}:{}-{} is outside {} of length {}", chromosome, start, end, chromosome, size)));
+        }
+
+        if self.chromosomes.last().is_none_or(|(last, _)| last != chromosome) {
+            if self.chromosomes.iter().any(|(seen, _)| seen == chromosome) {
+                return Err(invalid(format!("lines of {} are not together, the lines need to be sorted", chromosome)));
+            }
+            self.end_block()?;
+            self.chromosomes.push((chromosome.to_string(), size));
+        } else if start < self.last_start {
+            return Err(invalid(format!("{}:{} comes after {}, the lines need to be sorted", chromosome, start, self.last_start)));
+        }
+        if self.block_records == ITEMS_PER_BLOCK {
+            self.end_block()?;
+        }
+        self.last_start = start;
+        self.fields = self.fields.max(fields.len());
+
+        if self.block_records == 0 {
+            self.block_start = start;
+            self.block_end = end;
+        }
+        self.block_end = self.block_end.max(end);
+        self.block.extend((self.chromosomes.len() as u32 - 1).to_le_bytes());
+        self.block.extend(start.to_le_bytes());
+        self.block.extend(end.to_le_bytes());
+        self.block.extend(fields[3..].join("\t").as_bytes());
+        self.block.push(0);
+        self.block_records += 1;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.block_records == 0 {
+            return Ok(());
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        self.blocks.push(Block {
+            chromosome: self.chromosomes.len() as u32 - 1,
+            start: self.block_start,
+            end: self.block_end,
+            offset: self.offset,
+            size: compressed.len() as u64
+        });
+        self.largest_block = self.largest_block.max(self.block.len());
+        self.write(&compressed)?;
+        self.block.clear();
+        self.block_records = 0;
+        Ok(())
+    }
+
+    /// Write the last block, the indexes and the header
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.partial)).to_string();
+            self.line(&line)?;
+        }
+        self.end_block()?;
+
+        let tree_offset = self.offset;
+        let tree = self.chromosome_tree();
+        self.write(&tree)?;
+        let index_offset = self.offset;
+        let index = self.block_index(index_offset, tree_offset);
+        self.write(&index)?;
+        let auto_sql_offset = self.offset;
+        let auto_sql = self.auto_sql();
+        self.write(auto_sql.as_bytes())?;
+        self.write(&[0])?;
+        self.write(&MAGIC.to_le_bytes())?;
+
+        let mut header = Vec::new();
+        header.extend(MAGIC.to_le_bytes());
+        header.extend(4u16.to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(tree_offset.to_le_bytes());
+        header.extend(64u64.to_le_bytes());
+        header.extend(index_offset.to_le_bytes());
+        header.extend((self.fields.max(3) as u16).to_le_bytes());
+        header.extend(3u16.to_le_bytes());
+        header.extend(auto_sql_offset.to_le_bytes());
+        // no total summary or extension
+        header.extend(0u64.to_le_bytes());
+        header.extend((self.largest_block as u32).to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        header.extend(self.records.to_le_bytes());
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.flush()
+    }
+
+    // B+ tree of the chromosome names, padded with NULs to the longest, to their ids and sizes
+    fn chromosome_tree(&self) -> Vec<u8> {
+        let mut items = self.chromosomes.iter().enumerate()
+            .map(|(id, (name, size))| (name.as_bytes(), id as u32, *size))
+            .collect::<Vec<_>>();
+        items.sort();
+        let key_size = items.iter().map(|(name, _, _)| name.len()).max().unwrap_or(1);
+        let block_size = items.len().clamp(1, NODE_SIZE);
+        let key = |name: &[u8]| {
+            let mut key = name.to_vec();
+            key.resize(key_size, 0);
+            key
+        };
+
+        let mut tree = Vec::new();
+        tree.extend(TREE_MAGIC.to_le_bytes());
+        tree.extend((block_size as u32).to_le_bytes());
+        tree.extend((key_size as u32).to_le_bytes());
+        tree.extend(8u32.to_le_bytes());
+        tree.extend((items.len() as u64).to_le_bytes());
+        tree.extend(0u64.to_le_bytes());
+
+        let levels = levels(items.len(), block_size);
+        // a child offset and an id with a size take 8 bytes alike, so nodes at every level are the same size
+        let node = 4 + block_size * (key_size + 8);
+        let mut offset = self.offset + tree.len() as u64;
+        for level in (1..levels).rev() {
+            // items under each child of a node at this level
+            let per_child = block_size.pow(level as u32);
+            let nodes = items.len().div_ceil(per_child * block_size);
+            let mut child = offset + (nodes * node) as u64;
+            for index in 0..nodes {
+                let first = index * per_child * block_size;
+                let children = (items.len() - first).div_ceil(per_child).min(block_size);
+                tree.extend([0, 0]);
+                tree.extend((children as u16).to_le_bytes());
+                for item in (first..items.len()).step_by(per_child).take(children) {
+                    tree.extend(key(items[item].0));
+                    tree.extend(child.to_le_bytes());
+                    child += node as u64;
+                }
+                tree.resize(tree.len() + (block_size - children) * (key_size + 8), 0);
+            }
+            offset += (nodes * node) as u64;
+        }
+        for leaf in items.chunks(block_size).map(Some).chain(items.is_empty().then_some(None)) {
+            let leaf = leaf.unwrap_or_default();
+            tree.extend([1, 0]);
+            tree.extend((leaf.len() as u16).to_le_bytes());
+            for (name, id, size) in leaf {
+                tree.extend(key(name));
+                tree.extend(id.to_le_bytes());
+                tree.extend(size.to_le_bytes());
+            }
+            tree.resize(tree.len() + (block_size - leaf.len()) * (key_size + 8), 0);
+        }
+        tree
+    }
+
+    // R-tree of the blocks, each leaf item the bounds of a block with its offset and size, each node above the
+    // bounds of a child node with its offset
+    fn block_index(&self, start: u64, data_end: u64) -> Vec<u8> {
+        let blocks = &self.blocks;
+        let bounds = |blocks: &[Block]| (
+            blocks.first().map_or((0, 0), |block| (block.chromosome, block.start)),
+            blocks.iter().map(|block| (block.chromosome, block.end)).max().unwrap_or((0, 0))
+        );
+        let ((first_chromosome, first_start), (last_chromosome, last_end)) = bounds(blocks);
+
+        let mut index = Vec::new();
+        index.extend(INDEX_MAGIC.to_le_bytes());
+        index.extend((NODE_SIZE as u32).to_le_bytes());
+        index.extend((blocks.len() as u64).to_le_bytes());
+        for value in [first_chromosome, first_start, last_chromosome, last_end] {
+            index.extend(value.to_le_bytes());
+        }
+        index.extend(data_end.to_le_bytes());
+        index.extend((ITEMS_PER_BLOCK as u32).to_le_bytes());
+        index.extend(0u32.to_le_bytes());
+
+        let levels = levels(blocks.len(), NODE_SIZE);
+        let index_node = 4 + NODE_SIZE * 24;
+        let leaf_node = 4 + NODE_SIZE * 32;
+        let mut offset = start + index.len() as u64;
+        for level in (1..levels).rev() {
+            let per_child = NODE_SIZE.pow(level as u32);
+            let nodes = blocks.len().div_ceil(per_child * NODE_SIZE);
+            let mut child = offset + (nodes * index_node) as u64;
+            for node in 0..nodes {
+                let first = node * per_child * NODE_SIZE;
+                let children = blocks[first..].chunks(per_child).take(NODE_SIZE).collect::<Vec<_>>();
+                index.extend([0, 0]);
+                index.extend((children.len() as u16).to_le_bytes());
+                for child_blocks in &children {
+                    let ((start_chromosome, start), (end_chromosome, end)) = bounds(child_blocks);
+                    for value in [start_chromosome, start, end_chromosome, end] {
+                        index.extend(value.to_le_bytes());
+                    }
+                    index.extend(child.to_le_bytes());
+                    child += if level == 1 { leaf_node } else { index_node } as u64;
+                }
+                index.resize(index.len() + (NODE_SIZE - children.len()) * 24, 0);
+            }
+            offset += (nodes * index_node) as u64;
+        }
+        for leaf in blocks.chunks(NODE_SIZE).map(Some).chain(blocks.is_empty().then_some(None)) {
+            let leaf = leaf.unwrap_or_default();
+            index.extend([1, 0]);
+            index.extend((leaf.len() as u16).to_le_bytes());
+            for block in leaf {
+                for value in [block.chromosome, block.start, block.chromosome, block.end] {
+                    index.extend(value.to_le_bytes());
+                }
+                index.extend(block.offset.to_le_bytes());
+                index.extend(block.size.to_le_bytes());
+            }
+            index.resize(index.len() + (NODE_SIZE - leaf.len()) * 32, 0);
+        }
+        index
+    }
+
+    // the autoSql of the BED columns, with a string column for each column after the nine
+    fn auto_sql(&self) -> String {
+        let (table, end) = AUTO_SQL.trim_end().rsplit_once(')').unwrap_or((AUTO_SQL, ""));
+        let mut auto_sql = table.to_string();
+        for column in 10..=self.fields {
+            auto_sql += &format!("string\tcolumn{};\t\"Column {} of the BED line\"\n", column, column);
+        }
+        auto_sql + ")" + end + "\n"
+    }
+}
+
+// levels of a tree of items with this many children to a node, the leaves being one
+fn levels(mut items: usize, block_size: usize) -> usize {
+    let mut levels = 1;
+    while items > block_size {
+        items = items.div_ceil(block_size);
+        levels += 1;
+    }
+    levels
+}
+
+impl Write for BigBedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(newline) = self.partial.iter().position(|b| *b == b'\n') {
+            let line = self.partial.drain(..=newline).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line[..newline]).to_string();
+            self.line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_levels_hold_the_items() {
+        assert_eq!(levels(0, 256), 1);
+        assert_eq!(levels(256, 256), 1);
+        assert_eq!(levels(257, 256), 2);
+        assert_eq!(levels(256 * 256 + 1, 256), 3);
+    }
+}
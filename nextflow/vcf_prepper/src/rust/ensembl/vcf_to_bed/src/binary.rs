@@ -0,0 +1,162 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Binary records of BED lines, for merge_bed --format bin to read without parsing text
+//!
+//! The records follow the 8 bytes of [`MAGIC`]. A record is the length of the rest of it, the start and end and the
+//! number of its text columns, then each text column as its length and UTF-8 bytes: the chromosome first, then the
+//! columns of the BED line from the name on. Numbers are unsigned LEB128, 7 bits to a byte from the lowest with the
+//! top bit set on all but the last byte, so that most take a byte and a record is smaller than its BED line.
+
+use std::io::{self, BufRead, Write};
+
+/// Start of a file of binary records, the last byte being the version of the layout
+pub const MAGIC: [u8; 8] = *b"BEDBIN\0\x01";
+
+/// Largest length of a record after its length, so that a corrupt length is not allocated
+pub const MAX_RECORD: u64 = 1 << 26;
+
+/// A BED line read back from its binary record
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub chromosome: String,
+    pub start: u64,
+    pub end: u64,
+    // the columns after the end
+    pub columns: Vec<String>
+}
+
+fn push_number(out: &mut Vec<u8>, mut number: u64) {
+    while number >= 0x80 {
+        out.push(number as u8 | 0x80);
+        number >>= 7;
+    }
+    out.push(number as u8);
+}
+
+/// Write the record of a BED line in one write, so that it is not interleaved with another
+pub fn write_record(out: &mut dyn Write, chromosome: &str, start: u64, end: u64, columns: &[&str]) -> io::Result<()> {
+    let mut body = Vec::new();
+    push_number(&mut body, start);
+    push_number(&mut body, end);
+    push_number(&mut body, columns.len() as u64 + 1);
+    for column in std::iter::once(&chromosome).chain(columns) {
+        push_number(&mut body, column.len() as u64);
+        body.extend(column.as_bytes());
+    }
+    if body.len() as u64 > MAX_RECORD {
+        return Err(invalid("BED line too long for a binary record"));
+    }
+    let mut record = Vec::with_capacity(body.len() + 3);
+    push_number(&mut record, body.len() as u64);
+    record.extend(body);
+    out.write_all(&record)
+}
+
+/// Check that the input starts with [`MAGIC`]
+pub fn read_magic(input: &mut dyn BufRead) -> io::Result<()> {
+    let mut magic = [0; 8];
+    match input.read_exact(&mut magic) {
+        Ok(()) if magic == MAGIC => Ok(()),
+        Ok(()) | Err(_) => Err(invalid("not binary BED records"))
+    }
+}
+
+// the number at the start of the bytes, and the bytes after it
+fn take_number(bytes: &[u8]) -> io::Result<(u64, &[u8])> {
+    let mut number = 0;
+    for (index, byte) in bytes.iter().enumerate().take(10) {
+        number |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((number, &bytes[index + 1..]));
+        }
+    }
+    Err(invalid("truncated binary BED record"))
+}
+
+/// The next record of the input, None at its end
+pub fn read_record(input: &mut dyn BufRead) -> io::Result<Option<Record>> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut length = Vec::new();
+    while length.last().is_none_or(|byte| byte & 0x80 != 0) && length.len() < 10 {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        length.push(byte[0]);
+    }
+    let length = take_number(&length)?.0;
+    if length > MAX_RECORD {
+        return Err(invalid("binary BED record longer than the largest written"));
+    }
+    let mut record = vec![0; length as usize];
+    input.read_exact(&mut record)?;
+
+    let (start, rest) = take_number(&record)?;
+    let (end, rest) = take_number(rest)?;
+    let (count, mut rest) = take_number(rest)?;
+    let mut columns = Vec::new();
+    for _ in 0..count {
+        let (size, after) = take_number(rest)?;
+        let size = size as usize;
+        if after.len() < size {
+            return Err(invalid("truncated binary BED record"));
+        }
+        let column = std::str::from_utf8(&after[..size]).map_err(|_| invalid("binary BED record is not valid UTF-8"))?;
+        columns.push(column.to_string());
+        rest = &after[size..];
+    }
+    if columns.is_empty() {
+        return Err(invalid("binary BED record without a chromosome"));
+    }
+
+    let chromosome = columns.remove(0);
+    Ok(Some(Record { chromosome, start, end, columns }))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_read_back() {
+        let mut out = MAGIC.to_vec();
+        write_record(&mut out, "1", 99, 100, &["rs1", "SNV", "A", "G", "1", "missense_variant"]).unwrap();
+        write_record(&mut out, "chrX", 5_000_000_000, 5_000_000_002, &["rs2", "deletion", "ACT", "A", "3", ""]).unwrap();
+
+        let mut input = out.as_slice();
+        read_magic(&mut input).unwrap();
+        let first = read_record(&mut input).unwrap().unwrap();
+        assert_eq!(first.chromosome, "1");
+        assert_eq!((first.start, first.end), (99, 100));
+        assert_eq!(first.columns, ["rs1", "SNV", "A", "G", "1", "missense_variant"]);
+        let second = read_record(&mut input).unwrap().unwrap();
+        assert_eq!((second.chromosome.as_str(), second.start), ("chrX", 5_000_000_000));
+        assert_eq!(second.columns.last().map(String::as_str), Some(""));
+        assert!(read_record(&mut input).unwrap().is_none());
+
+        assert!(read_magic(&mut &b"1\t99\t100\n"[..]).is_err());
+        assert!(read_record(&mut &out[8..20]).is_err());
+        assert_eq!(take_number(&[0xe5, 0x8e, 0x26, 7]).unwrap(), (624_485, &[7][..]));
+        // a length past the largest record is refused before it is allocated
+        assert!(read_record(&mut &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01][..]).is_err());
+        assert!(write_record(&mut Vec::new(), "1", 0, 1, &[&"A".repeat(MAX_RECORD as usize)]).is_err());
+    }
+}
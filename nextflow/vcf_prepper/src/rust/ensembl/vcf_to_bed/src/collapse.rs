@@ -0,0 +1,179 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Runs of adjacent BED lines of the same consequence collapsed into one line spanning them
+//!
+//! Consecutive lines of a chromosome with the same group and severity are a run as long as each starts at or before
+//! the end of the run so far, so that their intervals overlap or touch. A run of one line is written as it is.
+//! A longer run is written from the start of its first line to its furthest end, with the variety the lines share or
+//! else sequence_alteration. The alleles of several variants do not make one allele, so reference and alts are `.`.
+//! The ids are joined, the first kept or dropped for `.` as chosen, and the extra columns are those of the first line.
+//!
+//! This differs from the merging of records during conversion, which joins the records of one variant, and needs
+//! the lines in position order.
+
+use std::io::{self, Write};
+
+/// What the id column of a collapsed line holds
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CollapseIds {
+    /// the distinct ids of the lines, comma separated
+    Join,
+    /// the id of the first line
+    First,
+    /// .
+    Drop
+}
+
+// the lines of a run so far
+struct Run {
+    first: String,
+    fields: Vec<String>,
+    start: u64,
+    end: u64,
+    ids: Vec<String>,
+    variety: Option<String>,
+    lines: usize
+}
+
+/// Writer collapsing the runs of the BED lines written through it, see the module documentation
+pub struct Collapse<W: Write> {
+    inner: W,
+    delimiter: String,
+    ids: CollapseIds,
+    run: Option<Run>,
+    // bytes written after the last complete line
+    partial: Vec<u8>
+}
+
+impl<W: Write> Collapse<W> {
+    pub fn new(inner: W, delimiter: &str, ids: CollapseIds) -> Self {
+        Collapse { inner, delimiter: delimiter.to_string(), ids, run: None, partial: Vec::new() }
+    }
+
+    /// Write the last run and return the writer the lines went to
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.partial)).to_string();
+            self.line(&line)?;
+        }
+        self.end_run()?;
+        Ok(self.inner)
+    }
+
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        let fields = line.split(self.delimiter.as_str()).map(str::to_string).collect::<Vec<_>>();
+        let span = (fields.len() >= 9).then(|| Some((fields[1].parse::<u64>().ok()?, fields[2].parse::<u64>().ok()?))).flatten();
+        let Some((start, end)) = span else {
+            // not a BED line of this tool, passed on as it is
+            self.end_run()?;
+            return writeln!(self.inner, "{}", line);
+        };
+
+        if let Some(run) = &mut self.run {
+            let same = run.fields[0] == fields[0] && run.fields[7] == fields[7] && run.fields[8] == fields[8];
+            if same && start <= run.end {
+                run.end = run.end.max(end);
+                if !run.ids.contains(&fields[3]) {
+                    run.ids.push(fields[3].clone());
+                }
+                if run.variety.as_ref() != Some(&fields[4]) {
+                    run.variety = None;
+                }
+                run.lines += 1;
+                return Ok(());
+            }
+        }
+
+        self.end_run()?;
+        self.run = Some(Run {
+            first: line.to_string(),
+            start,
+            end,
+            ids: vec![fields[3].clone()],
+            variety: Some(fields[4].clone()),
+            fields,
+            lines: 1
+        });
+        Ok(())
+    }
+
+    fn end_run(&mut self) -> io::Result<()> {
+        let Some(run) = self.run.take() else {
+            return Ok(());
+        };
+        if run.lines == 1 {
+            return writeln!(self.inner, "{}", run.first);
+        }
+
+        let mut fields = run.fields;
+        fields[1] = run.start.to_string();
+        fields[2] = run.end.to_string();
+        fields[3] = match self.ids {
+            CollapseIds::Join => run.ids.join(","),
+            CollapseIds::First => run.ids[0].clone(),
+            CollapseIds::Drop => ".".to_string()
+        };
+        fields[4] = run.variety.unwrap_or_else(|| "sequence_alteration".to_string());
+        fields[5] = ".".to_string();
+        fields[6] = ".".to_string();
+        writeln!(self.inner, "{}", fields.join(&self.delimiter))
+    }
+}
+
+impl<W: Write> Write for Collapse<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(newline) = self.partial.iter().position(|b| *b == b'\n') {
+            let line = self.partial.drain(..=newline).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line[..newline]).to_string();
+            self.line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collapsed(lines: &str, ids: CollapseIds) -> String {
+        let mut collapse = Collapse::new(Vec::new(), " ", ids);
+        collapse.write_all(lines.as_bytes()).unwrap();
+        String::from_utf8(collapse.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn runs_end_at_a_gap_or_another_consequence() {
+        let lines = concat!(
+            "1 99 100 rs1 SNV A T 3 intron_variant\n",
+            "1 100 101 rs2 SNV C G 3 intron_variant\n",
+            "1 105 106 rs3 SNV G A 3 intron_variant\n",
+            "1 106 108 rs4 deletion GTC G 3 intron_variant\n",
+            "1 108 109 rs5 SNV A C 1 missense_variant\n"
+        );
+        assert_eq!(collapsed(lines, CollapseIds::First), concat!(
+            "1 99 101 rs1 SNV . . 3 intron_variant\n",
+            "1 105 108 rs3 sequence_alteration . . 3 intron_variant\n",
+            "1 108 109 rs5 SNV A C 1 missense_variant\n"
+        ));
+        assert_eq!(collapsed(lines, CollapseIds::Drop).lines().next(), Some("1 99 101 . SNV . . 3 intron_variant"));
+    }
+}
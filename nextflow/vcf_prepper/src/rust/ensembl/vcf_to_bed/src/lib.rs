@@ -0,0 +1,2706 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+ 
+//! Conversion of VEP annotated VCF records into BED lines carrying the most severe consequence
+//!
+//! Coordinates are written as BED defines them, 0-based and half-open: a variant at VCF `POS` with a
+//! reference allele of `n` bases starts at `POS - 1` and ends at `POS - 1 + n`, so an SNV at 100 is `99 100`.
+//! Insertions are the empty interval `POS POS` after their anchor base, see [`compute_end`], unless
+//! [`InsertionSpan`] gives them the anchor base or the next one instead. An INFO `END` tag,
+//! the last affected base, is the end instead of the reference allele whenever a record has one.
+
+use std::{borrow::Cow, io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, Write}, fs::File, fmt, collections::{BTreeMap, BTreeSet, HashMap, HashSet}, ops::AddAssign, sync::{mpsc, Mutex}, thread, time::Duration};
+use vcf::{VCFError, VCFHeader, VCFReader, VCFRecord};
+use flate2::read::MultiGzDecoder;
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+
+pub mod bcf;
+pub mod binary;
+#[cfg(feature = "bigbed")]
+pub mod bigbed;
+pub mod collapse;
+pub mod progress;
+pub mod sort;
+pub mod tabix;
+use bcf::BcfText;
+use progress::Progress;
+use tabix::{ContigReader, Index};
+
+pub const VARIANTGROUP : [(&str, u8); 45] = [
+    ("frameshift_variant", 1),
+    ("inframe_deletion", 1),
+    ("inframe_insertion", 1),
+    ("missense_variant", 1),
+    ("protein_altering_variant", 1),
+    ("start_lost", 1),
+    ("stop_gained", 1),
+    ("stop_lost", 1),
+    ("splice_acceptor_variant", 2),
+    ("splice_donor_5th_base_variant", 2),
+    ("splice_donor_region_variant", 2),
+    ("splice_donor_variant", 2),
+    ("splice_polypyrimidine_tract_variant", 2),
+    ("splice_region_variant", 2),
+    ("3_prime_UTR_variant", 3),
+    ("5_prime_UTR_variant", 3),
+    ("coding_sequence_variant", 3),
+    ("incomplete_terminal_codon_variant", 3),
+    ("intron_variant", 3),
+    ("mature_miRNA_variant", 3),
+    ("NMD_transcript_variant", 3),
+    ("non_coding_transcript_exon_variant", 3),
+    ("non_coding_transcript_variant", 3),
+    ("start_retained_variant", 3),
+    ("stop_retained_variant", 3),
+    ("synonymous_variant", 3),
+    ("feature_elongation", 3),
+    ("feature_truncation", 3),
+    ("transcript_ablation", 3),
+    ("transcript_amplification", 3),
+    ("transcript_fusion", 3),
+    ("transcript_translocation", 3),
+    ("regulatory_region_variant", 4),
+    ("TF_binding_site_variant", 4),
+    ("regulatory_region_ablation", 4),
+    ("regulatory_region_amplification", 4),
+    ("regulatory_region_fusion", 4),
+    ("regulatory_region_translocation", 4),
+    ("TFBS_ablation", 4),
+    ("TFBS_amplification", 4),
+    ("TFBS_fusion", 4),
+    ("TFBS_translocation", 4),
+    ("upstream_gene_variant", 5),
+    ("downstream_gene_variant", 5),
+    ("intergenic_variant", 5)
+];
+
+// records handed to a worker at a time by convert_threaded
+const BATCH_SIZE: usize = 512;
+
+/// Variant groups shown in the browser, 0 is left for consequences without a group
+pub const GROUPS: std::ops::RangeInclusive<u8> = 1..=5;
+
+/// itemRgb colour of each variant group with --color-by-group, after the Ensembl variant colours: coding red,
+/// splice coral, transcript blue, regulatory brown and intergenic grey
+pub const GROUP_COLORS: [(u8, &str); 5] = [
+    (1, "255,0,0"),
+    (2, "255,127,80"),
+    (3, "2,89,156"),
+    (4, "165,42,42"),
+    (5, "99,99,99")
+];
+
+/// Ensembl's ranking of consequences from most to least severe, used where a severity file does not rank a term
+pub const SEVERITY : [(&str, u8); 41] = [
+    ("transcript_ablation", 1),
+    ("splice_acceptor_variant", 2),
+    ("splice_donor_variant", 3),
+    ("stop_gained", 4),
+    ("frameshift_variant", 5),
+    ("stop_lost", 6),
+    ("start_lost", 7),
+    ("transcript_amplification", 8),
+    ("feature_elongation", 9),
+    ("feature_truncation", 10),
+    ("inframe_insertion", 11),
+    ("inframe_deletion", 12),
+    ("missense_variant", 13),
+    ("protein_altering_variant", 14),
+    ("splice_donor_5th_base_variant", 15),
+    ("splice_region_variant", 16),
+    ("splice_donor_region_variant", 17),
+    ("splice_polypyrimidine_tract_variant", 18),
+    ("incomplete_terminal_codon_variant", 19),
+    ("start_retained_variant", 20),
+    ("stop_retained_variant", 21),
+    ("synonymous_variant", 22),
+    ("coding_sequence_variant", 23),
+    ("mature_miRNA_variant", 24),
+    ("5_prime_UTR_variant", 25),
+    ("3_prime_UTR_variant", 26),
+    ("non_coding_transcript_exon_variant", 27),
+    ("intron_variant", 28),
+    ("NMD_transcript_variant", 29),
+    ("non_coding_transcript_variant", 30),
+    ("coding_transcript_variant", 31),
+    ("upstream_gene_variant", 32),
+    ("downstream_gene_variant", 33),
+    ("TFBS_ablation", 34),
+    ("TFBS_amplification", 35),
+    ("TF_binding_site_variant", 36),
+    ("regulatory_region_ablation", 37),
+    ("regulatory_region_amplification", 38),
+    ("regulatory_region_variant", 39),
+    ("intergenic_variant", 40),
+    ("sequence_variant", 41),
+];
+
+/// Severity rank of a consequence term that is not ranked, less severe than any ranked term; lower ranks are more
+/// severe, and 255 stands for no consequence at all
+pub const UNRANKED: u8 = 254;
+
+/// The consequence terms of [`SEVERITY`] and [`VARIANTGROUP`] by their lowercase; SO terms are lowercase except
+/// for a few such as 3_prime_UTR_variant, which keep their case when the terms of another case are matched to them
+pub fn known_terms() -> HashMap<String, String> {
+    SEVERITY.iter().map(|(term, _)| term).chain(VARIANTGROUP.iter().map(|(term, _)| term))
+        .map(|term| (term.to_lowercase(), term.to_string()))
+        .collect()
+}
+
+/// Sequence Ontology accessions of the ranked consequence terms, written by `--severity-as accession`
+pub const SO_ACCESSION : [(&str, &str); 41] = [
+    ("transcript_ablation", "SO:0001893"),
+    ("splice_acceptor_variant", "SO:0001574"),
+    ("splice_donor_variant", "SO:0001575"),
+    ("stop_gained", "SO:0001587"),
+    ("frameshift_variant", "SO:0001589"),
+    ("stop_lost", "SO:0001578"),
+    ("start_lost", "SO:0002012"),
+    ("transcript_amplification", "SO:0001889"),
+    ("feature_elongation", "SO:0001907"),
+    ("feature_truncation", "SO:0001906"),
+    ("inframe_insertion", "SO:0001821"),
+    ("inframe_deletion", "SO:0001822"),
+    ("missense_variant", "SO:0001583"),
+    ("protein_altering_variant", "SO:0001818"),
+    ("splice_donor_5th_base_variant", "SO:0001787"),
+    ("splice_region_variant", "SO:0001630"),
+    ("splice_donor_region_variant", "SO:0002170"),
+    ("splice_polypyrimidine_tract_variant", "SO:0002169"),
+    ("incomplete_terminal_codon_variant", "SO:0001626"),
+    ("start_retained_variant", "SO:0002019"),
+    ("stop_retained_variant", "SO:0001567"),
+    ("synonymous_variant", "SO:0001819"),
+    ("coding_sequence_variant", "SO:0001580"),
+    ("mature_miRNA_variant", "SO:0001620"),
+    ("5_prime_UTR_variant", "SO:0001623"),
+    ("3_prime_UTR_variant", "SO:0001624"),
+    ("non_coding_transcript_exon_variant", "SO:0001792"),
+    ("intron_variant", "SO:0001627"),
+    ("NMD_transcript_variant", "SO:0001621"),
+    ("non_coding_transcript_variant", "SO:0001619"),
+    ("coding_transcript_variant", "SO:0001968"),
+    ("upstream_gene_variant", "SO:0001631"),
+    ("downstream_gene_variant", "SO:0001632"),
+    ("TFBS_ablation", "SO:0001895"),
+    ("TFBS_amplification", "SO:0001892"),
+    ("TF_binding_site_variant", "SO:0001782"),
+    ("regulatory_region_ablation", "SO:0001894"),
+    ("regulatory_region_amplification", "SO:0001891"),
+    ("regulatory_region_variant", "SO:0001566"),
+    ("intergenic_variant", "SO:0001628"),
+    ("sequence_variant", "SO:0001060"),
+];
+
+/// VEP impact of the ranked consequence terms, written by `--extra-columns impact`
+///
+/// The variant groups do not line up with the impacts - group 1 holds stop_gained (HIGH) and missense_variant
+/// (MODERATE), group 3 transcript_ablation (HIGH), synonymous_variant (LOW) and intron_variant (MODIFIER) - so the
+/// impact is that of the most severe term, as VEP gives it in IMPACT.
+pub const IMPACT : [(&str, &str); 41] = [
+    ("transcript_ablation", "HIGH"),
+    ("splice_acceptor_variant", "HIGH"),
+    ("splice_donor_variant", "HIGH"),
+    ("stop_gained", "HIGH"),
+    ("frameshift_variant", "HIGH"),
+    ("stop_lost", "HIGH"),
+    ("start_lost", "HIGH"),
+    ("transcript_amplification", "HIGH"),
+    ("feature_elongation", "HIGH"),
+    ("feature_truncation", "HIGH"),
+    ("inframe_insertion", "MODERATE"),
+    ("inframe_deletion", "MODERATE"),
+    ("missense_variant", "MODERATE"),
+    ("protein_altering_variant", "MODERATE"),
+    ("splice_donor_5th_base_variant", "LOW"),
+    ("splice_region_variant", "LOW"),
+    ("splice_donor_region_variant", "LOW"),
+    ("splice_polypyrimidine_tract_variant", "LOW"),
+    ("incomplete_terminal_codon_variant", "LOW"),
+    ("start_retained_variant", "LOW"),
+    ("stop_retained_variant", "LOW"),
+    ("synonymous_variant", "LOW"),
+    ("coding_sequence_variant", "MODIFIER"),
+    ("mature_miRNA_variant", "MODIFIER"),
+    ("5_prime_UTR_variant", "MODIFIER"),
+    ("3_prime_UTR_variant", "MODIFIER"),
+    ("non_coding_transcript_exon_variant", "MODIFIER"),
+    ("intron_variant", "MODIFIER"),
+    ("NMD_transcript_variant", "MODIFIER"),
+    ("non_coding_transcript_variant", "MODIFIER"),
+    ("coding_transcript_variant", "MODIFIER"),
+    ("upstream_gene_variant", "MODIFIER"),
+    ("downstream_gene_variant", "MODIFIER"),
+    ("TFBS_ablation", "MODIFIER"),
+    ("TFBS_amplification", "MODIFIER"),
+    ("TF_binding_site_variant", "MODIFIER"),
+    ("regulatory_region_ablation", "MODIFIER"),
+    ("regulatory_region_amplification", "MODIFIER"),
+    ("regulatory_region_variant", "MODIFIER"),
+    ("intergenic_variant", "MODIFIER"),
+    ("sequence_variant", "MODIFIER"),
+];
+
+/// ClinVar clinical significance terms as VEP writes them in CLIN_SIG, from most to least pathogenic
+pub const CLIN_SIG: [&str; 13] = [
+    "pathogenic",
+    "likely_pathogenic",
+    "risk_factor",
+    "affects",
+    "drug_response",
+    "association",
+    "conflicting_interpretations_of_pathogenicity",
+    "uncertain_significance",
+    "protective",
+    "likely_benign",
+    "benign",
+    "other",
+    "not_provided",
+];
+
+// position of the term in CLIN_SIG, terms it does not list come last
+fn clin_sig_rank(term: &str) -> usize {
+    CLIN_SIG.iter().position(|known| *known == term).unwrap_or(CLIN_SIG.len())
+}
+
+// the more pathogenic of two clinical significance terms, the first on a tie
+fn more_pathogenic(a: Option<String>, b: Option<String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if clin_sig_rank(&b) < clin_sig_rank(&a) { b } else { a }),
+        (a, b) => a.or(b)
+    }
+}
+
+/// Errors that stop the conversion
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("cannot open {path}: {source}")]
+    Open { path: String, source: io::Error },
+    #[error("cannot parse severity file {path}: {source}")]
+    Severity { path: String, source: serde_json::Error },
+    #[error("cannot parse severity file {path} line {line}: {message}")]
+    SeverityLine { path: String, line: usize, message: String },
+    #[error("cannot parse accession file {path}: {source}")]
+    Accessions { path: String, source: serde_json::Error },
+    #[error("cannot parse impact file {path}: {source}")]
+    Impacts { path: String, source: serde_json::Error },
+    #[error("severity rank of {term} in {path} is not a number: {value}")]
+    SeverityRank { path: String, term: String, value: String },
+    #[error("cannot read variant groups {path}: {message}")]
+    Groups { path: String, message: String },
+    #[error("cannot read group colours {path}: {message}")]
+    Colors { path: String, message: String },
+    #[error("severity file {path} does not rank {terms}")]
+    Unranked { path: String, terms: String },
+    #[error("skipped {skipped} of {records} records, more than --max-skip-fraction {max}")]
+    TooManySkipped { skipped: u64, records: u64, max: f64 },
+    #[error("VCF header of {path} does not define the {key} INFO field")]
+    NoCsqHeader { path: String, key: String },
+    #[error("VCF header of {path} does not describe the Format of {key}")]
+    NoCsqFormat { path: String, key: String },
+    #[error("{key} Format of {path} differs from that of {first}, the inputs cannot be converted together")]
+    FormatDiffers { path: String, first: String, key: String },
+    #[error("{key} entry of variant at {chromosome}:{position} has {fields} subfields, the header Format has {expected}")]
+    CsqWidth { key: String, chromosome: String, position: u64, fields: usize, expected: usize },
+    #[error("{path} is not sorted: variant at {chromosome}:{position} comes after {previous}")]
+    Unsorted { path: String, chromosome: String, position: u64, previous: String },
+    #[error("cannot read VCF {path}: {source}")]
+    Vcf { path: String, source: VCFError },
+    #[error("{field} of variant at {chromosome}:{position} is not valid UTF-8")]
+    Utf8 { field: &'static str, chromosome: String, position: u64 },
+    #[error("cannot read tabix index of {path}: {source}")]
+    Index { path: String, source: io::Error },
+    #[error("{path} needs a tabix (.tbi) or CSI (.csi) index to be converted in parallel")]
+    NoIndex { path: String },
+    #[error("{path} is BCF, which cannot be converted in parallel")]
+    BcfParallel { path: String },
+    #[error("cannot start thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("cannot write output: {0}")]
+    Write(#[from] io::Error)
+}
+
+// number of records skipped for each reason, reported at the end of the run, with the consequence terms met that
+// the severity ranks do not rank
+#[derive(Default)]
+pub struct Skipped {
+    pub long_ref: u64,
+    pub no_csq: u64,
+    pub bad_span: u64,
+    pub no_id: u64,
+    pub many_alts: u64,
+    pub bad_alt: u64,
+    pub unranked: BTreeSet<String>,
+    // records read, whether skipped or not
+    pub records: u64
+}
+
+impl Skipped {
+    pub fn total(&self) -> u64 {
+        self.long_ref + self.no_csq + self.bad_span + self.no_id + self.many_alts + self.bad_alt
+    }
+
+    /// Fraction of the records read that were skipped, 0 if none were read
+    pub fn fraction(&self) -> f64 {
+        if self.records == 0 { 0.0 } else { self.total() as f64 / self.records as f64 }
+    }
+}
+
+impl AddAssign for Skipped {
+    fn add_assign(&mut self, mut other: Skipped) {
+        self.long_ref += other.long_ref;
+        self.no_csq += other.no_csq;
+        self.bad_span += other.bad_span;
+        self.no_id += other.no_id;
+        self.many_alts += other.many_alts;
+        self.bad_alt += other.bad_alt;
+        self.unranked.append(&mut other.unranked);
+        self.records += other.records;
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "skipped {} records: {} long-ref, {} no-csq, {} bad-span, {} no-id, {} many-alts, {} bad-alt",
+            self.total(), self.long_ref, self.no_csq, self.bad_span, self.no_id, self.many_alts, self.bad_alt
+        )
+    }
+}
+
+/// A BED line; consecutive records of the same variant are merged into it before it is written
+#[derive(Serialize, Clone)]
+pub struct Line {
+    pub chromosome: String,
+    // 0-based start and exclusive end
+    pub start: u64,
+    pub end: u64,
+    pub id: String,
+    pub variety: String,
+    pub reference: String,
+    // sorted so that the output is reproducible between runs
+    pub alts: BTreeSet<String>,
+    pub group: u8,
+    pub severity: String,
+    #[serde(skip)]
+    pub severity_rank: u8,
+    // highest allele frequency of the alts, if the af column is written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub af: Option<f64>,
+    // most pathogenic clinical significance of the alts, if the clin_sig column is written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clin_sig: Option<String>,
+    // values of the passed through INFO tags the record has, comma separated
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub info: BTreeMap<String, String>,
+    // distinct consequence terms of the alts, if the n_consequences column is written
+    #[serde(skip)]
+    pub consequences: BTreeSet<String>,
+    // feature (transcript) of the CSQ entries of the line with --per-transcript, . for entries without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    // 1-based POS of the record, for the vcf_pos column
+    #[serde(skip)]
+    pub vcf_pos: u64
+}
+
+impl Line {
+    pub fn compatible(&self, other: &Line) -> bool {
+        self.chromosome == other.chromosome &&
+        self.id == other.id &&
+        self.start == other.start &&
+        self.reference == other.reference &&
+        self.variety == other.variety &&
+        self.transcript == other.transcript
+    }
+    
+    pub fn redundant(&self, other: &Line) -> bool {
+        self.id == other.id && 
+        self.variety != other.variety 
+    }
+
+    // the consequence term, or its accession if there is one with --severity-as accession
+    fn severity_column<'a>(&'a self, options: &'a Options) -> &'a str {
+        match options.severity_as {
+            SeverityAs::Term => &self.severity,
+            SeverityAs::Accession => options.accessions.get(&self.severity).unwrap_or(&self.severity)
+        }
+    }
+
+    fn write(&self, options: &Options, out: &mut dyn LineSink) -> io::Result<()> {
+        let out = out.writer(self.group)?;
+        if options.format == OutputFormat::Jsonl {
+            let mut line = serde_json::to_value(self)?;
+            line["severity"] = self.severity_column(options).into();
+            if let Some(colors) = &options.group_colors {
+                line["item_rgb"] = colors.get(&self.group).map_or("0,0,0", String::as_str).into();
+            }
+            serde_json::to_writer(&mut *out, &line)?;
+            return writeln!(out);
+        }
+
+        let name = options.name_template.render(self, options);
+        if options.format == OutputFormat::Bin {
+            let alts = self.alts.iter().map(String::as_str).collect::<Vec<_>>().join(",");
+            let group = self.group.to_string();
+            let trailing = self.trailing_columns(options);
+            let mut columns = vec![&*name, &self.variety, &self.reference, &alts, &group, self.severity_column(options)];
+            columns.extend(trailing.iter().map(|column| &**column));
+            return binary::write_record(out, &self.chromosome, self.start, self.end, &columns);
+        }
+
+        let d = &options.delimiter;
+        write!(out, "{}{d}{}{d}{}{d}{}{d}{}{d}{}{d}", self.chromosome, self.start, self.end, name, self.variety, self.reference)?;
+        for (index, alt) in self.alts.iter().enumerate() {
+            write!(out, "{}{}", if index > 0 { "," } else { "" }, alt)?;
+        }
+        write!(out, "{d}{}{d}{}", self.group, self.severity_column(options))?;
+        for column in self.trailing_columns(options) {
+            write!(out, "{d}{}", column)?;
+        }
+        writeln!(out)
+    }
+
+    // the columns after the consequence: transcript, colour and extra columns and passed through INFO
+    fn trailing_columns<'a>(&'a self, options: &'a Options) -> Vec<Cow<'a, str>> {
+        let mut columns = Vec::new();
+        if let Some(transcript) = &self.transcript {
+            columns.push(Cow::from(transcript));
+        }
+        if let Some(colors) = &options.group_colors {
+            columns.push(self.start.to_string().into());
+            columns.push(self.end.to_string().into());
+            columns.push(colors.get(&self.group).map_or("0,0,0", String::as_str).into());
+        }
+        for column in &options.extra_columns {
+            columns.push(match column {
+                ExtraColumn::Af => self.af.map_or(".".into(), |af| af.to_string().into()),
+                ExtraColumn::ClinSig => self.clin_sig.as_deref().unwrap_or(".").into(),
+                ExtraColumn::Impact => options.impacts.get(&self.severity).map_or(".", String::as_str).into(),
+                ExtraColumn::Length => (self.end - self.start).to_string().into(),
+                ExtraColumn::NConsequences => self.consequences.len().to_string().into(),
+                ExtraColumn::VcfPos => self.vcf_pos.to_string().into()
+            });
+        }
+        for tag in &options.info_passthrough {
+            columns.push(self.info.get(tag).map_or(".", String::as_str).into());
+        }
+        columns
+    }
+    
+    pub fn merge(&mut self, mut more: Option<Line>, options: &Options, out: &mut dyn LineSink) -> io::Result<()> {
+        // merge new line if not empty (and a Line instance)
+        if let Some(ref mut more) = more {
+            if self.compatible(more) {
+                debug!("merging {} {}:{} into the current line", more.id, more.chromosome, more.start);
+                self.alts.append(&mut more.alts);
+                self.af = match (self.af, more.af) {
+                    (Some(af), Some(more_af)) => Some(af.max(more_af)),
+                    (af, more_af) => af.or(more_af)
+                };
+                self.clin_sig = more_pathogenic(self.clin_sig.take(), more.clin_sig.take());
+                for (tag, value) in std::mem::take(&mut more.info) {
+                    self.info.entry(tag).or_insert(value);
+                }
+                self.consequences.append(&mut more.consequences);
+                // the span covers both records whichever is more severe, e.g. when only one of them has INFO END
+                self.end = self.end.max(more.end);
+                if more.severity_rank < self.severity_rank {
+                    self.group = more.group;
+                    self.severity = std::mem::take(&mut more.severity);
+                    self.severity_rank = more.severity_rank;
+                }
+                return Ok(());
+            }
+            
+            // if somehow with same rs id we have different variety of variant we skip the later ones
+            if self.redundant(more) {
+                debug!("skipping {} {} - redundant with {}", more.id, more.variety, self.variety);
+                return Ok(());
+            }
+        }
+        
+        // if new Line is not compatible with the current one it is a new variant
+        // print out the current line
+        if !self.alts.is_empty() && options.written(self) && options.within_contig(self) {
+            debug!("writing {} {}:{}", self.id, self.chromosome, self.start);
+            self.write(options, out)?;
+        }
+        
+        // make the new Line as the current one
+        if let Some(more) = more {
+            *self = more;
+        }
+        Ok(())
+    }
+}
+
+/// Where merged lines are written: a writer takes the lines of every variant group, other sinks give each group
+/// a writer of its own, as the files of --split-by-group
+pub trait LineSink {
+    /// Writer of the next line, which is of the group
+    fn writer(&mut self, group: u8) -> io::Result<&mut dyn Write>;
+}
+
+impl<W: Write> LineSink for W {
+    fn writer(&mut self, _group: u8) -> io::Result<&mut dyn Write> {
+        Ok(self)
+    }
+}
+
+/// The lines of the variants at the current position - more than one when records have several ids (e.g. rs1;rs2),
+/// so that the lines of each id are merged even when consecutive records alternate between them
+#[derive(Default)]
+pub struct Merger {
+    pending: Vec<Line>
+}
+
+impl Merger {
+    pub fn add(&mut self, line: Line, options: &Options, out: &mut dyn LineSink) -> io::Result<()> {
+        let moved = self.pending.first().is_some_and(|current| {
+            current.chromosome != line.chromosome || current.start != line.start
+        });
+        if moved {
+            self.finish(options, out)?;
+        }
+
+        // split alts of the same id are kept apart
+        let same = |current: &Line| current.id == line.id && (!options.split_alts || current.alts == line.alts);
+        match self.pending.iter_mut().find(|current| same(current)) {
+            Some(current) => current.merge(Some(line), options, out),
+            None => {
+                self.pending.push(line);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the pending lines in the order their ids were first seen
+    pub fn finish(&mut self, options: &Options, out: &mut dyn LineSink) -> io::Result<()> {
+        for mut current in self.pending.drain(..) {
+            current.merge(None, options, out)?;
+        }
+        Ok(())
+    }
+}
+
+// dummy initial value for the object to read line from vcf
+// this line is guranteed to not get printed as alt.len == 0
+impl Default for Line {
+    fn default() -> Self {
+        Line {
+            chromosome: "".to_string(),
+            start: 1,
+            end: 0,
+            id: "".to_string(),
+            variety: "".to_string(),
+            reference: "".to_string(),
+            alts: BTreeSet::new(),
+            group: 0,
+            severity: "".to_string(),
+            severity_rank: 255,
+            af: None,
+            clin_sig: None,
+            info: BTreeMap::new(),
+            consequences: BTreeSet::new(),
+            transcript: None,
+            vcf_pos: 0
+        }
+    }
+}
+
+/// Settings for converting records, taken from the command line
+pub struct Options {
+    /// INFO key of the annotation entries, CSQ for VEP
+    pub annotation_key: String,
+    /// tool that wrote the annotation entries
+    pub annotator: Annotator,
+    /// index of the Allele field within a CSQ entry
+    pub allele_field: usize,
+    /// index of the Consequence field within a CSQ entry
+    pub consequence_field: usize,
+    /// index of the VARIANT_CLASS field within a CSQ entry, none if the entries have no variant class and it is worked
+    /// out from the alleles
+    pub class_field: Option<usize>,
+    /// separator between the consequence terms of a CSQ entry
+    pub consequence_delimiter: String,
+    /// leave out the FORMAT and sample columns before the records are parsed
+    pub ignore_samples: bool,
+    /// lowercase consequence terms before they are ranked and grouped, or write a built-in term in its own case
+    pub lowercase_consequences: bool,
+    /// the built-in consequence terms by their lowercase, see [`known_terms`]
+    pub known_terms: HashMap<String, String>,
+    /// fail on a record that comes before the one it follows, or on a CSQ entry not of csq_width subfields, instead
+    /// of warning
+    pub strict: bool,
+    /// number of subfields of a CSQ entry in the header Format, if it describes one
+    pub csq_width: Option<usize>,
+    /// keep the case of the alleles instead of uppercasing them
+    pub preserve_case: bool,
+    /// skip variants with longer reference allele, 0 for no limit
+    pub max_ref_length: usize,
+    /// skip variants with more alts, 0 for no limit
+    pub max_alts: usize,
+    /// where the ids of the lines come from
+    pub id_source: IdSource,
+    /// what happens to a record without an id
+    pub missing_id: MissingId,
+    /// separator between the fields of a BED line
+    pub delimiter: String,
+    /// where an insertion is placed
+    pub insertion_span: InsertionSpan,
+    /// format of the name column of a BED line
+    pub name_template: NameTemplate,
+    /// how lines are written
+    pub format: OutputFormat,
+    /// only write lines of these variant groups, all if empty
+    pub groups: Vec<u8>,
+    /// only write lines whose most severe consequence ranks at or below this, lower ranks being more severe
+    pub min_severity_rank: Option<u8>,
+    /// only convert records of contigs matching one of these names or * patterns, all if empty
+    pub include_contigs: Vec<String>,
+    /// do not convert records of contigs matching one of these names or * patterns, even if they are included
+    pub exclude_contigs: Vec<String>,
+    /// chromosome naming of the output, as in the VCF if none
+    pub chr_style: Option<ChrStyle>,
+    /// only convert records overlapping one of these regions (named as in the VCF), all if empty
+    pub regions: Vec<Region>,
+    /// length of each chromosome (named as in the output) that lines are checked to end within, none checked if empty
+    pub contig_lengths: HashMap<String, u64>,
+    /// what happens to a line that ends past the length of its chromosome
+    pub past_contig_end: PastContigEnd,
+    /// write a line for each alt with its own most severe consequence instead of one for all the alts
+    pub split_alts: bool,
+    /// index of the Feature field within a CSQ entry if a line is written for each feature (transcript) with its
+    /// own most severe consequence, none for one line for all features
+    pub feature_field: Option<usize>,
+    /// itemRgb colour of each variant group, written with thickStart and thickEnd after the nine BED columns if some;
+    /// a group without one is black
+    pub group_colors: Option<HashMap<u8, String>>,
+    /// columns written after the nine BED columns, in this order
+    pub extra_columns: Vec<ExtraColumn>,
+    /// which of two consequences of the same rank is the most severe
+    pub tie_break: TieBreak,
+    /// what the severity column holds
+    pub severity_as: SeverityAs,
+    /// Sequence Ontology accession of each consequence term
+    pub accessions: HashMap<String, String>,
+    /// impact (HIGH, MODERATE, LOW or MODIFIER) of each consequence term, for the impact column
+    pub impacts: HashMap<String, String>,
+    /// where the allele frequency of the af column comes from
+    pub af_field: Field,
+    /// where the clinical significance of the clin_sig column comes from
+    pub clin_sig_field: Field,
+    /// INFO tags written as columns after the extra columns, in this order
+    pub info_passthrough: Vec<String>,
+    /// report the records read and lines written to stderr this often
+    pub progress: Option<Duration>
+}
+
+impl Options {
+    // whether the line passes the group and severity filters
+    fn written(&self, line: &Line) -> bool {
+        (self.groups.is_empty() || self.groups.contains(&line.group))
+            && self.min_severity_rank.is_none_or(|rank| line.severity_rank <= rank)
+    }
+
+    // whether the line is written by the chromosome lengths, warning of one ending past its chromosome
+    fn within_contig(&self, line: &Line) -> bool {
+        let Some(length) = self.contig_lengths.get(&line.chromosome) else {
+            return true;
+        };
+        if line.end <= *length {
+            return true;
+        }
+        let dropped = self.past_contig_end == PastContigEnd::Drop;
+        warn!("{} {}:{}-{} ends past {} of length {}{}", line.id, line.chromosome, line.start, line.end,
+            line.chromosome, length, if dropped { ", dropping it" } else { "" }
+        );
+        !dropped
+    }
+
+    /// A consequence term as it is looked up in the severity ranks and variant groups
+    pub fn lookup_term(&self, term: String) -> String {
+        if !self.lowercase_consequences {
+            return term;
+        }
+        let lowercase = term.to_lowercase();
+        self.known_terms.get(&lowercase).cloned().unwrap_or(lowercase)
+    }
+
+    // the consequence terms of a CSQ entry as they are looked up
+    fn lookup_terms(&self, terms: String) -> String {
+        if !self.lowercase_consequences {
+            return terms;
+        }
+        terms.split(self.consequence_delimiter.as_str())
+            .map(|term| self.lookup_term(term.to_string()))
+            .collect::<Vec<_>>()
+            .join(&self.consequence_delimiter)
+    }
+
+    /// The reader the records are parsed from, leaving out the sample columns if they are ignored
+    pub fn reader<'a>(&self, input: impl BufRead + 'a) -> Box<dyn BufRead + 'a> {
+        if self.ignore_samples {
+            Box::new(SitesOnly::new(input))
+        } else {
+            Box::new(input)
+        }
+    }
+
+    /// whether records of the contig are converted by the include and exclude lists
+    pub fn contig_allowed(&self, contig: &str) -> bool {
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| wildcard_match(pattern, contig));
+        (self.include_contigs.is_empty() || matches(&self.include_contigs)) && !matches(&self.exclude_contigs)
+    }
+}
+
+// whether the name matches the pattern, where * matches any run of characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else { return false };
+
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else { return rest.is_empty() };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            annotation_key: "CSQ".to_string(),
+            annotator: Annotator::Vep,
+            allele_field: 0,
+            consequence_field: 1,
+            class_field: Some(21),
+            consequence_delimiter: "&".to_string(),
+            ignore_samples: true,
+            lowercase_consequences: true,
+            known_terms: known_terms(),
+            strict: false,
+            csq_width: None,
+            preserve_case: false,
+            max_ref_length: 0,
+            max_alts: 0,
+            id_source: IdSource::Column,
+            missing_id: MissingId::Skip,
+            delimiter: "\t".to_string(),
+            insertion_span: InsertionSpan::Empty,
+            name_template: NameTemplate::default(),
+            format: OutputFormat::Bed,
+            groups: Vec::new(),
+            min_severity_rank: None,
+            include_contigs: Vec::new(),
+            exclude_contigs: Vec::new(),
+            chr_style: None,
+            regions: Vec::new(),
+            contig_lengths: HashMap::new(),
+            past_contig_end: PastContigEnd::Warn,
+            split_alts: false,
+            feature_field: None,
+            group_colors: None,
+            extra_columns: Vec::new(),
+            tie_break: TieBreak::First,
+            severity_as: SeverityAs::Term,
+            accessions: HashMap::new(),
+            impacts: HashMap::new(),
+            af_field: Field::Info("AF".to_string()),
+            clin_sig_field: Field::Info("CLIN_SIG".to_string()),
+            info_passthrough: Vec::new(),
+            progress: None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExtraColumn {
+    /// highest allele frequency of the alts, . if there is none
+    Af,
+    /// most pathogenic ClinVar clinical significance of the alts, . if there is none
+    #[value(name = "clin_sig")]
+    ClinSig,
+    /// impact of the most severe consequence, HIGH, MODERATE, LOW or MODIFIER, . for a term without one
+    Impact,
+    /// number of reference bases the BED interval covers, end - start, so 0 for an insertion
+    Length,
+    /// number of distinct consequence terms of the CSQ entries of the alts
+    #[value(name = "n_consequences")]
+    NConsequences,
+    /// POS of the VCF record, 1-based, to compare with the 0-based start
+    #[value(name = "vcf_pos")]
+    VcfPos
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ChrStyle {
+    /// without a chr prefix, e.g. 1 and MT
+    Ensembl,
+    /// with a chr prefix, e.g. chr1 and chrM
+    Ucsc
+}
+
+impl ChrStyle {
+    /// The chromosome name in this style, the mitochondrion is MT in Ensembl and chrM in UCSC
+    pub fn rename(&self, chromosome: &str) -> String {
+        let bare = chromosome.strip_prefix("chr").unwrap_or(chromosome);
+        match (self, bare) {
+            (ChrStyle::Ensembl, "M" | "MT") => "MT".to_string(),
+            (ChrStyle::Ensembl, _) => bare.to_string(),
+            (ChrStyle::Ucsc, "M" | "MT") => "chrM".to_string(),
+            (ChrStyle::Ucsc, _) => format!("chr{}", bare)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SeverityAs {
+    /// the consequence term, e.g. missense_variant
+    Term,
+    /// the Sequence Ontology accession of the term, e.g. SO:0001583
+    Accession
+}
+
+/// Which of two consequences of the same severity rank is the most severe one of a variant
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum TieBreak {
+    /// the one seen first, in the order of the CSQ entries and their terms
+    First,
+    /// the one in the lower variant group, a term without a group coming last
+    Group,
+    /// the one first in alphabetical order
+    Alpha
+}
+
+impl TieBreak {
+    // whether a term of the same rank as the most severe one so far takes its place
+    fn prefers(self, term: &str, group: u8, most_severe: &str, most_severe_group: u8) -> bool {
+        match self {
+            TieBreak::First => false,
+            // group 0 wraps round to come after the others
+            TieBreak::Group => group.wrapping_sub(1) < most_severe_group.wrapping_sub(1),
+            TieBreak::Alpha => term < most_severe
+        }
+    }
+}
+
+/// What happens to a line ending past the length of its chromosome, which bedToBigBed rejects
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum PastContigEnd {
+    /// write it with a warning
+    Warn,
+    /// leave it out with a warning
+    Drop
+}
+
+/// How an insertion, which replaces no reference base, is placed in BED coordinates
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum InsertionSpan {
+    /// the empty interval between the anchor base at POS and the next base, POS..POS
+    Empty,
+    /// the anchor base at POS, POS - 1..POS
+    Anchor,
+    /// the base after the anchor, POS..POS + 1
+    Next
+}
+
+impl InsertionSpan {
+    // the interval of an insertion after the 1-based anchor position, none if the next base is past the coordinates
+    fn span(self, anchor: u64) -> Option<(u64, u64)> {
+        match self {
+            InsertionSpan::Empty => Some((anchor, anchor)),
+            InsertionSpan::Anchor => Some((anchor - 1, anchor)),
+            InsertionSpan::Next => Some((anchor, anchor.checked_add(1)?))
+        }
+    }
+}
+
+/// Tool that wrote the annotations of the records, which decides their layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Annotator {
+    /// VEP, under CSQ: Allele trimmed of the base shared by REF and ALT, Consequence and VARIANT_CLASS
+    Vep,
+    /// SnpEff, under ANN: Allele as in ALT and Annotation, but no variant class
+    SnpEff
+}
+
+impl Annotator {
+    /// SnpEff for its ANN key, VEP for CSQ or any other key
+    pub fn for_key(key: &str) -> Annotator {
+        match key {
+            "ANN" => Annotator::SnpEff,
+            _ => Annotator::Vep
+        }
+    }
+
+    /// name of the subfield with the consequence terms in the header Format
+    pub fn consequence_name(&self) -> &'static str {
+        match self {
+            Annotator::Vep => "Consequence",
+            Annotator::SnpEff => "Annotation"
+        }
+    }
+
+    /// index of the subfield with the variant class in the default layout
+    pub fn class_index(&self) -> Option<usize> {
+        match self {
+            Annotator::Vep => Some(21),
+            Annotator::SnpEff => None
+        }
+    }
+
+    // the alts as the tool writes them in the Allele field, in the order of the alts
+    fn alleles(&self, reference: &str, alts: &BTreeSet<String>) -> Vec<String> {
+        match self {
+            Annotator::Vep => vep_alleles(reference, alts),
+            Annotator::SnpEff => alts.iter().cloned().collect()
+        }
+    }
+}
+
+/// A value of a record, either from a CSQ subfield at the given index or from an INFO tag
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Csq(usize),
+    Info(String)
+}
+
+impl Field {
+    /// The CSQ subfield of the name if the header Format has it, otherwise the INFO tag
+    pub fn named(name: &str, format: &Option<HashMap<String, usize>>) -> Field {
+        match format.as_ref().and_then(|format| format.get(name)) {
+            Some(index) => Field::Csq(*index),
+            None => Field::Info(name.to_string())
+        }
+    }
+
+    // non-empty values of the field, from the CSQ entries selected by matching for a CSQ subfield
+    fn values(&self, record: &VCFRecord, csq: &CsqFields, matching: &[bool], name: &str) -> Vec<String> {
+        let values = match self {
+            Field::Csq(index) => csq.column(*index, name).into_iter()
+                .zip(matching)
+                .filter(|(_, m)| **m)
+                .map(|(value, _)| value)
+                .collect::<Vec<_>>(),
+            Field::Info(tag) => record.info(tag.as_bytes()).map(|values| {
+                values.iter().map(|value| String::from_utf8_lossy(value).to_string()).collect()
+            }).unwrap_or_default()
+        };
+        values.into_iter().filter(|value| !value.is_empty() && value != ".").collect()
+    }
+}
+
+/// A genomic region given as chromosome:start-end with 1-based inclusive coordinates, or just a chromosome
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub chromosome: String,
+    pub start: u64,
+    pub end: u64
+}
+
+impl Region {
+    /// whether the 1-based inclusive span overlaps the region
+    pub fn overlaps(&self, chromosome: &str, start: u64, end: u64) -> bool {
+        self.chromosome == chromosome && start <= self.end && end >= self.start
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((chromosome, span)) = s.rsplit_once(':') else {
+            return Ok(Region { chromosome: s.to_string(), start: 1, end: u64::MAX });
+        };
+
+        let invalid = || format!("expected chromosome:start-end, got {}", s);
+        let number = |n: &str| n.replace(',', "").parse::<u64>().map_err(|_| invalid());
+        let (start, end) = match span.split_once('-') {
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (number(span)?, u64::MAX)
+        };
+        if chromosome.is_empty() || start == 0 || start > end {
+            return Err(invalid());
+        }
+
+        Ok(Region { chromosome: chromosome.to_string(), start, end })
+    }
+}
+
+/// Where the ids naming the lines of a record come from, `column` or `info:TAG`
+#[derive(Clone, Debug, PartialEq)]
+pub enum IdSource {
+    /// the ID column
+    Column,
+    /// an INFO tag, whose numbers (as those of the RS tag of dbSNP) are prefixed with rs
+    Info(String)
+}
+
+impl std::str::FromStr for IdSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "column" => Ok(IdSource::Column),
+            Some(("info", tag)) if !tag.is_empty() => Ok(IdSource::Info(tag.to_string())),
+            _ => Err(format!("expected column or info:TAG, got {}", s))
+        }
+    }
+}
+
+/// Format of the name column of the BED lines, in which {id}, {severity} and {variety} stand for the values of
+/// the line, e.g. `{id}_{severity}`; it cannot hold whitespace, which would break the column
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    const PLACEHOLDERS: [&'static str; 3] = ["id", "severity", "variety"];
+
+    /// The template as given
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // the name of the line, the id itself for the default template
+    fn render<'a>(&self, line: &'a Line, options: &'a Options) -> Cow<'a, str> {
+        if self.0 == "{id}" {
+            return Cow::Borrowed(&line.id);
+        }
+        let mut name = String::new();
+        let mut rest = self.0.as_str();
+        // the template is checked when parsed, so every { opens a known placeholder
+        while let Some((before, tail)) = rest.split_once('{') {
+            let (placeholder, tail) = tail.split_once('}').unwrap_or((tail, ""));
+            name += before;
+            name += match placeholder {
+                "id" => &line.id,
+                "severity" => line.severity_column(options),
+                _ => &line.variety
+            };
+            rest = tail;
+        }
+        Cow::Owned(name + rest)
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        NameTemplate("{id}".to_string())
+    }
+}
+
+impl std::str::FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.chars().any(char::is_whitespace) {
+            return Err(format!("name template cannot hold whitespace: {:?}", s));
+        }
+        let mut rest = s;
+        while let Some(open) = rest.find(['{', '}']) {
+            match rest[open..].strip_prefix('{').and_then(|tail| tail.split_once('}')) {
+                Some((placeholder, tail)) if Self::PLACEHOLDERS.contains(&placeholder) => rest = tail,
+                _ => return Err(format!("expected placeholders {{id}}, {{severity}} or {{variety}} in the name template, got {}", s))
+            }
+        }
+        Ok(NameTemplate(s.to_string()))
+    }
+}
+
+/// What happens to a record without an id in the id source
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum MissingId {
+    /// it is skipped
+    Skip,
+    /// its lines are named .
+    Dot,
+    /// its lines are named by its position and alleles, chromosome_position_ref_alts
+    Synthesize
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// BED columns
+    Bed,
+    /// a JSON object per line
+    Jsonl,
+    /// length-prefixed binary records of the BED columns, for merge_bed --format bin, see [`binary`]
+    Bin
+}
+
+// input is read from stdin if the path is -
+// and wrapped in a gzip decoder if it starts with the gzip magic bytes, then in a BCF decoder if it is BCF
+// fill_buf peeks into the buffer without consuming it so the VCF reader still gets the whole stream
+// (stdin cannot be rewound)
+pub fn open_vcf(path: &str) -> io::Result<Box<dyn BufRead>> {
+    let input: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+
+    let mut reader = BufReader::new(input);
+    let mut reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    if reader.fill_buf()?.starts_with(bcf::MAGIC) {
+        reader = Box::new(BcfText::new(reader)?);
+    }
+    Ok(reader)
+}
+
+/// The VCFs read one after the other as one VCF with the header of the first, each of the others without its header
+pub fn open_vcfs(paths: &[String]) -> Result<Box<dyn BufRead>, Error> {
+    let open = |path: &String| open_vcf(path).map_err(|source| Error::Open { path: path.clone(), source });
+    let mut reader = open(&paths[0])?;
+    for path in &paths[1..] {
+        let mut next = open(path)?;
+        let mut header = Vec::new();
+        while next.fill_buf().map_err(|source| Error::Open { path: path.clone(), source })?.starts_with(b"#") {
+            next.read_until(b'\n', &mut header).map_err(|source| Error::Open { path: path.clone(), source })?;
+        }
+        reader = Box::new(reader.chain(next));
+    }
+    Ok(reader)
+}
+
+/// VCF lines without their FORMAT and sample columns, so that the genotypes are not parsed
+pub struct SitesOnly<R> {
+    inner: R,
+    line: Vec<u8>,
+    consumed: usize
+}
+
+impl<R: BufRead> SitesOnly<R> {
+    pub fn new(inner: R) -> Self {
+        SitesOnly { inner, line: Vec::new(), consumed: 0 }
+    }
+
+    // cut a record or the #CHROM line after its eighth (INFO) column, meta-information lines are kept whole
+    fn next_line(&mut self) -> io::Result<()> {
+        self.line.clear();
+        self.consumed = 0;
+        self.inner.read_until(b'\n', &mut self.line)?;
+        if self.line.starts_with(b"##") {
+            return Ok(());
+        }
+
+        if let Some((ninth, _)) = self.line.iter().enumerate().filter(|(_, b)| **b == b'\t').nth(7) {
+            self.line.truncate(ninth);
+            self.line.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for SitesOnly<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for SitesOnly<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.line.len() {
+            self.next_line()?;
+        }
+        Ok(&self.line[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+    }
+}
+
+/// Header lines of the VCF, to put in front of records read from the middle of the file
+pub fn read_header(path: &str) -> io::Result<Vec<u8>> {
+    let mut reader = open_vcf(path)?;
+    let mut header = Vec::new();
+    while reader.fill_buf()?.starts_with(b"#") {
+        reader.read_until(b'\n', &mut header)?;
+    }
+
+    Ok(header)
+}
+
+// map each CSQ subfield name to its index using the Format given in the INFO header description of the key,
+// e.g. Format: Allele|Consequence|... for VEP or Functional annotations: 'Allele | Annotation | ...' for SnpEff
+pub fn csq_format(header: &VCFHeader, key: &str) -> Option<HashMap<String, usize>> {
+    let info = header.info(key.as_bytes())?;
+    let description = String::from_utf8_lossy(info.description);
+    let format = match description.split_once("Format:") {
+        Some((_, format)) => format,
+        None => description.split_once(':').filter(|(_, format)| format.contains('|'))?.1
+    };
+
+    Some(format.trim().trim_matches(['\'', '"']).split('|').enumerate().map(|(index, name)| {
+        (name.trim().to_string(), index)
+    }).collect())
+}
+
+// index of a CSQ subfield - given by the user, looked up from the header or the default VEP layout
+pub fn csq_field_index(given: Option<usize>, format: &Option<HashMap<String, usize>>, name: &str, default: usize) -> usize {
+    if let Some(index) = given {
+        return index;
+    }
+
+    match format.as_ref().map(|format| format.get(name)) {
+        Some(Some(index)) => *index,
+        Some(None) => {
+            warn!("CSQ header Format does not have {0} field, using index {1}", name, default);
+            default
+        },
+        None => default
+    }
+}
+
+// the text of each CSQ entry of the record - INFO is scanned for CSQ once per record, see [`CsqFields`]
+fn csq_entries<'a>(record: &'a VCFRecord, key: &str) -> Vec<Cow<'a, str>> {
+    record.info(key.as_bytes())
+        .map(|csqs| csqs.iter().map(|csq| String::from_utf8_lossy(csq)).collect())
+        .unwrap_or_default()
+}
+
+// CSQ entries of a record split into their subfields, each entry is split once whatever the subfields read from it
+struct CsqFields<'a> {
+    record: &'a VCFRecord,
+    entries: Vec<Vec<&'a str>>
+}
+
+impl<'a> CsqFields<'a> {
+    fn new(record: &'a VCFRecord, entries: &'a [Cow<'a, str>]) -> Self {
+        CsqFields { record, entries: entries.iter().map(|entry| entry.split('|').collect()).collect() }
+    }
+
+    // get a subfield from each of the CSQ entries, warn if an entry is too short to have it
+    fn column(&self, index: usize, name: &str) -> Vec<String> {
+        self.entries.iter().map(|fields| {
+            fields.get(index).map(|field| field.to_string()).unwrap_or_else(|| {
+                warn!("CSQ entry of variant ({0}:{1}) has {2} fields, expected {3} at index {4}",
+                    record_chromosome(self.record),
+                    self.record.position,
+                    fields.len(),
+                    name,
+                    index
+                );
+                String::new()
+            })
+        }).collect()
+    }
+}
+
+// alleles as VEP writes them in the Allele field of CSQ - if all the alleles start with the same base it is trimmed,
+// leaving - for an allele that becomes empty (e.g. REF AC ALT A gives -, REF A ALT AT gives T)
+// in the order of the alts
+fn vep_alleles(reference: &str, alts: &BTreeSet<String>) -> Vec<String> {
+    let Some(first) = reference.chars().next() else {
+        return alts.iter().cloned().collect();
+    };
+    if !alts.iter().all(|alt| alt.starts_with(first) && !alt.starts_with('<')) {
+        return alts.iter().cloned().collect();
+    }
+
+    alts.iter().map(|alt| match &alt[first.len_utf8()..] {
+        "" => "-".to_string(),
+        rest => rest.to_string()
+    }).collect()
+}
+
+// variant class of alleles without one from the annotations, sequence_alteration if the alts are of different classes
+fn allele_class(reference: &str, alts: &BTreeSet<String>) -> &'static str {
+    let classes = alts.iter().map(|alt| match (reference.len(), alt.len()) {
+        (1, 1) => "SNV",
+        (r, a) if r == a => "substitution",
+        (1, _) if alt.starts_with(reference) => "insertion",
+        (_, 1) if reference.starts_with(alt.as_str()) => "deletion",
+        _ => "indel"
+    }).collect::<BTreeSet<_>>();
+
+    match classes.len() {
+        1 => classes.into_iter().next().unwrap_or("sequence_alteration"),
+        _ => "sequence_alteration"
+    }
+}
+
+// decode the %XX escapes of a VCF 4.3 INFO value, leaving anything that is not a valid escape as it is
+fn percent_decode(value: String) -> String {
+    if !value.contains('%') {
+        return value;
+    }
+
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// alleles of bases are uppercased unless the case is preserved, symbolic and breakend alleles are left as they are
+fn normalize_case(mut allele: String, options: &Options) -> String {
+    if !options.preserve_case && allele.bytes().all(|b| b.is_ascii_alphabetic()) {
+        allele.make_ascii_uppercase();
+    }
+    allele
+}
+
+fn record_chromosome(record: &VCFRecord) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(&record.chromosome)
+}
+
+// borrow a field of the record as a str, failing with the variant location if it is not UTF-8
+fn utf8_str<'a>(bytes: &'a [u8], field: &'static str, record: &VCFRecord) -> Result<&'a str, Error> {
+    std::str::from_utf8(bytes).map_err(|_| Error::Utf8 {
+        field,
+        chromosome: record_chromosome(record).to_string(),
+        position: record.position
+    })
+}
+
+fn utf8(bytes: &[u8], field: &'static str, record: &VCFRecord) -> Result<String, Error> {
+    utf8_str(bytes, field, record).map(str::to_string)
+}
+
+/// Convert a VCF record into BED lines, one for each of its ids, or none if the record is skipped
+pub fn process_record(
+    record: &VCFRecord,
+    severity: &HashMap<String, u8>,
+    variant_groups: &HashMap<String, u8>,
+    options: &Options,
+    skipped: &mut Skipped
+) -> Result<Vec<Line>, Error> {
+    skipped.records += 1;
+    let reference = normalize_case(utf8(&record.reference, "REF", record)?, options);
+    let ref_len = reference.len() as u64;
+    // a malformed record whose reference allele is empty or does not fit the coordinates cannot be placed
+    let Some(last) = reference_last(record.position, ref_len) else {
+        warn!("skipping {}:{} - reference allele of {} bases cannot be placed at the position", record_chromosome(record), record.position, ref_len);
+        skipped.bad_span += 1;
+        return Ok(vec![]);
+    };
+    if !options.regions.is_empty() {
+        let chromosome = record_chromosome(record);
+        if !options.regions.iter().any(|region| region.overlaps(&chromosome, record.position, last)) {
+            return Ok(vec![]);
+        }
+    }
+    if !options.contig_allowed(&record_chromosome(record)) {
+        return Ok(vec![]);
+    }
+    if options.max_ref_length > 0 && reference.len() > options.max_ref_length {
+        debug!("skipping {}:{} - reference allele longer than {}", record_chromosome(record), record.position, options.max_ref_length);
+        skipped.long_ref += 1;
+        return Ok(vec![]);
+    }
+    if options.max_alts > 0 && record.alternative.len() > options.max_alts {
+        debug!("skipping {}:{} - more than {} alts", record_chromosome(record), record.position, options.max_alts);
+        skipped.many_alts += 1;
+        return Ok(vec![]);
+    }
+    
+    // * stands for an allele removed by an overlapping deletion, which has a record of its own
+    let alts = record.alternative.iter().filter(|a| a.as_slice() != b"*").map(|a| {
+        utf8(a, "ALT", record).map(|alt| normalize_case(alt, options))
+    }).collect::<Result<BTreeSet<_>,_>>()?;
+    if alts.is_empty() {
+        debug!("skipping {}:{} - no alt other than *", record_chromosome(record), record.position);
+        return Ok(vec![]);
+    }
+    let mut chromosome = utf8(&record.chromosome, "CHROM", record)?;
+    if let Some(style) = options.chr_style {
+        chromosome = style.rename(&chromosome);
+    }
+
+    let mut ids = record_ids(record, &options.id_source)?;
+    if ids.is_empty() {
+        match options.missing_id {
+            MissingId::Skip => {
+                debug!("skipping {}:{} - no id", record_chromosome(record), record.position);
+                skipped.no_id += 1;
+                return Ok(vec![]);
+            },
+            MissingId::Dot => ids.push(".".to_string()),
+            MissingId::Synthesize => {
+                let alts = alts.iter().map(String::as_str).collect::<Vec<_>>().join(",");
+                ids.push(format!("{}_{}_{}_{}", chromosome, record.position, reference, alts));
+            }
+        }
+    }
+    
+    // characters that are not allowed in INFO values, such as a , between consequences, are percent-encoded by VCF 4.3
+    let csq_info = csq_entries(record, &options.annotation_key);
+    let csq_fields = CsqFields::new(record, &csq_info);
+    // an entry of another width has its subfields where the Format does not expect them, so they are misread
+    let misfit = options.csq_width
+        .and_then(|width| Some((width, csq_fields.entries.iter().find(|fields| fields.len() != width)?)));
+    if let Some((width, entry)) = misfit {
+        let error = Error::CsqWidth {
+            key: options.annotation_key.clone(),
+            chromosome: record_chromosome(record).to_string(),
+            position: record.position,
+            fields: entry.len(),
+            expected: width
+        };
+        if options.strict {
+            return Err(error);
+        }
+        warn!("{}", error);
+    }
+    let csq = csq_fields.column(options.consequence_field, "Consequence").into_iter()
+        .map(percent_decode)
+        .map(|terms| options.lookup_terms(terms))
+        .collect::<Vec<_>>();
+    // if csq is empty we won't have most severe consequence
+    if csq.is_empty(){
+        debug!("skipping {}:{} - no {}", record_chromosome(record), record.position, options.annotation_key);
+        skipped.no_csq += 1;
+        return Ok(vec![]);
+    }
+    
+    let class = options.class_field.map(|index| csq_fields.column(index, "VARIANT_CLASS"));
+    
+    // VEP writes one CSQ entry per allele and transcript - only the entries of the alts of this record count
+    // if none match (e.g. the Allele field is elsewhere) all the entries are used
+    let csq_alleles = csq_fields.column(options.allele_field, "Allele").into_iter()
+        .map(|allele| normalize_case(allele, options))
+        .collect::<Vec<_>>();
+    let vep_alts = options.annotator.alleles(&reference, &alts);
+    let mut matching = csq_alleles.iter().map(|allele| vep_alts.contains(allele)).collect::<Vec<_>>();
+    let structural = structural_variant(record, &alts);
+    // VEP writes the class of a structural variant (e.g. deletion) as the allele of its entries
+    if structural.is_some() {
+        matching.iter_mut().for_each(|m| *m = true);
+    }
+    else if !matching.contains(&true) {
+        warn!("no CSQ entry of variant ({0}:{1}) is for one of its alleles, using all entries", chromosome, record.position);
+        matching.iter_mut().for_each(|m| *m = true);
+    }
+    
+    let info = options.info_passthrough.iter().filter_map(|tag| {
+        let values = record.info(tag.as_bytes())?;
+        let values = values.iter().map(|value| String::from_utf8_lossy(value)).collect::<Vec<_>>();
+        Some((tag.clone(), values.join(",")))
+    }).collect::<BTreeMap<_, _>>();
+    
+    // with --split-alts each alt gets lines of its own, from the CSQ entries of that alt only
+    let subsets = if options.split_alts && alts.len() > 1 {
+        alts.iter().zip(&vep_alts).map(|(alt, vep_alt)| {
+            let mut alt_matching = csq_alleles.iter().map(|allele| allele == vep_alt).collect::<Vec<_>>();
+            if structural.is_some() || !alt_matching.contains(&true) {
+                alt_matching = matching.clone();
+            }
+            (BTreeSet::from([alt.clone()]), alt_matching)
+        }).collect::<Vec<_>>()
+    } else {
+        vec![(alts, matching)]
+    };
+    // with --per-transcript the entries of each alt subset are grouped further by their feature
+    let subsets = match options.feature_field {
+        Some(feature_field) => {
+            let features = csq_fields.column(feature_field, "Feature");
+            subsets.into_iter().flat_map(|(alts, matching)| {
+                let mut by_feature = BTreeMap::<&str, Vec<bool>>::new();
+                for (index, feature) in features.iter().enumerate().filter(|(index, _)| matching[*index]) {
+                    let feature = if feature.is_empty() { "." } else { feature.as_str() };
+                    by_feature.entry(feature).or_insert_with(|| vec![false; matching.len()])[index] = true;
+                }
+                by_feature.into_iter()
+                    .map(|(feature, matching)| (alts.clone(), matching, Some(feature.to_string())))
+                    .collect::<Vec<_>>()
+            }).collect::<Vec<_>>()
+        },
+        None => subsets.into_iter().map(|(alts, matching)| (alts, matching, None)).collect()
+    };
+    
+    let mut lines = Vec::new();
+    for (alts, matching, transcript) in subsets {
+        let af = if options.extra_columns.contains(&ExtraColumn::Af) {
+            // VEP joins the frequencies of co-located variants with &
+            options.af_field.values(record, &csq_fields, &matching, "allele frequency").iter()
+                .flat_map(|value| value.split('&').filter_map(|af| af.parse::<f64>().ok()).collect::<Vec<_>>())
+                .reduce(f64::max)
+        } else {
+            None
+        };
+        let clin_sig = if options.extra_columns.contains(&ExtraColumn::ClinSig) {
+            // ClinVar submissions can disagree, e.g. pathogenic&likely_pathogenic, the most pathogenic term is kept
+            options.clin_sig_field.values(record, &csq_fields, &matching, "clinical significance").iter()
+                .flat_map(|value| value.split('&').filter(|term| !term.is_empty()).map(str::to_string).collect::<Vec<_>>())
+                .fold(None, |most, term| more_pathogenic(most, Some(term)))
+        } else {
+            None
+        };
+    
+        let mut variant_group = 0;
+        let mut most_severe_csq = "";
+        let mut most_severe_csq_rank = 255;
+        let mut consequences = BTreeSet::new();
+    
+        // calculate most severe consequence and variant group of that consequence
+        for ((csq_str, allele), _) in csq.iter().zip(&csq_alleles).zip(&matching).filter(|(_, m)| **m) {
+            debug!("{} consequence of allele {} is {}", ids.join(";"), allele, csq_str);
+            for csq_here in csq_str.split(options.consequence_delimiter.as_str()).map(str::trim).filter(|term| !term.is_empty()) {
+                if options.extra_columns.contains(&ExtraColumn::NConsequences) {
+                    consequences.insert(csq_here.to_string());
+                }
+                let csq_rank_here = severity.get(csq_here).copied().unwrap_or_else(|| {
+                    if !skipped.unranked.contains(csq_here) {
+                        skipped.unranked.insert(csq_here.to_string());
+                    }
+                    UNRANKED
+                });
+                let group_here = *variant_groups.get(csq_here).unwrap_or(&0);
+                let tied = csq_rank_here == most_severe_csq_rank && !most_severe_csq.is_empty()
+                    && options.tie_break.prefers(csq_here, group_here, most_severe_csq, variant_group);
+                if csq_rank_here < most_severe_csq_rank || tied {
+                    variant_group = group_here;
+                    most_severe_csq = csq_here;
+                    most_severe_csq_rank = csq_rank_here;
+                }
+            }
+        }
+    
+        debug!("most severe consequence of {} is {} (rank {})", ids.join(";"), most_severe_csq, most_severe_csq_rank);
+    
+        // calcualte variant class - we store it as variety
+        // variety should always be same for each variant allele - VEP puts variant class at variant level (using Bio::EnsEMBL::Variation::Utils::Sequence::SO_variation_class)
+        // if cannot be deduced the default value is - sequence_alteration
+        // with split alts the class is taken from the first entry of the alt
+        let class_index = matching.iter().position(|m| *m).filter(|_| options.split_alts).unwrap_or(0);
+        let mut variety = match &class {
+            Some(class) => class[class_index].to_string(),
+            None => allele_class(&reference, &alts).to_string()
+        };
+    
+        if let Some(sv) = &structural {
+            variety = sv.variety.to_string();
+        }
+        // if sequence_alteration we check if we can convert it to indel (the condition is that all the variant allele is eiter insertion or deletion or indel)
+        else if variety.eq(&String::from("sequence_alteration")) {
+            let mut convert_sequence_alteration = true;
+            for alt in alts.iter() {
+                // note that we are not minimilizing the variant alleles here 
+                let calc_variety = match (alt.len()<2, reference.len()<2, alt.len() == reference.len()) {
+                    (true, true, true) => { "SNV" },
+                    (true, false, false) => { "deletion" },
+                    (false, true, false) => { "insertion" },
+                    (false, false, false) => { "indel" },
+                    (false, false, true) => { "substitute" },
+                    // only an empty alt is shorter than the reference allele, which is at least a base
+                    _ => {
+                        warn!("skipping {}:{} - empty alt allele", chromosome, record.position);
+                        skipped.bad_alt += 1;
+                        return Ok(vec![]);
+                    }
+                };
+            
+                // if any of the variant allele is SNV or substitution we will log (because this is not a regular case)
+                // and, keep the variety as sequence_alteration
+                if calc_variety.eq(&String::from("SNV")) || calc_variety.eq(&String::from("substitute")) {
+                    warn!("sequence_alteration variant ({0} {1}:{2}) contain variant allele of type {3}",
+                        ids.join(";"),
+                        chromosome,
+                        record.position,
+                        calc_variety
+                    );
+        
+                    convert_sequence_alteration = false;
+                    break;
+                }
+            }
+        
+            if convert_sequence_alteration {
+                variety = "indel".to_string();
+            }
+        }
+
+        let (mut start, mut end) = match &structural {
+            Some(sv) => (sv.start, sv.end),
+            None if variety == "sequence_alteration" => alts_span(record.position, &reference, &alts),
+            None => {
+                let end = compute_end(&variety, record.position, ref_len);
+                // start position in bed is 0-indexed - an insertion is an empty interval after the anchor base
+                (if variety == "insertion" { end } else { record.position - 1 }, end)
+            }
+        };
+        // INFO END (the last affected base, 1-based) is the end of sequence alleles too when it is given
+        if structural.is_none() {
+            if let Some(info_end) = end_tag(record).filter(|info_end| *info_end >= start) {
+                end = info_end;
+            }
+        }
+        if variety == "insertion" && start == end {
+            let Some(span) = options.insertion_span.span(start) else {
+                warn!("skipping {}:{} - the base after the insertion is past the end of the coordinates", chromosome, record.position);
+                skipped.bad_span += 1;
+                return Ok(vec![]);
+            };
+            (start, end) = span;
+        }
+    
+        for id in ids.iter() {
+            let more = Line {
+                chromosome: chromosome.clone(),
+                start,
+                end,
+                id: id.to_string(),
+                variety: variety.clone(),
+                reference: reference.clone(),
+                alts: alts.clone(),
+                group: variant_group,
+                severity: most_severe_csq.to_string(),
+                severity_rank: most_severe_csq_rank,
+                af,
+                clin_sig: clin_sig.clone(),
+                info: info.clone(),
+                consequences: consequences.clone(),
+                transcript: transcript.clone(),
+                vcf_pos: record.position
+            };
+        
+            lines.push(more);
+        }
+    
+    }
+    
+    Ok(lines)
+}
+
+/// Convert one VCF record into its BED lines, for tools embedding the conversion without reading a file through
+/// [`Converter`]
+///
+/// The CSQ entries are read, the most severe consequence of the alts ranked by severity and grouped by
+/// variant_groups, and the BED span worked out. [`SEVERITY`] and [`VARIANTGROUP`] are the built-in tables, and
+/// `Options::default()` reads VEP annotations under CSQ. There is a line for each id of the record, and none if
+/// the record is skipped. The lines are not merged with those of neighbouring records of the same variant,
+/// which [`Line::merge`] does.
+pub fn record_to_lines(
+    record: &VCFRecord,
+    severity: &HashMap<String, u8>,
+    variant_groups: &HashMap<String, u8>,
+    options: &Options
+) -> Result<Vec<Line>, Error> {
+    process_record(record, severity, variant_groups, options, &mut Skipped::default())
+}
+
+// BED span and variety of a record with a symbolic structural variant alt such as <DEL>
+struct StructuralVariant {
+    start: u64,
+    end: u64,
+    variety: &'static str
+}
+
+fn info_value(record: &VCFRecord, key: &[u8]) -> Option<String> {
+    record.info(key)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).to_string())
+}
+
+// the ids of the record from the source, each getting a line of its own
+fn record_ids(record: &VCFRecord, source: &IdSource) -> Result<Vec<String>, Error> {
+    let mut ids = Vec::new();
+    match source {
+        // an ID such as rs1;rs2 lists co-located variants merged by dbSNP
+        IdSource::Column => for b in record.id.iter() {
+            ids.extend(utf8_str(b, "ID", record)?.split(';').filter(|id| !id.is_empty()).map(str::to_string));
+        },
+        IdSource::Info(tag) => for value in record.info(tag.as_bytes()).into_iter().flatten() {
+            let id = String::from_utf8_lossy(value);
+            if id.is_empty() || id == "." {
+                continue;
+            }
+            // the RS tag of dbSNP holds the number of the rs id
+            ids.push(if id.bytes().all(|b| b.is_ascii_digit()) { format!("rs{}", id) } else { id.to_string() });
+        }
+    }
+    Ok(ids)
+}
+
+fn end_tag(record: &VCFRecord) -> Option<u64> {
+    info_value(record, b"END").and_then(|end| end.parse::<u64>().ok())
+}
+
+// the span comes from INFO END (the last affected base, 1-based) or else SVLEN rather than the reference allele,
+// and keeps the padding base at POS like the deletions of sequence alleles; insertions are empty intervals after POS
+fn structural_variant(record: &VCFRecord, alts: &BTreeSet<String>) -> Option<StructuralVariant> {
+    let symbolic = alts.iter().find(|alt| alt.starts_with('<') && alt.ends_with('>'))?;
+    // SVTYPE or the alt up to its subtype, e.g. DEL of <DEL:ME:ALU>
+    let sv_type = info_value(record, b"SVTYPE")
+        .unwrap_or_else(|| symbolic[1..symbolic.len() - 1].split(':').next().unwrap_or_default().to_string());
+    let variety = match sv_type.as_str() {
+        "DEL" => "deletion",
+        "INS" => "insertion",
+        "DUP" => "duplication",
+        "INV" => "inversion",
+        "CNV" => "copy_number_variation",
+        _ => "sequence_alteration"
+    };
+
+    let position = record.position;
+    if variety == "insertion" {
+        return Some(StructuralVariant { start: position, end: position, variety });
+    }
+
+    let end = end_tag(record)
+        .or_else(|| {
+            info_value(record, b"SVLEN")
+                .and_then(|length| length.parse::<i64>().ok())
+                .and_then(|length| position.checked_add(length.unsigned_abs()))
+        })
+        .unwrap_or_else(|| {
+            warn!("structural variant ({0}:{1}) has neither END nor SVLEN, using its reference allele span", record_chromosome(record), position);
+            position - 1 + record.reference.len() as u64
+        });
+    Some(StructuralVariant { start: position - 1, end: end.max(position), variety })
+}
+
+/// Last 1-based position of a reference allele, none if the allele is empty, the position is 0 or the allele runs
+/// past the end of the coordinates
+///
+/// A record that passes this can be placed by [`compute_end`] and [`alts_span`] without overflowing.
+pub fn reference_last(position: u64, ref_len: u64) -> Option<u64> {
+    if position == 0 || ref_len == 0 {
+        return None;
+    }
+    position.checked_add(ref_len - 1)
+}
+
+/// Exclusive BED end of a variant from its 1-based VCF position and reference allele length
+///
+/// The reference allele covers the 1-based inclusive positions `position..=position + ref_len - 1`,
+/// which are the 0-based half-open `position - 1..position - 1 + ref_len` in BED, and every variety but the
+/// insertion replaces or removes the whole of it, whatever the alt length - an SNV is the case of a one base
+/// reference, a deletion or indel drops its anchor base along with the rest. An insertion replaces nothing: it is
+/// placed between the anchor base at `position` and the next base, giving the empty interval `position..position`.
+pub fn compute_end(variety: &str, position: u64, ref_len: u64) -> u64 {
+    match variety {
+        "insertion" => position,
+        _ => position - 1 + ref_len
+    }
+}
+
+/// BED span covering every alt of a record VEP calls sequence_alteration, e.g. an SNV and an insertion
+///
+/// Each alt is placed by what it does to the reference: a single base change covers its base, an insertion
+/// after the anchor base is the empty interval at `position` and anything else covers the whole reference allele.
+pub fn alts_span(position: u64, reference: &str, alts: &BTreeSet<String>) -> (u64, u64) {
+    let ref_len = reference.len() as u64;
+    alts.iter().map(|alt| {
+        let variety = match (alt.len(), reference.len()) {
+            (1, 1) => "SNV",
+            (alt_len, 1) if alt_len > 1 && alt.as_bytes()[0] == reference.as_bytes()[0] => "insertion",
+            _ => "sequence_alteration"
+        };
+        let end = compute_end(variety, position, ref_len);
+        (if variety == "insertion" { end } else { position - 1 }, end)
+    })
+    .reduce(|(start, end), (alt_start, alt_end)| (start.min(alt_start), end.max(alt_end)))
+    .unwrap_or((position - 1, compute_end("sequence_alteration", position, ref_len)))
+}
+
+/// Read a config file as text, decompressing it first if it is gzipped
+pub fn read_config(path: &str) -> io::Result<String> {
+    let content = std::fs::read(path)?;
+    if !content.starts_with(&[0x1f, 0x8b]) {
+        return String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    let mut text = String::new();
+    MultiGzDecoder::new(content.as_slice()).read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Read a file mapping consequence terms to severity rank, either a JSON object or tab separated term and rank lines
+///
+/// The ranks are numbers, given as strings in JSON (e.g. "13"), and are parsed here once for all the records.
+pub fn read_severity(path: &str) -> Result<HashMap<String, u8>, Error> {
+    let content = read_config(path).map_err(|source| Error::Open { path: path.to_string(), source })?;
+    if content.trim_start().starts_with('{') {
+        let ranks = serde_json::from_str::<HashMap<String, String>>(&content)
+            .map_err(|source| Error::Severity { path: path.to_string(), source })?;
+        return ranks.into_iter().map(|(term, rank)| match rank.parse::<u8>() {
+            Ok(rank) => Ok((term, rank)),
+            Err(_) => Err(Error::SeverityRank { path: path.to_string(), term, value: rank })
+        }).collect();
+    }
+
+    let mut severity = HashMap::new();
+    // trailing whitespace, such as the \r of a line ending in \r\n or a tab after the rank, is left out
+    for (index, line) in content.lines().map(str::trim_end).enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: String| Error::SeverityLine { path: path.to_string(), line: index + 1, message };
+        let (term, rank) = match line.split('\t').collect::<Vec<_>>()[..] {
+            [term, rank] => (term.trim(), rank.trim()),
+            _ => return Err(error(format!("expected a term and a rank separated by a tab: {}", line)))
+        };
+        let Ok(rank) = rank.parse::<u8>() else {
+            return Err(error(format!("severity rank of {} is not a number: {}", term, rank)));
+        };
+        severity.insert(term.to_string(), rank);
+    }
+    Ok(severity)
+}
+
+/// Read a file mapping consequence terms to variant groups, either a JSON object or tab separated term and group lines
+pub fn read_variant_groups(path: &str) -> Result<HashMap<String, u8>, Error> {
+    let error = |message: String| Error::Groups { path: path.to_string(), message };
+    let content = read_config(path).map_err(|source| Error::Open { path: path.to_string(), source })?;
+
+    let entries = if content.trim_start().starts_with('{') {
+        serde_json::from_str::<HashMap<String, serde_json::Value>>(&content)
+            .map_err(|e| error(e.to_string()))?
+            .into_iter()
+            .map(|(term, group)| {
+                let group = match group {
+                    serde_json::Value::String(group) => group,
+                    group => group.to_string()
+                };
+                (term, group)
+            })
+            .collect::<Vec<_>>()
+    } else {
+        content.lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once('\t') {
+                Some((term, group)) => Ok((term.trim().to_string(), group.trim().to_string())),
+                None => Err(error(format!("expected a term and a group separated by a tab: {}", line)))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    entries.into_iter().map(|(term, group)| {
+        match group.parse::<u8>() {
+            Ok(group) if GROUPS.contains(&group) => Ok((term, group)),
+            _ => Err(error(format!("group of {} is not between {} and {}: {}", term, GROUPS.start(), GROUPS.end(), group)))
+        }
+    }).collect()
+}
+
+/// Colours of variant groups from a JSON object of group to R,G,B or a tab separated file of the two
+pub fn read_group_colors(path: &str) -> Result<HashMap<u8, String>, Error> {
+    let error = |message: String| Error::Colors { path: path.to_string(), message };
+    let content = read_config(path).map_err(|source| Error::Open { path: path.to_string(), source })?;
+
+    let entries = if content.trim_start().starts_with('{') {
+        serde_json::from_str::<HashMap<String, String>>(&content)
+            .map_err(|e| error(e.to_string()))?
+            .into_iter()
+            .collect::<Vec<_>>()
+    } else {
+        content.lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once('\t') {
+                Some((group, color)) => Ok((group.trim().to_string(), color.trim().to_string())),
+                None => Err(error(format!("expected a group and a colour separated by a tab: {}", line)))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    entries.into_iter().map(|(group, color)| {
+        let group = match group.parse::<u8>() {
+            Ok(group) if GROUPS.contains(&group) => group,
+            _ => return Err(error(format!("group is not between {} and {}: {}", GROUPS.start(), GROUPS.end(), group)))
+        };
+        let components = color.split(',').map(|c| c.trim().parse::<u8>()).collect::<Result<Vec<_>, _>>();
+        match components {
+            Ok(components) if components.len() == 3 => Ok((group, components.iter().map(u8::to_string).collect::<Vec<_>>().join(","))),
+            _ => Err(error(format!("colour of group {} is not R,G,B with each between 0 and 255: {}", group, color)))
+        }
+    }).collect()
+}
+
+/// Consequence terms with a variant group that the severity ranks do not cover, sorted
+pub fn unranked_terms(severity: &HashMap<String, u8>, variant_groups: &HashMap<String, u8>) -> Vec<String> {
+    let mut terms = variant_groups.keys().filter(|term| !severity.contains_key(*term)).cloned().collect::<Vec<_>>();
+    terms.sort();
+    terms
+}
+
+// the contigs seen and the last record, to find records that come before the one they follow
+#[derive(Default)]
+struct OrderCheck {
+    contigs: HashSet<Vec<u8>>,
+    previous: Option<(Vec<u8>, u64)>,
+    unsorted: u64
+}
+
+impl OrderCheck {
+    // location of the previous record if the record is at an earlier position of its contig or on a contig
+    // that was left before
+    fn regression(&mut self, record: &VCFRecord) -> Option<String> {
+        let location = |(chromosome, position): &(Vec<u8>, u64)| format!("{}:{}", String::from_utf8_lossy(chromosome), position);
+        let regression = match &self.previous {
+            Some((chromosome, position)) if *chromosome == record.chromosome => record.position < *position,
+            Some(_) => self.contigs.contains(&record.chromosome),
+            None => false
+        };
+        let previous = self.previous.replace((record.chromosome.clone(), record.position));
+        self.contigs.insert(record.chromosome.clone());
+
+        if !regression {
+            return None;
+        }
+        self.unsorted += 1;
+        previous.as_ref().map(location)
+    }
+}
+
+/// Severity ranks, variant groups and options needed to convert records
+pub struct Converter {
+    pub severity: HashMap<String, u8>,
+    pub variant_groups: HashMap<String, u8>,
+    pub options: Options
+}
+
+impl Converter {
+    /// Convert all the records of the reader, writing the merged lines to out
+    pub fn convert<R: BufRead>(&self, reader: &mut VCFReader<R>, path: &str, out: &mut dyn LineSink) -> Result<Skipped, Error> {
+        self.convert_reporting(reader, path, out, self.progress())
+    }
+
+    // reporter of the progress of a conversion, if asked for
+    fn progress(&self) -> Option<Progress> {
+        self.options.progress.map(|every| Progress::new(every, Box::new(io::stderr())))
+    }
+
+    fn convert_reporting<R: BufRead>(&self, reader: &mut VCFReader<R>, path: &str, out: &mut dyn LineSink, mut progress: Option<Progress>) -> Result<Skipped, Error> {
+        let mut counted;
+        let out: &mut dyn LineSink = match &progress {
+            Some(progress) => {
+                counted = progress.lines(out);
+                &mut counted
+            }
+            None => out
+        };
+        let mut record = reader.empty_record();
+        let mut lines = Merger::default();
+        let mut skipped = Skipped::default();
+        let mut order = OrderCheck::default();
+        while reader.next_record(&mut record).map_err(|source| Error::Vcf { path: path.to_string(), source })? {
+            self.check_order(&mut order, &record, path)?;
+            if let Some(progress) = &mut progress {
+                progress.record()?;
+            }
+            for more in process_record(&record, &self.severity, &self.variant_groups, &self.options, &mut skipped)? {
+                lines.add(more, &self.options, out)?;
+            }
+        }
+
+        lines.finish(&self.options, out)?;
+        self.order_summary(&order, path);
+        Ok(skipped)
+    }
+
+    /// Convert the records of the reader with a pool of worker threads, writing the same output as [`Converter::convert`]
+    ///
+    /// The calling thread reads the records in batches, which idle workers take from a bounded channel, and writes the
+    /// lines of each batch in the order it was read, so merging sees the records in VCF order. Batches read ahead of the
+    /// next one to write are bounded, and the records of written batches are reused for the next ones.
+    pub fn convert_threaded<R: BufRead>(&self, reader: &mut VCFReader<R>, path: &str, threads: usize, out: &mut dyn LineSink) -> Result<Skipped, Error> {
+        if threads < 2 {
+            return self.convert(reader, path, out);
+        }
+        let mut progress = self.progress();
+        let mut counted;
+        let out: &mut dyn LineSink = match &progress {
+            Some(progress) => {
+                counted = progress.lines(out);
+                &mut counted
+            }
+            None => out
+        };
+        let in_flight = threads * 4;
+        let (work, batches) = mpsc::sync_channel::<(usize, Vec<VCFRecord>)>(in_flight);
+        let batches = Mutex::new(batches);
+        let (done, results) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let done = done.clone();
+                let batches = &batches;
+                scope.spawn(move || {
+                    // the lock is only held while waiting for the next batch
+                    let next = || batches.lock().ok().and_then(|batches| batches.recv().ok());
+                    while let Some((number, batch)) = next() {
+                        let mut skipped = Skipped::default();
+                        let lines = batch.iter()
+                            .map(|record| process_record(record, &self.severity, &self.variant_groups, &self.options, &mut skipped))
+                            .collect::<Result<Vec<_>, _>>();
+                        if done.send((number, lines.map(|lines| (lines, skipped)), batch)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(done);
+
+            let mut lines = Merger::default();
+            let mut skipped = Skipped::default();
+            let mut order = OrderCheck::default();
+            let mut spare = Vec::new();
+            let mut pending = BTreeMap::new();
+            let (mut read, mut written) = (0, 0);
+            let mut more = true;
+            while more || written < read {
+                // write the batches that are next in order, waiting for one if too many are read ahead or all are read
+                pending.extend(results.try_iter().map(|(number, result, batch)| (number, (result, batch))));
+                while written < read && (read - written >= in_flight || !more || pending.contains_key(&written)) {
+                    let Some((result, batch)) = pending.remove(&written) else {
+                        let (number, result, batch) = results.recv().expect("workers run until the batches are all sent");
+                        pending.insert(number, (result, batch));
+                        continue;
+                    };
+                    let (batch_lines, batch_skipped): (Vec<Vec<Line>>, Skipped) = result?;
+                    for line in batch_lines.into_iter().flatten() {
+                        lines.add(line, &self.options, out)?;
+                    }
+                    skipped += batch_skipped;
+                    spare.extend(batch);
+                    written += 1;
+                }
+                if !more {
+                    continue;
+                }
+
+                let mut batch = Vec::with_capacity(BATCH_SIZE);
+                while batch.len() < BATCH_SIZE {
+                    let mut record = spare.pop().unwrap_or_else(|| reader.empty_record());
+                    if !reader.next_record(&mut record).map_err(|source| Error::Vcf { path: path.to_string(), source })? {
+                        more = false;
+                        break;
+                    }
+                    self.check_order(&mut order, &record, path)?;
+                    if let Some(progress) = &mut progress {
+                        progress.record()?;
+                    }
+                    batch.push(record);
+                }
+                if !batch.is_empty() {
+                    work.send((read, batch)).expect("workers run until the batches are all sent");
+                    read += 1;
+                }
+            }
+            drop(work);
+
+            lines.finish(&self.options, out)?;
+            self.order_summary(&order, path);
+            Ok(skipped)
+        })
+    }
+
+    // warn about the first record out of coordinate order, an error if strict
+    fn check_order(&self, order: &mut OrderCheck, record: &VCFRecord, path: &str) -> Result<(), Error> {
+        if let Some(previous) = order.regression(record) {
+            if self.options.strict {
+                return Err(Error::Unsorted {
+                    path: path.to_string(),
+                    chromosome: record_chromosome(record).to_string(),
+                    position: record.position,
+                    previous
+                });
+            }
+            if order.unsorted == 1 {
+                warn!("variant at {}:{} comes after {} - {} is not sorted and its lines are written out of order",
+                    record_chromosome(record), record.position, previous, path);
+            }
+        }
+        Ok(())
+    }
+
+    fn order_summary(&self, order: &OrderCheck, path: &str) {
+        if order.unsorted > 1 {
+            warn!("{} records of {} come before the record they follow", order.unsorted, path);
+        }
+    }
+
+    /// Convert the records of the regions, using the tabix index to skip to them
+    pub fn convert_regions(&self, path: &str, index: &Index, out: &mut dyn LineSink) -> Result<Skipped, Error> {
+        let header = read_header(path).map_err(|source| Error::Open { path: path.to_string(), source })?;
+        let mut records: Box<dyn Read> = Box::new(Cursor::new(header));
+        for contig in index.contigs().filter(|contig| self.has_regions_on(contig)) {
+            records = Box::new(records.chain(self.contig_records(index, path, contig)?));
+        }
+
+        let mut reader = VCFReader::new(self.options.reader(BufReader::new(records)))
+            .map_err(|source| Error::Vcf { path: path.to_string(), source })?;
+        self.convert(&mut reader, path, out)
+    }
+
+    fn has_regions_on(&self, contig: &str) -> bool {
+        if !self.options.contig_allowed(contig) {
+            return false;
+        }
+        self.options.regions.is_empty() || self.options.regions.iter().any(|region| region.chromosome == contig)
+    }
+
+    // records of the contig, or only from the first to the last of its regions if there are any
+    fn contig_records(&self, index: &Index, path: &str, contig: &str) -> Result<ContigReader, Error> {
+        let regions = self.options.regions.iter().filter(|region| region.chromosome == contig);
+        let span = regions.fold(None, |span: Option<(u64, u64)>, region| match span {
+            Some((start, end)) => Some((start.min(region.start), end.max(region.end))),
+            None => Some((region.start, region.end))
+        });
+
+        match span {
+            Some((start, end)) => index.region_reader(path, contig, start, end),
+            None => index.contig_reader(path, contig)
+        }.map_err(|source| Error::Index { path: path.to_string(), source })
+    }
+
+    /// Convert the contigs of a bgzipped and tabix indexed VCF in parallel
+    ///
+    /// Each contig is converted on its own into a temporary file, so no line is merged across contigs,
+    /// and the files are then written to out in the order the contigs appear in the VCF.
+    pub fn convert_parallel(&self, path: &str, threads: usize, out: &mut dyn LineSink) -> Result<Skipped, Error> {
+        let index = Index::for_vcf(path)
+            .map_err(|source| Error::Index { path: path.to_string(), source })?
+            .ok_or_else(|| Error::NoIndex { path: path.to_string() })?;
+        let header = read_header(path).map_err(|source| Error::Open { path: path.to_string(), source })?;
+        let contigs = index.contigs().filter(|contig| self.has_regions_on(contig)).collect::<Vec<_>>();
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+        let parts = pool.install(|| contigs.par_iter().map(|contig| {
+            let records = self.contig_records(&index, path, contig)?;
+            let mut reader = VCFReader::new(self.options.reader(BufReader::new(Cursor::new(&header).chain(records))))
+                .map_err(|source| Error::Vcf { path: path.to_string(), source })?;
+
+            let mut part = Part::create()?;
+            // contigs converted at the same time do not report progress
+            let skipped = self.convert_reporting(&mut reader, path, &mut part, None)?;
+            Ok((part, skipped))
+        }).collect::<Result<Vec<_>, Error>>())?;
+
+        let mut skipped = Skipped::default();
+        for (part, part_skipped) in parts {
+            part.copy(out)?;
+            skipped += part_skipped;
+        }
+        Ok(skipped)
+    }
+}
+
+// the lines of a contig converted by convert_parallel, each kept in the temporary file after its group and length
+// so that it is written on to the writer of its group
+struct Part {
+    file: BufWriter<File>,
+    group: u8,
+    line: Vec<u8>
+}
+
+impl Part {
+    fn create() -> io::Result<Self> {
+        Ok(Part { file: BufWriter::new(tempfile::tempfile()?), group: 0, line: Vec::new() })
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.file.write_all(&[self.group])?;
+            self.file.write_all(&(self.line.len() as u64).to_le_bytes())?;
+            self.file.write_all(&self.line)?;
+            self.line.clear();
+        }
+        Ok(())
+    }
+
+    fn copy(mut self, out: &mut dyn LineSink) -> io::Result<()> {
+        self.end_line()?;
+        let mut file = self.file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        file.rewind()?;
+        let mut file = BufReader::new(file);
+        let mut group = [0; 1];
+        let mut length = [0; 8];
+        while !file.fill_buf()?.is_empty() {
+            file.read_exact(&mut group)?;
+            file.read_exact(&mut length)?;
+            self.line.resize(u64::from_le_bytes(length) as usize, 0);
+            file.read_exact(&mut self.line)?;
+            out.writer(group[0])?.write_all(&self.line)?;
+        }
+        Ok(())
+    }
+}
+
+impl LineSink for Part {
+    fn writer(&mut self, group: u8) -> io::Result<&mut dyn Write> {
+        self.end_line()?;
+        self.group = group;
+        Ok(&mut self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(id: &str, start: u64, variety: &str, alt: &str, severity: &str, severity_rank: u8) -> Line {
+        Line {
+            chromosome: "1".to_string(),
+            start,
+            end: start + 1,
+            id: id.to_string(),
+            variety: variety.to_string(),
+            reference: "A".to_string(),
+            alts: BTreeSet::from([alt.to_string()]),
+            group: 3,
+            severity: severity.to_string(),
+            severity_rank,
+            af: None,
+            clin_sig: None,
+            info: BTreeMap::new(),
+            consequences: BTreeSet::new(),
+            transcript: None,
+            vcf_pos: start + 1
+        }
+    }
+
+    #[test]
+    fn end_is_computed_for_each_variety() {
+        // REF A at 100
+        assert_eq!(compute_end("SNV", 100, 1), 100);
+        // REF A ALT ATT at 100, inserted between 100 and 101
+        assert_eq!(compute_end("insertion", 100, 1), 100);
+        // REF ACG ALT A at 100
+        assert_eq!(compute_end("deletion", 100, 3), 102);
+        // REF ACG ALT TT at 100
+        assert_eq!(compute_end("indel", 100, 3), 102);
+        assert_eq!(compute_end("delins", 100, 3), 102);
+        // REF AC ALT GT at 100
+        assert_eq!(compute_end("substitution", 100, 2), 101);
+        assert_eq!(compute_end("MNV", 100, 2), 101);
+        assert_eq!(compute_end("sequence_alteration", 100, 4), 103);
+        // an SNV covers its reference allele too, should it be longer than one base
+        assert_eq!(compute_end("SNV", 100, 2), 101);
+    }
+
+    #[test]
+    fn reference_that_cannot_be_placed_has_no_last_position() {
+        assert_eq!(reference_last(100, 3), Some(102));
+        assert_eq!(reference_last(100, 0), None);
+        assert_eq!(reference_last(0, 1), None);
+        assert_eq!(reference_last(u64::MAX, 1), Some(u64::MAX));
+        assert_eq!(reference_last(u64::MAX, 2), None);
+    }
+
+    #[test]
+    fn contigs_are_matched_by_pattern() {
+        let options = Options {
+            include_contigs: vec!["chr*".to_string(), "MT".to_string()],
+            exclude_contigs: vec!["*_alt".to_string(), "chrUn*".to_string()],
+            ..Options::default()
+        };
+
+        assert!(options.contig_allowed("chr1"));
+        assert!(options.contig_allowed("MT"));
+        assert!(!options.contig_allowed("1"));
+        assert!(!options.contig_allowed("chr1_KI270762v1_alt"));
+        assert!(!options.contig_allowed("chrUn_GL000220v1"));
+        assert!(Options::default().contig_allowed("anything"));
+        assert!(wildcard_match("a*b*c", "aXbYc") && !wildcard_match("a*b*c", "aXcYb") && wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn sequence_alteration_spans_all_alts() {
+        let alts = |alts: &[&str]| alts.iter().map(|alt| alt.to_string()).collect::<BTreeSet<_>>();
+
+        // REF A ALT T,AGG at 100 - the SNV covers the anchor base the insertion follows
+        assert_eq!(alts_span(100, "A", &alts(&["T", "AGG"])), (99, 100));
+        assert_eq!(alts_span(100, "A", &alts(&["AGG"])), (100, 100));
+        // REF AC ALT GT,ACT at 100 - the substitution of both bases covers the insertion
+        assert_eq!(alts_span(100, "AC", &alts(&["GT", "ACT"])), (99, 101));
+        // REF ACG ALT T,A at 100
+        assert_eq!(alts_span(100, "ACG", &alts(&["T", "A"])), (99, 102));
+    }
+
+    #[test]
+    fn class_is_worked_out_from_the_alleles() {
+        let alts = |alts: &[&str]| alts.iter().map(|alt| alt.to_string()).collect::<BTreeSet<_>>();
+
+        assert_eq!(allele_class("A", &alts(&["G", "T"])), "SNV");
+        assert_eq!(allele_class("AC", &alts(&["GT"])), "substitution");
+        assert_eq!(allele_class("A", &alts(&["AT", "ATT"])), "insertion");
+        assert_eq!(allele_class("ACG", &alts(&["A"])), "deletion");
+        assert_eq!(allele_class("ACG", &alts(&["TT"])), "indel");
+        assert_eq!(allele_class("A", &alts(&["AT", "G"])), "sequence_alteration");
+    }
+
+    #[test]
+    fn vep_alleles_are_trimmed_of_the_shared_first_base() {
+        let alts = |alts: &[&str]| alts.iter().map(|alt| alt.to_string()).collect::<BTreeSet<_>>();
+
+        assert_eq!(vep_alleles("A", &alts(&["AT", "A"])), ["-", "T"]);
+        assert_eq!(vep_alleles("A", &alts(&["G"])), ["G"]);
+        assert_eq!(vep_alleles("A", &alts(&["A", "<DEL>"])), ["<DEL>", "A"]);
+        // the first character is shared whatever its width, without slicing inside it
+        assert_eq!(vep_alleles("é", &alts(&["éT"])), ["T"]);
+        assert_eq!(vep_alleles("é", &alts(&["èT"])), ["èT"]);
+    }
+
+    #[test]
+    fn csq_columns_line_up_by_entry() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tG,AT\t.\t.\tCSQ=G|missense_variant|SNV,T|intron_variant|insertion,T\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+
+        let entries = csq_entries(&record, "CSQ");
+        let csq = CsqFields::new(&record, &entries);
+        assert_eq!(csq.column(0, "Allele"), ["G", "T", "T"]);
+        assert_eq!(csq.column(1, "Consequence"), ["missense_variant", "intron_variant", ""]);
+        assert_eq!(csq.column(2, "VARIANT_CLASS"), ["SNV", "insertion", ""]);
+    }
+
+    #[test]
+    fn record_with_empty_reference_is_skipped() {
+        // the VCF reader refuses an empty REF column, so the allele is emptied after reading
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tT\t.\t.\tCSQ=T|missense_variant\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+        record.reference.clear();
+
+        let mut skipped = Skipped::default();
+        let lines = process_record(&record, &HashMap::new(), &HashMap::new(), &Options::default(), &mut skipped).unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(skipped.bad_span, 1);
+    }
+
+    #[test]
+    fn insertion_whose_next_base_cannot_be_placed_is_skipped() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tAT\t.\t.\tCSQ=T|intron_variant|insertion\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+        record.position = u64::MAX;
+
+        let options = Options { insertion_span: InsertionSpan::Next, class_field: Some(2), ..Options::default() };
+        let mut skipped = Skipped::default();
+        let lines = process_record(&record, &HashMap::new(), &HashMap::new(), &options, &mut skipped).unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(skipped.bad_span, 1);
+
+        let options = Options { insertion_span: InsertionSpan::Anchor, ..options };
+        let lines = process_record(&record, &HashMap::new(), &HashMap::new(), &options, &mut skipped).unwrap();
+        assert_eq!((lines[0].start, lines[0].end), (u64::MAX - 1, u64::MAX));
+    }
+
+    #[test]
+    fn record_with_empty_alt_is_skipped() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tG,T\t.\t.\tCSQ=T|missense_variant\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+        record.alternative[0].clear();
+
+        let options = Options { class_field: None, ..Options::default() };
+        let mut skipped = Skipped::default();
+        let lines = process_record(&record, &HashMap::new(), &HashMap::new(), &options, &mut skipped).unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(skipped.bad_alt, 1);
+    }
+
+    #[test]
+    fn ties_are_broken_by_the_chosen_rule() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tT\t.\t.\tCSQ=T|upstream_gene_variant,T|intron_variant,T|downstream_gene_variant\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+
+        let severity = HashMap::from([
+            ("upstream_gene_variant".to_string(), 20),
+            ("intron_variant".to_string(), 20),
+            ("downstream_gene_variant".to_string(), 20)
+        ]);
+        let variant_groups = HashMap::from([
+            ("upstream_gene_variant".to_string(), 5),
+            ("intron_variant".to_string(), 3),
+            ("downstream_gene_variant".to_string(), 5)
+        ]);
+        let most_severe = |tie_break| {
+            let options = Options { tie_break, class_field: None, ..Options::default() };
+            let lines = process_record(&record, &severity, &variant_groups, &options, &mut Skipped::default()).unwrap();
+            (lines[0].severity.clone(), lines[0].group)
+        };
+        assert_eq!(most_severe(TieBreak::First), ("upstream_gene_variant".to_string(), 5));
+        assert_eq!(most_severe(TieBreak::Group), ("intron_variant".to_string(), 3));
+        assert_eq!(most_severe(TieBreak::Alpha), ("downstream_gene_variant".to_string(), 5));
+    }
+
+    #[test]
+    fn unranked_consequence_does_not_outrank_a_ranked_one() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+            1\t100\trs1\tA\tT\t.\t.\tCSQ=T|made_up_variant,T|downstream_gene_variant\n\
+            1\t200\trs2\tA\tT\t.\t.\tCSQ=T|made_up_variant\n";
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        let severity = HashMap::from([("downstream_gene_variant".to_string(), 33)]);
+        let options = Options { class_field: None, ..Options::default() };
+
+        let mut most_severe = Vec::new();
+        while reader.next_record(&mut record).unwrap() {
+            let lines = process_record(&record, &severity, &HashMap::new(), &options, &mut Skipped::default()).unwrap();
+            most_severe.push((lines[0].severity.clone(), lines[0].severity_rank));
+        }
+        assert_eq!(most_severe, [("downstream_gene_variant".to_string(), 33), ("made_up_variant".to_string(), UNRANKED)]);
+    }
+
+    // BED span and variety of the single line converted from a record with the built-in tables
+    fn converted(record: &str) -> (u64, u64, String, String) {
+        let vcf = format!("##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n{}\n", record);
+        let mut reader = VCFReader::new(vcf.as_bytes()).unwrap();
+        let mut record = reader.empty_record();
+        reader.next_record(&mut record).unwrap();
+
+        let severity = SEVERITY.iter().map(|(term, rank)| (term.to_string(), *rank)).collect();
+        let variant_groups = VARIANTGROUP.iter().map(|(term, group)| (term.to_string(), *group)).collect();
+        let options = Options { class_field: None, ..Options::default() };
+        let lines = record_to_lines(&record, &severity, &variant_groups, &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        (lines[0].start, lines[0].end, lines[0].variety.clone(), lines[0].severity.clone())
+    }
+
+    #[test]
+    fn a_record_of_each_variety_is_converted() {
+        let span = |start, end, variety: &str, severity: &str| (start, end, variety.to_string(), severity.to_string());
+        assert_eq!(converted("1\t100\trs1\tA\tT\t.\t.\tCSQ=T|missense_variant"), span(99, 100, "SNV", "missense_variant"));
+        assert_eq!(converted("1\t100\trs1\tA\tATT\t.\t.\tCSQ=TT|intron_variant"), span(100, 100, "insertion", "intron_variant"));
+        assert_eq!(converted("1\t100\trs1\tACG\tA\t.\t.\tCSQ=-|frameshift_variant"), span(99, 102, "deletion", "frameshift_variant"));
+        assert_eq!(converted("1\t100\trs1\tACG\tTT\t.\t.\tCSQ=TT|inframe_deletion"), span(99, 102, "indel", "inframe_deletion"));
+        assert_eq!(converted("1\t100\trs1\tAC\tGT\t.\t.\tCSQ=GT|missense_variant"), span(99, 101, "substitution", "missense_variant"));
+        assert_eq!(converted("1\t100\trs1\tA\tT,AGG\t.\t.\tCSQ=T|synonymous_variant,GG|intron_variant"),
+            span(99, 100, "sequence_alteration", "synonymous_variant"));
+        assert_eq!(converted("1\t100\trs1\tA\t<DEL>\t.\t.\tEND=200;CSQ=deletion|transcript_ablation"),
+            span(99, 200, "deletion", "transcript_ablation"));
+    }
+
+    fn spaced() -> Options {
+        Options { delimiter: " ".to_string(), ..Options::default() }
+    }
+
+    fn written(out: Vec<u8>) -> String {
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn compatible_lines_are_merged() {
+        let mut out = Vec::new();
+        let mut current = line("rs1", 99, "SNV", "G", "intron_variant", 30);
+        current.merge(Some(line("rs1", 99, "SNV", "T", "missense_variant", 13)), &spaced(), &mut out).unwrap();
+        assert!(out.is_empty());
+
+        current.merge(None, &spaced(), &mut out).unwrap();
+        assert_eq!(written(out), "1 99 100 rs1 SNV A G,T 3 missense_variant\n");
+    }
+
+    #[test]
+    fn merged_span_grows_and_never_shrinks() {
+        let spanning = |end: u64, alt: &str, severity: &str, severity_rank: u8| {
+            Line { end, ..line("rs1", 99, "deletion", alt, severity, severity_rank) }
+        };
+
+        // a later, less severe record with a larger end still widens the span
+        let mut out = Vec::new();
+        let mut current = spanning(102, "G", "missense_variant", 13);
+        current.merge(Some(spanning(105, "T", "intron_variant", 30)), &spaced(), &mut out).unwrap();
+        current.merge(None, &spaced(), &mut out).unwrap();
+        assert_eq!(written(out), "1 99 105 rs1 deletion A G,T 3 missense_variant\n");
+
+        // and a more severe one with a smaller end does not narrow it
+        let mut out = Vec::new();
+        let mut current = spanning(105, "G", "intron_variant", 30);
+        current.merge(Some(spanning(102, "T", "missense_variant", 13)), &spaced(), &mut out).unwrap();
+        current.merge(None, &spaced(), &mut out).unwrap();
+        assert_eq!(written(out), "1 99 105 rs1 deletion A G,T 3 missense_variant\n");
+    }
+
+    #[test]
+    fn redundant_line_is_skipped() {
+        let mut out = Vec::new();
+        let mut current = line("rs1", 99, "SNV", "G", "intron_variant", 30);
+        current.merge(Some(line("rs1", 99, "deletion", "-", "missense_variant", 13)), &spaced(), &mut out).unwrap();
+        current.merge(None, &spaced(), &mut out).unwrap();
+
+        assert_eq!(written(out), "1 99 100 rs1 SNV A G 3 intron_variant\n");
+    }
+
+    #[test]
+    fn last_variant_is_written_once_when_the_final_line_is_redundant() {
+        let mut out = Vec::new();
+        let mut lines = Merger::default();
+        for line in [
+            line("rs1", 99, "SNV", "G", "intron_variant", 30),
+            line("rs2", 199, "SNV", "T", "intron_variant", 30),
+            line("rs2", 199, "SNV", "C", "missense_variant", 13),
+            line("rs2", 199, "deletion", "-", "stop_gained", 3)
+        ] {
+            lines.add(line, &spaced(), &mut out).unwrap();
+        }
+        lines.finish(&spaced(), &mut out).unwrap();
+        lines.finish(&spaced(), &mut out).unwrap();
+
+        assert_eq!(written(out), "1 99 100 rs1 SNV A G 3 intron_variant\n1 199 200 rs2 SNV A C,T 3 missense_variant\n");
+    }
+
+    #[test]
+    fn incompatible_line_flushes_current() {
+        let mut out = Vec::new();
+        let mut current = Line::default();
+        current.merge(Some(line("rs1", 99, "SNV", "G", "intron_variant", 30)), &spaced(), &mut out).unwrap();
+        assert!(out.is_empty());
+
+        current.merge(Some(line("rs2", 199, "SNV", "T", "missense_variant", 13)), &spaced(), &mut out).unwrap();
+        assert_eq!(written(out.clone()), "1 99 100 rs1 SNV A G 3 intron_variant\n");
+
+        current.merge(None, &spaced(), &mut out).unwrap();
+        assert_eq!(written(out), "1 99 100 rs1 SNV A G 3 intron_variant\n1 199 200 rs2 SNV A T 3 missense_variant\n");
+    }
+
+    // a generated line as small indices - id, position, variety, alt (0 for none) and rank - so that a failing
+    // sequence shrinks by dropping lines and lowering indices towards the simplest line
+    type Spec = [u8; 5];
+
+    fn generated(spec: &Spec) -> Line {
+        let [id, position, variety, alt, rank] = *spec;
+        let mut line = line(&format!("rs{}", id % 3), 99 + 100 * (position % 2) as u64, ["SNV", "deletion"][variety as usize % 2],
+            ["", "C", "G", "T"][alt as usize % 4], "intron_variant", rank % 40);
+        if alt % 4 == 0 {
+            line.alts.clear();
+        }
+        line
+    }
+
+    // xorshift standing in for proptest, which is not available offline, so that the cases are the same on every run
+    fn sequences(cases: usize) -> Vec<Vec<Spec>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..cases).map(|_| {
+            let length = next() % 12;
+            (0..length).map(|_| std::array::from_fn(|_| (next() % 8) as u8)).collect()
+        }).collect()
+    }
+
+    // the broken invariant of the lines written when the sequence is merged one line after another, if any - the
+    // lines come in position order, as the records of a sorted VCF
+    fn broken_invariant(specs: &[Spec]) -> Option<String> {
+        let mut generated_lines = specs.iter().map(generated).collect::<Vec<_>>();
+        generated_lines.sort_by_key(|line| line.start);
+        let mut out = Vec::new();
+        let mut lines = Merger::default();
+        let mut previous: Option<Line> = None;
+        // variety of the first line of each id at each run of lines at one position, the variety its line is written with
+        let mut varieties = HashSet::new();
+        let mut seen = HashSet::new();
+        for line in generated_lines {
+            if previous.as_ref().is_some_and(|previous| previous.start != line.start) {
+                seen.clear();
+            }
+            if seen.insert(line.id.clone()) {
+                varieties.insert((line.id.clone(), line.start.to_string(), line.variety.clone()));
+            }
+            let written_before = out.len();
+            lines.add(line.clone(), &spaced(), &mut out).unwrap();
+            if previous.as_ref().is_some_and(|previous| previous.compatible(&line)) && out.len() > written_before {
+                return Some(format!("merging {} after a compatible line wrote {}", line.id, String::from_utf8_lossy(&out[written_before..])));
+            }
+            previous = Some(line);
+        }
+        lines.finish(&spaced(), &mut out).unwrap();
+
+        let written = written(out);
+        let lines = written.lines().map(|line| line.split(' ').collect::<Vec<_>>()).collect::<Vec<_>>();
+        if let Some(line) = lines.iter().find(|fields| fields[6].is_empty()) {
+            return Some(format!("line without alts written: {}", line.join(" ")));
+        }
+        let given = specs.iter().map(generated).flat_map(|line| line.alts.into_iter().map(move |alt| (line.id.clone(), alt))).collect::<HashSet<_>>();
+        if let Some(line) = lines.iter().find(|fields| fields[6].split(',').any(|alt| !given.contains(&(fields[3].to_string(), alt.to_string())))) {
+            return Some(format!("line written with an alt no line of its id had: {}", line.join(" ")));
+        }
+        if let Some(pair) = lines.windows(2).find(|pair| pair[0][3] == pair[1][3] && pair[0][1] == pair[1][1]) {
+            return Some(format!("consecutive lines of one id and position written: {} and {}", pair[0].join(" "), pair[1].join(" ")));
+        }
+        let redundant = |fields: &&Vec<&str>| !varieties.contains(&(fields[3].to_string(), fields[1].to_string(), fields[4].to_string()));
+        if let Some(line) = lines.iter().find(redundant) {
+            return Some(format!("line of another variety than the first of its id written: {}", line.join(" ")));
+        }
+        None
+    }
+
+    // the smallest sequence still breaking an invariant, by dropping lines and lowering their indices
+    fn shrunk(mut specs: Vec<Spec>) -> Vec<Spec> {
+        loop {
+            let smaller = (0..specs.len())
+                .map(|index| [&specs[..index], &specs[index + 1..]].concat())
+                .chain((0..specs.len() * 5).filter(|i| specs[i / 5][i % 5] > 0).map(|i| {
+                    let mut lowered = specs.clone();
+                    lowered[i / 5][i % 5] -= 1;
+                    lowered
+                }))
+                .find(|candidate| broken_invariant(candidate).is_some());
+            match smaller {
+                Some(smaller) => specs = smaller,
+                None => return specs
+            }
+        }
+    }
+
+    #[test]
+    fn merged_lines_keep_their_invariants() {
+        for specs in sequences(2000) {
+            if broken_invariant(&specs).is_some() {
+                let specs = shrunk(specs);
+                panic!("{} for {:?}", broken_invariant(&specs).unwrap(), specs);
+            }
+        }
+    }
+}
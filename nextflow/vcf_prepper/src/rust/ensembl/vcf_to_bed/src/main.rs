@@ -14,277 +14,768 @@
  * limitations under the License.
  */
  
-use std::{io::{BufReader,Write}, fs::File, env, collections::HashMap, collections::HashSet};
-use vcf::{VCFError, VCFReader};
-use flate2::read::MultiGzDecoder;
-
-const VARIANTGROUP : [(&str, u8); 45] = [
-    ("frameshift_variant", 1),
-    ("inframe_deletion", 1),
-    ("inframe_insertion", 1),
-    ("missense_variant", 1),
-    ("protein_altering_variant", 1),
-    ("start_lost", 1),
-    ("stop_gained", 1),
-    ("stop_lost", 1),
-    ("splice_acceptor_variant", 2),
-    ("splice_donor_5th_base_variant", 2),
-    ("splice_donor_region_variant", 2),
-    ("splice_donor_variant", 2),
-    ("splice_polypyrimidine_tract_variant", 2),
-    ("splice_region_variant", 2),
-    ("3_prime_UTR_variant", 3),
-    ("5_prime_UTR_variant", 3),
-    ("coding_sequence_variant", 3),
-    ("incomplete_terminal_codon_variant", 3),
-    ("intron_variant", 3),
-    ("mature_miRNA_variant", 3),
-    ("NMD_transcript_variant", 3),
-    ("non_coding_transcript_exon_variant", 3),
-    ("non_coding_transcript_variant", 3),
-    ("start_retained_variant", 3),
-    ("stop_retained_variant", 3),
-    ("synonymous_variant", 3),
-    ("feature_elongation", 3),
-    ("feature_truncation", 3),
-    ("transcript_ablation", 3),
-    ("transcript_amplification", 3),
-    ("transcript_fusion", 3),
-    ("transcript_translocation", 3),
-    ("regulatory_region_variant", 4),
-    ("TF_binding_site_variant", 4),
-    ("regulatory_region_ablation", 4),
-    ("regulatory_region_amplification", 4),
-    ("regulatory_region_fusion", 4),
-    ("regulatory_region_translocation", 4),
-    ("TFBS_ablation", 4),
-    ("TFBS_amplification", 4),
-    ("TFBS_fusion", 4),
-    ("TFBS_translocation", 4),
-    ("upstream_gene_variant", 5),
-    ("downstream_gene_variant", 5),
-    ("intergenic_variant", 5)
-];
-
-struct Line {
-    chromosome: String,
-    start: u64,
-    end: u64,
-    id: String,
-    variety: String,
-    reference: String,
-    alts: HashSet<String>,
-    group: u8,
-    severity: String,
-    severity_rank: u8
+use std::{io::{self, BufWriter, IsTerminal, Write}, fs::File, collections::{BTreeMap, HashMap}, process::ExitCode, time::Duration};
+use vcf::VCFReader;
+use flate2::{write::GzEncoder, Compression};
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use log::{error, warn};
+#[cfg(feature = "bigbed")]
+use vcf_to_bed::bigbed::BigBedWriter;
+use vcf_to_bed::{Annotator, ChrStyle, Converter, Error, ExtraColumn, Field, IdSource, InsertionSpan, LineSink, MissingId, NameTemplate, Options, OutputFormat, PastContigEnd, Region, TieBreak, GROUPS, GROUP_COLORS, IMPACT, SEVERITY, SO_ACCESSION, SeverityAs, VARIANTGROUP, csq_field_index, csq_format, known_terms, SitesOnly, open_vcfs, read_config, read_header, read_group_colors, read_severity, read_variant_groups, unranked_terms, bcf::is_bcf, binary, collapse::{Collapse, CollapseIds}, sort::{Ranking, Sorter, fai_order, read_chrom_sizes}, tabix::Index};
+
+/// Convert a VEP annotated VCF into a BED file of variants with their most severe consequence
+#[derive(Parser)]
+#[command(version, about, after_help = "Exit status is 0 on success, 1 on an error, 2 on a usage error and 3 when more \
+    records were skipped than --max-skip-fraction allows")]
+struct Args {
+    /// Input VCF or BCF file, plain or gzipped, - for stdin; can be given more than once, e.g. for the VCF of each
+    /// chromosome, to convert the inputs in order into one output
+    #[arg(short, long)]
+    input: Vec<String>,
+
+    /// Output BED file, - for stdout
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Gzip the output, implied by an output file name ending in .gz
+    #[arg(long)]
+    gzip: bool,
+
+    /// Format of the output lines
+    #[arg(long, value_enum, default_value_t = OutputFormat::Bed)]
+    output_format: OutputFormat,
+
+    /// Separator between the fields of a BED line, e.g. ' ' for the space separated output of older versions
+    #[arg(long, default_value = "\t")]
+    delimiter: String,
+
+    /// Format of the name column of a BED line, with placeholders {id}, {severity} and {variety}, e.g. {id}_{severity};
+    /// it cannot hold whitespace or the delimiter
+    #[arg(long, value_name = "TEMPLATE", default_value = "{id}")]
+    name_template: NameTemplate,
+
+    /// BED interval of an insertion after the anchor base at POS: empty is the 0-length POS..POS between the anchor
+    /// and the next base, anchor covers the anchor base and next the base after it, for browsers that do not draw
+    /// empty intervals
+    #[arg(long, value_enum, default_value = "empty")]
+    insertion_span: InsertionSpan,
+
+    /// JSON or tab separated file mapping consequence terms to severity rank, overriding the built-in Ensembl ranks
+    #[arg(short, long)]
+    severity: Option<String>,
+
+    /// Which of two consequences of the same severity rank is the most severe: the first seen, the one in the lower
+    /// variant group or the first alphabetically
+    #[arg(long, value_enum, default_value_t = TieBreak::First)]
+    tie_break: TieBreak,
+
+    /// Write the most severe consequence as its term or its Sequence Ontology accession
+    #[arg(long, value_enum, default_value_t = SeverityAs::Term)]
+    severity_as: SeverityAs,
+
+    /// JSON file mapping consequence terms to Sequence Ontology accessions, overriding or extending the built-in ones
+    #[arg(long, value_name = "FILE")]
+    so_accessions: Option<String>,
+
+    /// JSON file mapping consequence terms to the impacts of --extra-columns impact, overriding or extending the
+    /// built-in ones
+    #[arg(long, value_name = "FILE")]
+    impacts: Option<String>,
+
+    /// INFO key of the consequence annotations, e.g. ANN for SnpEff, whose entries have an Annotation field in place
+    /// of Consequence and no variant class, so that the class is worked out from the alleles
+    #[arg(long, value_name = "KEY", default_value = "CSQ")]
+    annotation_key: String,
+
+    /// Index of the Allele field within a CSQ entry, matched against the alts to pair entries with them [default: from
+    /// the VCF header, otherwise 0]
+    #[arg(long)]
+    allele_field: Option<usize>,
+
+    /// Index of the Consequence field within a CSQ entry [default: from the VCF header, otherwise 1]
+    #[arg(long)]
+    consequence_field: Option<usize>,
+
+    /// Index of the VARIANT_CLASS field within a CSQ entry [default: from the VCF header, otherwise 21]
+    #[arg(long)]
+    class_field: Option<usize>,
+
+    /// Separator between the consequence terms of a CSQ entry, e.g. , for a percent-encoded %2C
+    #[arg(long, default_value = "&", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    consequence_delimiter: String,
+
+    /// Keep lowercase bases of the alleles as they are instead of uppercasing them
+    #[arg(long)]
+    preserve_case: bool,
+
+    /// Skip variants with reference allele longer than this (e.g. 31 for older bedToBigBed), 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    max_ref_length: usize,
+
+    /// Skip variants with more alts than this, such as hyper-multiallelic sites, 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    max_alts: usize,
+
+    /// Fail with exit status 3 when more than this fraction of the records read, between 0 and 1, were skipped;
+    /// the output is still written
+    #[arg(long, value_name = "FRACTION")]
+    max_skip_fraction: Option<f64>,
+
+    /// Where the ids naming the lines come from, the ID column or info:TAG for an INFO tag such as info:RS, whose
+    /// numbers are prefixed with rs
+    #[arg(long, value_name = "SOURCE", default_value = "column")]
+    id_source: IdSource,
+
+    /// What happens to a record without an id in --id-source, such as one with an ID of .; by default it is skipped
+    /// and counted as no-id in the summary
+    #[arg(long, value_enum, default_value = "skip")]
+    missing_id: MissingId,
+
+    /// Skip the records without an id, as --missing-id skip
+    #[arg(long, conflicts_with_all = ["missing_id", "synthesize_id"])]
+    require_id: bool,
+
+    /// Name the records without an id chromosome_position_ref_alts, as --missing-id synthesize
+    #[arg(long, conflicts_with = "missing_id")]
+    synthesize_id: bool,
+
+    /// Convert the contigs of a bgzipped VCF (not BCF) with a tabix or CSI index in parallel using this many threads
+    #[arg(long, value_name = "THREADS")]
+    parallel: Option<usize>,
+
+    /// Work out the lines of the records on this many threads while the records are read and the lines written
+    /// in order, for any VCF
+    #[arg(long, default_value_t = 1, conflicts_with = "parallel")]
+    threads: usize,
+
+    /// Sort the output by chromosome, start and end instead of writing it in VCF order
+    #[arg(long)]
+    sort: bool,
+
+    /// Also keep only the most severe line of each id, as merge_bed does, in the same bounded memory as --sort;
+    /// implies --sort
+    #[arg(long)]
+    dedup: bool,
+
+    /// FASTA index (.fai) whose contig order --sort uses instead of byte order, as bedToBigBed expects
+    #[arg(long, value_name = "FAI")]
+    genome: Option<String>,
+
+    /// Number of lines --sort holds in memory before spilling sorted chunks to temporary files
+    #[arg(long, value_name = "LINES", default_value_t = 1_000_000)]
+    sort_buffer: usize,
+
+    /// Write the sorted lines as a BigBed to this file in place of the output, with the chromosome lengths of
+    /// --chrom-sizes; a build without the bigbed feature writes a BED next to it for bedToBigBed instead
+    #[arg(long, value_name = "OUT.bb", requires = "chrom_sizes", conflicts_with_all = ["output", "check", "split_by_group"])]
+    bigbed: Option<String>,
+
+    /// chrom.sizes file (or FASTA index) of the lengths of the chromosomes, named as in the output, for --bigbed and
+    /// to check that every line ends within its chromosome
+    #[arg(long, value_name = "FILE")]
+    chrom_sizes: Option<String>,
+
+    /// What happens to a line ending past the length of its chromosome in --chrom-sizes
+    #[arg(long, value_enum, default_value = "warn", requires = "chrom_sizes")]
+    past_contig_end: PastContigEnd,
+
+    /// Collapse runs of overlapping or touching lines of a chromosome with the same group and severity into one line
+    /// spanning them, with . for reference and alts; the lines need to be in position order, as from a sorted VCF or --sort.
+    /// With --split-by-group the runs are those of each file
+    #[arg(long)]
+    collapse_runs: bool,
+
+    /// What the id column of a line collapsed by --collapse-runs holds: the ids joined, the first or .
+    #[arg(long, value_enum, default_value_t = CollapseIds::Join, requires = "collapse_runs")]
+    collapse_ids: CollapseIds,
+
+    /// Write a line for each alt of a multiallelic record, with the most severe consequence of that alt
+    #[arg(long)]
+    split_alts: bool,
+
+    /// Write a line for each CSQ feature (transcript) of a variant, with the most severe consequence in that feature
+    /// and the feature as a column after the severity; . stands for the entries without a feature. merge_bed and
+    /// --dedup keep one line of each id, so they undo this
+    #[arg(long)]
+    per_transcript: bool,
+
+    /// Write the thickStart, thickEnd and itemRgb columns after the nine BED columns, colouring each line by its
+    /// variant group
+    #[arg(long)]
+    color_by_group: bool,
+
+    /// JSON or tab separated file mapping variant groups to R,G,B colours for --color-by-group, overriding the
+    /// built-in colours
+    #[arg(long, value_name = "FILE", requires = "color_by_group")]
+    group_colors: Option<String>,
+
+    /// Columns to write after the nine BED columns, e.g. af,clin_sig,length
+    #[arg(long, value_enum, value_delimiter = ',')]
+    extra_columns: Vec<ExtraColumn>,
+
+    /// CSQ subfield (if the header Format has it) or INFO tag the af column is read from, e.g. gnomADg_AF
+    #[arg(long, value_name = "NAME", default_value = "AF")]
+    af_field: String,
+
+    /// CSQ subfield (if the header Format has it) or INFO tag the clin_sig column is read from
+    #[arg(long, value_name = "NAME", default_value = "CLIN_SIG")]
+    clin_sig_field: String,
+
+    /// INFO tags to write as columns after the extra columns, e.g. RS,dbSNPBuildID; multiple values are
+    /// comma separated in the order of the VCF and a missing tag is written as .
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    info_passthrough: Vec<String>,
+
+    /// Only convert records of these contigs, comma separated names or patterns with * (e.g. chr*), or a file of one per line
+    #[arg(long, value_name = "CONTIGS", value_delimiter = ',')]
+    include_contigs: Vec<String>,
+
+    /// Do not convert records of these contigs (e.g. *_alt), as for --include-contigs; takes precedence over it
+    #[arg(long, value_name = "CONTIGS", value_delimiter = ',')]
+    exclude_contigs: Vec<String>,
+
+    /// Add (ucsc) or strip (ensembl) the chr prefix of chromosome names, writing the mitochondrion as chrM or MT
+    #[arg(long, value_enum)]
+    chr_style: Option<ChrStyle>,
+
+    /// Only convert records overlapping this region, chromosome:start-end (1-based, inclusive) or a whole chromosome, named as in the VCF;
+    /// can be given more than once. The tabix or CSI index of a bgzipped VCF is used to skip to the regions if there is one
+    #[arg(long)]
+    region: Vec<Region>,
+
+    /// Only write variants whose most severe consequence is in one of these variant groups, e.g. 1,2
+    #[arg(long, value_delimiter = ',')]
+    groups: Vec<u8>,
+
+    /// Only write variants whose most severe consequence is at least as severe as this rank of the severity table -
+    /// lower ranks are more severe, e.g. 14 keeps protein_altering_variant and the consequences ranked above it
+    #[arg(long, value_name = "RANK")]
+    min_severity_rank: Option<u8>,
+
+    /// Write the lines of each variant group to a file of its own, PREFIX1.bed to PREFIX5.bed (.jsonl for jsonl
+    /// output, .gz with --gzip) and another for any other group seen, in place of the output
+    #[arg(long, value_name = "PREFIX", conflicts_with_all = ["output", "check"])]
+    split_by_group: Option<String>,
+
+    /// JSON or tab separated file mapping consequence terms to variant groups, overriding or extending the built-in groups
+    #[arg(long, value_name = "FILE")]
+    variant_groups: Option<String>,
+
+    /// Leave out the FORMAT and sample columns of the VCF before parsing its records, as only INFO is converted;
+    /// false parses the genotypes of every record
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    ignore_samples: bool,
+
+    /// Match the consequence terms of the CSQ entries and of the severity and variant group files whatever their
+    /// case, lowercasing them but for the few built-in SO terms of another case such as 3_prime_UTR_variant;
+    /// false keeps their case
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    lowercase_consequences: bool,
+
+    /// Fail instead of warning when the severity file does not rank every consequence term with a variant group,
+    /// when a record of the VCF comes before the one it follows, or when a CSQ entry has more or fewer subfields than
+    /// the header Format describes
+    #[arg(long)]
+    strict: bool,
+
+    /// Only check that the input converts, reporting the skipped records, without writing any output;
+    /// fails if the VCF header does not define CSQ
+    #[arg(long)]
+    check: bool,
+
+    /// Print the index and name of each CSQ subfield described by the VCF header, as --consequence-field and
+    /// --class-field count them, and exit
+    #[arg(long)]
+    print_csq_format: bool,
+
+    /// Do not report the number of skipped records at the end of the run
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Report the records read, lines written and records per second to stderr this often, when stderr is a
+    /// terminal and not with --quiet or --parallel
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10")]
+    progress: Option<u64>,
+
+    /// Positional form of the above, kept for backward compatibility
+    #[arg(value_names = ["INPUT", "OUTPUT", "SEVERITY"], num_args = 0..=3)]
+    positional: Vec<String>,
 }
 
-impl Line {
-    fn compatible(&self, other: &Line) -> bool {
-        self.chromosome == other.chromosome &&
-        self.id == other.id &&
-        self.start == other.start &&
-        self.reference == other.reference &&
-        self.variety == other.variety
+impl Args {
+    // named options take precedence, positional values fill whatever is left in order
+    // the output is not needed with --check, --split-by-group, --bigbed or --print-csq-format
+    fn resolve(&self) -> (Vec<String>, Option<String>, Option<String>) {
+        let mut positional = self.positional.iter();
+        let mut take = |named: &Option<String>, name: &str| {
+            named.as_ref().or_else(|| positional.next()).cloned().unwrap_or_else(|| {
+                Args::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("the following required argument was not provided: --{}", name)
+                ).exit()
+            })
+        };
+
+        if (self.sort || self.dedup || self.bigbed.is_some()) && self.output_format != OutputFormat::Bed {
+            Args::command().error(ErrorKind::ArgumentConflict, "--sort only supports bed output").exit()
+        }
+        if self.collapse_runs && self.output_format != OutputFormat::Bed {
+            Args::command().error(ErrorKind::ArgumentConflict, "--collapse-runs only supports bed output").exit()
+        }
+        if self.split_by_group.is_some() && self.output_format == OutputFormat::Bin {
+            Args::command().error(ErrorKind::ArgumentConflict, "--split-by-group does not support bin output").exit()
+        }
+        if self.name_template.as_str().contains(self.delimiter.as_str()) {
+            Args::command().error(ErrorKind::ArgumentConflict, "--name-template cannot hold the delimiter").exit()
+        }
+        if self.genome.is_some() && !(self.sort || self.dedup) {
+            Args::command().error(ErrorKind::MissingRequiredArgument, "--genome needs --sort or --dedup").exit()
+        }
+
+        if self.max_skip_fraction.is_some_and(|fraction| !(0.0..=1.0).contains(&fraction)) {
+            Args::command().error(ErrorKind::InvalidValue, "--max-skip-fraction is between 0 and 1").exit()
+        }
+        if self.input.len() > 1 && (self.parallel.is_some() || !self.region.is_empty()) {
+            Args::command().error(ErrorKind::ArgumentConflict, "--parallel and --region read a single input").exit()
+        }
+
+        let inputs = if self.input.is_empty() { vec![take(&None, "input")] } else { self.input.clone() };
+        let output = if self.split_by_group.is_some() || self.bigbed.is_some() || self.print_csq_format {
+            None
+        } else if self.check {
+            self.output.as_ref().or_else(|| positional.next()).cloned()
+        } else {
+            Some(take(&self.output, "output"))
+        };
+        let severity = self.severity.as_ref().or_else(|| positional.next()).cloned();
+        (inputs, output, severity)
     }
-    
-    fn redundant(&self, other: &Line) -> bool {
-        self.id == other.id && 
-        self.variety != other.variety 
+}
+
+// the output, or a file per variant group - finish has to be called to complete the gzip streams
+enum Sink {
+    Output(Output),
+    Groups(GroupFiles)
+}
+
+impl Sink {
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Output(out) => out.finish(),
+            Sink::Groups(files) => files.finish()
+        }
     }
-    
-    fn merge(&mut self, mut more: Option<Line>, out: &mut File) {
-        // merge new line if not empty (and a Line instance)
-        if let Some(ref mut more) = more {
-            if self.compatible(more) {
-                self.alts.extend(more.alts.clone());
-                if more.severity_rank < self.severity_rank {
-                    if more.end > self.end {
-                        self.end = more.end;
-                        self.variety = more.variety.clone();
-                    }
-                    self.group = more.group;
-                    self.severity = more.severity.to_string();
-                    self.severity_rank = more.severity_rank;
-                }
-                return;
-            }
-            
-            // if somehow with same rs id we have different variety of variant we skip the later ones
-            if self.redundant(more) {
-                return
-            }
+}
+
+impl LineSink for Sink {
+    fn writer(&mut self, group: u8) -> io::Result<&mut dyn Write> {
+        match self {
+            Sink::Output(out) => Ok(out),
+            Sink::Groups(files) => Ok(files.file(group)?)
         }
-        
-        // if new Line is not compatible with the current one it is a new variant
-        // print out the current line
-        if self.alts.len() > 0 {
-            let alts = Vec::from_iter(self.alts.clone());
-            write!(out, "{} {} {} {} {} {} {} {} {}\n",
-                self.chromosome, self.start, self.end,
-                self.id, self.variety, self.reference,
-                alts.join(","), self.group, self.severity
-            ).unwrap();
+    }
+}
+
+// plain or gzipped output
+enum Output {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+    Collapsed(Box<Collapse<Output>>),
+    #[cfg(feature = "bigbed")]
+    BigBed(BigBedWriter)
+}
+
+impl Output {
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+            Output::Collapsed(collapse) => collapse.finish()?.finish(),
+            #[cfg(feature = "bigbed")]
+            Output::BigBed(bigbed) => bigbed.finish()
         }
-        
-        // make the new Line as the current one
-        if let Some(more) = more {
-            *self = more;
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Collapsed(collapse) => collapse.write(buf),
+            #[cfg(feature = "bigbed")]
+            Output::BigBed(bigbed) => bigbed.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Collapsed(collapse) => collapse.flush(),
+            #[cfg(feature = "bigbed")]
+            Output::BigBed(bigbed) => bigbed.flush()
         }
     }
 }
 
-fn main() -> Result<(), VCFError> {
-    // read cli arguments
-    let args = env::args().collect::<Vec<_>>();
-    let mut reader = VCFReader::new(BufReader::new(MultiGzDecoder::new(File::open(
-        &args[1]
-    )?)))?;
-    let mut out = File::create(&args[2]).unwrap();
-    let json = std::fs::read_to_string(&args[3]).unwrap();
-        
-    let severity = {
-        serde_json::from_str::<HashMap<String, String>>(&json).unwrap()
+// the output of --split-by-group, each line goes to the file of its group
+struct GroupFiles {
+    prefix: String,
+    extension: String,
+    gzip: bool,
+    delimiter: String,
+    collapse: Option<CollapseIds>,
+    files: BTreeMap<u8, Output>
+}
+
+impl GroupFiles {
+    // the files of the groups shown in the browser are created up front, so that a group without lines has an empty file
+    fn create(prefix: &str, format: OutputFormat, delimiter: &str, gzip: bool, collapse: Option<CollapseIds>) -> io::Result<Self> {
+        let extension = match format {
+            OutputFormat::Bed => "bed",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Bin => "bin"
+        };
+        let mut files = GroupFiles {
+            prefix: prefix.to_string(),
+            extension: if gzip { format!("{}.gz", extension) } else { extension.to_string() },
+            gzip,
+            delimiter: delimiter.to_string(),
+            collapse,
+            files: BTreeMap::new()
+        };
+        for group in GROUPS {
+            files.file(group)?;
+        }
+        Ok(files)
+    }
+
+    fn file(&mut self, group: u8) -> io::Result<&mut Output> {
+        if !self.files.contains_key(&group) {
+            let path = format!("{}{}.{}", self.prefix, group, self.extension);
+            let file = collapsed(create_output(&path, self.gzip)?, &self.delimiter, self.collapse);
+            self.files.insert(group, file);
+        }
+        Ok(self.files.get_mut(&group).expect("file of the group was just created"))
+    }
+
+    fn finish(self) -> io::Result<()> {
+        for (_, file) in self.files {
+            file.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// the output collapsing runs of lines with --collapse-runs
+fn collapsed(out: Output, delimiter: &str, collapse: Option<CollapseIds>) -> Output {
+    match collapse {
+        Some(ids) => Output::Collapsed(Box::new(Collapse::new(out, delimiter, ids))),
+        None => out
+    }
+}
+
+// output goes to stdout if the path is -
+// the BigBed of --bigbed, or the BED to convert into it in a build without the bigbed feature
+#[cfg(feature = "bigbed")]
+fn create_bigbed(path: &str, chrom_sizes: &str, delimiter: &str) -> Result<Output, Error> {
+    let sizes = read_chrom_sizes(chrom_sizes).map_err(|source| Error::Open { path: chrom_sizes.to_string(), source })?;
+    BigBedWriter::create(path, sizes, delimiter).map(Output::BigBed).map_err(|source| Error::Open { path: path.to_string(), source })
+}
+
+#[cfg(not(feature = "bigbed"))]
+fn create_bigbed(path: &str, chrom_sizes: &str, _delimiter: &str) -> Result<Output, Error> {
+    let bed = format!("{}.bed", path.strip_suffix(".bb").unwrap_or(path));
+    warn!("built without the bigbed feature, writing the sorted lines to {} - convert it with bedToBigBed {} {} {}", bed, bed, chrom_sizes, path);
+    create_output(&bed, false).map_err(|source| Error::Open { path: bed.clone(), source })
+}
+
+fn create_output(path: &str, gzip: bool) -> io::Result<Output> {
+    let writer: Box<dyn Write> = if path == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(path)?)
     };
-    
-    // create the severity hash
+
+    let writer = BufWriter::new(writer);
+    if gzip || path.ends_with(".gz") {
+        Ok(Output::Gzip(GzEncoder::new(writer, Compression::default())))
+    } else {
+        Ok(Output::Plain(writer))
+    }
+}
+
+// contig names or patterns, a value naming a file is replaced by the lines of the file
+fn contig_list(values: &[String]) -> Result<Vec<String>, Error> {
+    let mut contigs = Vec::new();
+    for value in values {
+        if !std::path::Path::new(value).is_file() {
+            contigs.push(value.clone());
+            continue;
+        }
+
+        let content = std::fs::read_to_string(value).map_err(|source| Error::Open { path: value.clone(), source })?;
+        contigs.extend(content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(str::to_string));
+    }
+    Ok(contigs)
+}
+
+// exit status of a run that converted its input but skipped more records than --max-skip-fraction allows
+const TOO_MANY_SKIPPED: u8 = 3;
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ Error::TooManySkipped { .. }) => {
+            error!("{}", e);
+            ExitCode::from(TOO_MANY_SKIPPED)
+        },
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let (inputs, output, severity_file) = args.resolve();
+    // the inputs are read as one VCF, named by all of their paths
+    let input = inputs.join(", ");
+
+    let vcf_error = |source| Error::Vcf { path: input.clone(), source };
+    let mut input_file = open_vcfs(&inputs)?;
+    if args.ignore_samples {
+        input_file = Box::new(SitesOnly::new(input_file));
+    }
+    let mut reader = VCFReader::new(input_file).map_err(vcf_error)?;
+
+    let annotator = Annotator::for_key(&args.annotation_key);
+    let no_csq_header = reader.header().info(args.annotation_key.as_bytes()).is_none();
+    let format = csq_format(reader.header(), &args.annotation_key);
+    for path in &inputs[1..] {
+        let header = read_header(path).map_err(|source| Error::Open { path: path.clone(), source })?;
+        let header = VCFReader::new(header.as_slice()).map_err(|source| Error::Vcf { path: path.clone(), source })?;
+        if csq_format(header.header(), &args.annotation_key) != format {
+            return Err(Error::FormatDiffers { path: path.clone(), first: inputs[0].clone(), key: args.annotation_key.clone() });
+        }
+    }
+    if args.print_csq_format {
+        let format = format.ok_or_else(|| Error::NoCsqFormat { path: input.clone(), key: args.annotation_key.clone() })?;
+        let mut fields = format.into_iter().collect::<Vec<_>>();
+        fields.sort_by_key(|(_, index)| *index);
+        let mut stdout = io::stdout().lock();
+        for (name, index) in fields {
+            writeln!(stdout, "{}\t{}", index, name)?;
+        }
+        return Ok(());
+    }
+    if format.is_none() && (args.allele_field.is_none() || args.consequence_field.is_none() || args.class_field.is_none()) {
+        warn!("VCF header does not describe {} Format, using default field indices", args.annotation_key);
+    }
+    let mut accessions = SO_ACCESSION.iter()
+        .map(|(term, accession)| (term.to_string(), accession.to_string()))
+        .collect::<HashMap<_, _>>();
+    if let Some(accession_file) = &args.so_accessions {
+        let json = read_config(accession_file)
+            .map_err(|source| Error::Open { path: accession_file.clone(), source })?;
+        accessions.extend(serde_json::from_str::<HashMap<String, String>>(&json)
+            .map_err(|source| Error::Accessions { path: accession_file.clone(), source })?);
+    }
+    let mut impacts = IMPACT.iter()
+        .map(|(term, impact)| (term.to_string(), impact.to_string()))
+        .collect::<HashMap<_, _>>();
+    if let Some(impact_file) = &args.impacts {
+        let json = read_config(impact_file)
+            .map_err(|source| Error::Open { path: impact_file.clone(), source })?;
+        impacts.extend(serde_json::from_str::<HashMap<String, String>>(&json)
+            .map_err(|source| Error::Impacts { path: impact_file.clone(), source })?);
+    }
+    let options = Options {
+        annotation_key: args.annotation_key.clone(),
+        annotator,
+        allele_field: csq_field_index(args.allele_field, &format, "Allele", 0),
+        consequence_field: csq_field_index(args.consequence_field, &format, annotator.consequence_name(), 1),
+        class_field: match annotator.class_index() {
+            Some(default) => Some(csq_field_index(args.class_field, &format, "VARIANT_CLASS", default)),
+            None => args.class_field
+        },
+        consequence_delimiter: args.consequence_delimiter.clone(),
+        ignore_samples: args.ignore_samples,
+        lowercase_consequences: args.lowercase_consequences,
+        known_terms: known_terms(),
+        strict: args.strict,
+        csq_width: format.as_ref().and_then(|format| format.values().max()).map(|last| last + 1),
+        preserve_case: args.preserve_case,
+        max_ref_length: args.max_ref_length,
+        max_alts: args.max_alts,
+        id_source: args.id_source.clone(),
+        missing_id: match (args.require_id, args.synthesize_id) {
+            (true, _) => MissingId::Skip,
+            (_, true) => MissingId::Synthesize,
+            _ => args.missing_id
+        },
+        delimiter: args.delimiter.clone(),
+        name_template: args.name_template.clone(),
+        insertion_span: args.insertion_span,
+        format: args.output_format,
+        groups: args.groups.clone(),
+        min_severity_rank: args.min_severity_rank,
+        include_contigs: contig_list(&args.include_contigs)?,
+        exclude_contigs: contig_list(&args.exclude_contigs)?,
+        chr_style: args.chr_style,
+        regions: args.region.clone(),
+        contig_lengths: match &args.chrom_sizes {
+            Some(path) => read_chrom_sizes(path)
+                .map_err(|source| Error::Open { path: path.clone(), source })?
+                .into_iter()
+                .map(|(name, length)| (name, length as u64))
+                .collect(),
+            None => HashMap::new()
+        },
+        past_contig_end: args.past_contig_end,
+        split_alts: args.split_alts,
+        feature_field: args.per_transcript.then(|| csq_field_index(None, &format, "Feature", 6)),
+        group_colors: match (args.color_by_group, &args.group_colors) {
+            (false, _) => None,
+            (true, file) => {
+                let mut colors = GROUP_COLORS.iter()
+                    .map(|(group, color)| (*group, color.to_string()))
+                    .collect::<HashMap<_, _>>();
+                if let Some(file) = file {
+                    colors.extend(read_group_colors(file)?);
+                }
+                Some(colors)
+            }
+        },
+        extra_columns: args.extra_columns.clone(),
+        tie_break: args.tie_break,
+        severity_as: args.severity_as,
+        accessions,
+        impacts,
+        af_field: Field::named(&args.af_field, &format),
+        clin_sig_field: Field::named(&args.clin_sig_field, &format),
+        info_passthrough: args.info_passthrough.clone(),
+        progress: args.progress
+            .filter(|_| !args.quiet && io::stderr().is_terminal())
+            .map(Duration::from_secs)
+    };
+        
+    // create the variant group hash
     let mut variant_groups = HashMap::new();
     for (csq, value) in &VARIANTGROUP {
         variant_groups.insert(csq.to_string(), *value);
     }
+    if let Some(groups_file) = &args.variant_groups {
+        let groups = read_variant_groups(groups_file)?;
+        variant_groups.extend(groups.into_iter().map(|(term, group)| (options.lookup_term(term), group)));
+    }
     
-    let mut record = reader.empty_record();
-    // dummy initial value for the object to read line from vcf
-    // this line is guranteed to not get printed as alt.len == 0
-    let mut lines = Line {
-        chromosome: "".to_string(),
-        start: 1,
-        end: 0,
-        id: "".to_string(),
-        variety: "".to_string(),
-        reference: "".to_string(),
-        alts: HashSet::new(),
-        group: 0,
-        severity: "".to_string(),
-        severity_rank: 255
-    };
-    while reader.next_record(&mut record)? {
-        let reference = String::from_utf8(record.reference.clone()).unwrap();
-        let ref_len = reference.len() as u64;
-        
-        let mut multiple_ids = false;
-        let ids = record.id.iter().map(|b| {
-            String::from_utf8(b.clone())
-        }).collect::<Result<Vec<_>,_>>().unwrap();
-        // for now - we assume a variant cannot have mutliple ids
-        for id in ids.iter() {
-            if id.contains(";") { multiple_ids = true; }
-        }
-        if multiple_ids { continue; }
-        
-        let alts = record.alternative.iter().map(|a| {
-            String::from_utf8(a.clone())
-        }).collect::<Result<HashSet<_>,_>>().unwrap();
-        
-        let csq = record.info(b"CSQ").map(|csqs| {
-            csqs.iter().map(|csq| {
-                let s = String::from_utf8_lossy(csq);
-                s.split("|").nth(1).unwrap_or("").to_string()
-            }).collect::<Vec<String>>()
-        }).unwrap_or(vec![]);
-        // if csq is empty we won't have most severe consequence
-        if csq.is_empty(){ continue; }
-        
-        let class = record.info(b"CSQ").map(|csqs| {
-            csqs.iter().map(|csq| {
-                let s = String::from_utf8_lossy(csq);
-                s.split("|").nth(21).unwrap_or("").to_string()
-            }).collect::<Vec<String>>()
-        }).unwrap_or(vec![]);
-        
-        for id in ids.iter() {
-            let mut variant_group = 0;
-            let mut most_severe_csq = "";
-            let mut most_severe_csq_rank = 255;
-            
-            // calculate most severe consequence and variant group of that consequence
-            for csq_str in csq.iter() {
-                for csq_here in csq_str.split("&") {
-                    let csq_rank_here = (*severity.get(csq_here).unwrap_or(&String::from("0"))).parse::<u8>().unwrap();
-                    if csq_rank_here < most_severe_csq_rank {
-                        variant_group = *variant_groups.get(csq_here).unwrap_or(&0);
-                        most_severe_csq = csq_here;
-                        most_severe_csq_rank = csq_rank_here;
-                    }
-                }
-            }
-            
-            // calcualte variant class - we store it as variety
-            // variety should always be same for each variant allele - VEP puts variant class at variant level (using Bio::EnsEMBL::Variation::Utils::Sequence::SO_variation_class)
-            // if cannot be deduced the default value is - sequence_alteration
-            let mut variety = class[0].to_string();
-            
-            // if sequence_alteration we check if we can convert it to indel (the condition is that all the variant allele is eiter insertion or deletion or indel)
-            if variety.eq(&String::from("sequence_alteration")) {
-                let mut convert_sequence_alteration = true;
-                for alt in alts.iter() {
-                    // note that we are not minimilizing the variant alleles here 
-                    let calc_variety = match (alt.len()<2, reference.len()<2, alt.len() == reference.len()) {
-                        (true, true, true) => { "SNV" },
-                        (true, false, false) => { "deletion" },
-                        (false, true, false) => { "insertion" },
-                        (false, false, false) => { "indel" },
-                        (false, false, true) => { "substitute" },
-                        _ => todo!(),
-                    };
-                    
-                    // if any of the variant allele is SNV or substitution we will log (because this is not a regular case)
-                    // and, keep the variety as sequence_alteration
-                    if calc_variety.eq(&String::from("SNV")) || calc_variety.eq(&String::from("substitute")) {
-                        println!("[WARNING] sequence_alteration variant ({0} {1}:{2}) contain variant allele of type {3}",
-                            id, 
-                            String::from_utf8(record.chromosome.to_vec()).unwrap(), 
-                            record.position,
-                            calc_variety
-                        );
-                
-                        convert_sequence_alteration = false;
-                        break;
-                    }
-                }
-                
-                if convert_sequence_alteration {
-                    variety = "indel".to_string();
-                }
-            }
+    // built-in ranks, with those of the severity file taking precedence
+    let mut severity = SEVERITY.iter()
+        .map(|(csq, rank)| (csq.to_string(), *rank))
+        .collect::<HashMap<_, _>>();
+    if let Some(severity_file) = &severity_file {
+        let ranks = read_severity(severity_file)?.into_iter()
+            .map(|(term, rank)| (options.lookup_term(term), rank))
+            .collect();
 
-            // start position in bed is 0-indexed
-            let mut start = record.position - 1;
-        
-            // end in bed is exclusive
-            let mut end = start + ref_len;
-            if variety.eq(&String::from("insertion")) {
-                start += 1;
-                end = start;
+        // a term the file misses gets its built-in rank, which may not fit the scale of the file
+        let unranked = unranked_terms(&ranks, &variant_groups);
+        if !unranked.is_empty() {
+            if args.strict {
+                return Err(Error::Unranked { path: severity_file.clone(), terms: unranked.join(", ") });
             }
-            
-            let more = Line {
-                chromosome: String::from_utf8(record.chromosome.to_vec()).unwrap(),
-                start: start,
-                end: end,
-                id: id.to_string(),
-                variety: variety,
-                reference: reference.clone(),
-                alts: alts.clone(),
-                group: variant_group,
-                severity: most_severe_csq.to_string(),
-                severity_rank: most_severe_csq_rank
-            };
-            
-            lines.merge(Some(more), &mut out);
+            warn!("severity file {} does not rank {}", severity_file, unranked.join(", "));
         }
+        severity.extend(ranks);
     }
+    let collapse = args.collapse_runs.then_some(args.collapse_ids);
+    let mut out = match (&args.split_by_group, output.as_ref().filter(|_| !args.check)) {
+        (None, None) if args.bigbed.is_some() => {
+            let chrom_sizes = args.chrom_sizes.as_deref().expect("--bigbed requires --chrom-sizes");
+            let bigbed = create_bigbed(args.bigbed.as_deref().unwrap(), chrom_sizes, &args.delimiter)?;
+            Some(Sink::Output(collapsed(bigbed, &args.delimiter, collapse)))
+        },
+        (Some(prefix), _) => Some(Sink::Groups(GroupFiles::create(prefix, args.output_format, &args.delimiter, args.gzip, collapse)
+            .map_err(|source| Error::Open { path: prefix.clone(), source })?)),
+        (None, Some(output)) => {
+            let out = create_output(output, args.gzip).map_err(|source| Error::Open { path: output.clone(), source })?;
+            Some(Sink::Output(collapsed(out, &args.delimiter, collapse)))
+        },
+        (None, None) => None
+    };
     
-    lines.merge(None, &mut out);
+    // --dedup ranks the severity column as it is written
+    let ranks = severity.iter()
+        .map(|(term, rank)| (term.clone(), *rank))
+        .map(|(term, rank)| match args.severity_as {
+            SeverityAs::Term => (term, rank),
+            SeverityAs::Accession => (options.accessions.get(&term).cloned().unwrap_or(term), rank)
+        })
+        .collect::<HashMap<_, _>>();
+    let contig_order = match &args.genome {
+        Some(genome) => fai_order(genome).map_err(|source| Error::Open { path: genome.clone(), source })?,
+        None => HashMap::new()
+    };
+    let converter = Converter { severity, variant_groups, options };
+    let mut sorter = ((args.sort || args.dedup || args.bigbed.is_some()) && out.is_some()).then(|| {
+        let sorter = Sorter::new(&args.delimiter, args.sort_buffer).contig_order(contig_order);
+        if args.dedup { sorter.dedup(Ranking::new(ranks)) } else { sorter }
+    });
+    let (mut grouped, mut sink);
+    let writer: &mut dyn LineSink = match (&mut sorter, &mut out) {
+        (Some(sorter), _) => {
+            grouped = sorter.grouped();
+            &mut grouped
+        },
+        (None, Some(out)) => out,
+        (None, None) => {
+            sink = io::sink();
+            &mut sink
+        }
+    };
+    // bin output is not split by group, it goes to the one output
+    if args.output_format == OutputFormat::Bin {
+        writer.writer(0)?.write_all(&binary::MAGIC)?;
+    }
+    // the index of a BCF numbers its contigs rather than naming them, so the regions of a BCF are found by reading it
+    let bcf = input != "-" && (args.parallel.is_some() || !args.region.is_empty())
+        && is_bcf(&input).map_err(|source| Error::Open { path: input.clone(), source })?;
+    if bcf && args.parallel.is_some() {
+        return Err(Error::BcfParallel { path: input });
+    }
+    let index = if args.region.is_empty() || args.parallel.is_some() || bcf {
+        None
+    } else {
+        Index::for_vcf(&input).map_err(|source| Error::Index { path: input.clone(), source })?
+    };
+    let skipped = match (args.parallel, index) {
+        (Some(threads), _) => converter.convert_parallel(&input, threads, writer)?,
+        (None, Some(index)) => converter.convert_regions(&input, &index, writer)?,
+        (None, None) => converter.convert_threaded(&mut reader, &input, args.threads, writer)?
+    };
+    if let Some(mut out) = out {
+        if let Some(sorter) = sorter {
+            sorter.finish(&mut out)?;
+        }
+        out.finish()?;
+    }
+
+    if !args.quiet {
+        eprintln!("[INFO] {}", skipped);
+    }
+    if !skipped.unranked.is_empty() {
+        let terms = skipped.unranked.iter().map(String::as_str).collect::<Vec<_>>().join(", ");
+        warn!("consequence terms not in the severity ranks, ranked least severe: {}", terms);
+    }
+    if args.check && no_csq_header {
+        return Err(Error::NoCsqHeader { path: input, key: args.annotation_key });
+    }
+    if let Some(max) = args.max_skip_fraction.filter(|max| skipped.fraction() > *max) {
+        return Err(Error::TooManySkipped { skipped: skipped.total(), records: skipped.records, max });
+    }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,129 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Progress of a conversion, reported every few seconds
+//!
+//! The reporter counts the records read and the lines written, the latter as the lines ask for their writer,
+//! and writes a line with both and the rate of records per second since the start whenever
+//! the interval has passed. The clock is only read once every [`CHECK_EVERY`] records.
+
+use std::{cell::Cell, io::{self, Write}, rc::Rc, time::{Duration, Instant}};
+use crate::LineSink;
+
+/// Records read between two looks at the clock
+pub const CHECK_EVERY: u64 = 1024;
+
+pub struct Progress {
+    every: Duration,
+    started: Instant,
+    last: Instant,
+    records: u64,
+    lines: Rc<Cell<u64>>,
+    report: Box<dyn Write>
+}
+
+impl Progress {
+    /// Reporter writing to report once every interval
+    pub fn new(every: Duration, report: Box<dyn Write>) -> Self {
+        let now = Instant::now();
+        Progress { every, started: now, last: now, records: 0, lines: Rc::default(), report }
+    }
+
+    /// Count a record read, reporting if the interval has passed since the last report
+    pub fn record(&mut self) -> io::Result<()> {
+        self.records += 1;
+        if !self.records.is_multiple_of(CHECK_EVERY) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.last) < self.every {
+            return Ok(());
+        }
+        self.last = now;
+        let seconds = now.duration_since(self.started).as_secs_f64();
+        let rate = if seconds > 0.0 { self.records as f64 / seconds } else { 0.0 };
+        writeln!(self.report, "[INFO] {} records read, {} lines written, {:.0} records/s", self.records, self.lines.get(), rate)
+    }
+
+    /// Sink counting the lines written through it to out
+    pub fn lines<'a>(&self, out: &'a mut dyn LineSink) -> Lines<'a> {
+        Lines { out, lines: self.lines.clone() }
+    }
+}
+
+/// Sink passing the lines on, see [`Progress::lines`]
+pub struct Lines<'a> {
+    out: &'a mut dyn LineSink,
+    lines: Rc<Cell<u64>>
+}
+
+impl LineSink for Lines<'_> {
+    fn writer(&mut self, group: u8) -> io::Result<&mut dyn Write> {
+        self.lines.set(self.lines.get() + 1);
+        self.out.writer(group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // report kept for the test to read
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_and_lines_are_reported() {
+        let report = Shared::default();
+        let mut progress = Progress::new(Duration::ZERO, Box::new(report.clone()));
+        let mut out = Vec::new();
+        for _ in 0..CHECK_EVERY * 2 {
+            writeln!(progress.lines(&mut out).writer(3).unwrap(), "1\t99\t100").unwrap();
+            progress.record().unwrap();
+        }
+
+        let report = String::from_utf8(report.0.lock().unwrap().clone()).unwrap();
+        let reports = report.lines().collect::<Vec<_>>();
+        assert_eq!(reports.len(), 2);
+        assert!(reports[1].starts_with("[INFO] 2048 records read, 2048 lines written, "), "{}", report);
+        assert!(reports[1].ends_with(" records/s"), "{}", report);
+        assert_eq!(out.len(), 2048 * 9);
+    }
+
+    #[test]
+    fn nothing_is_reported_within_the_interval() {
+        let report = Shared::default();
+        let mut progress = Progress::new(Duration::from_secs(3600), Box::new(report.clone()));
+        for _ in 0..CHECK_EVERY * 2 {
+            progress.record().unwrap();
+        }
+
+        assert!(report.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Sorting of BED lines by (chromosome, start, end)
+//!
+//! Lines written to the sorter are kept in memory until there are more than fit in a chunk,
+//! then the chunk is sorted and spilled to a temporary file. Finishing merges the spilled chunks
+//! with whatever is left in memory. Chromosomes are compared by bytes and coordinates as numbers,
+//! the same order as `LC_ALL=C sort -k1,1 -k2,2n -k3,3n`; lines with equal keys keep their input order.
+//! Given a contig order, such as that of a FASTA index, chromosomes are sorted in that order instead and
+//! those it does not list come after, in byte order. The variant group of each line written through [`Grouped`]
+//! is kept with it, so that the sorted lines go on to the writer of their group.
+//!
+//! A deduplicating sorter also keeps only the most severe line of each id by the [`Ranking`] merge_bed uses. The
+//! sorted lines are numbered and sorted again by id, rank and number to pick the line of each id, and the picked
+//! lines are sorted back by their number, so that memory stays bounded by the chunk size throughout.
+
+use std::{io::{self, BufRead, BufReader, BufWriter, Lines, Seek, Write}, fs::File, collections::HashMap};
+use crate::LineSink;
+
+type Key = (usize, String, u64, u64);
+
+pub struct Sorter {
+    delimiter: String,
+    chunk_size: usize,
+    lines: Vec<(Key, u8, String)>,
+    chunks: Vec<File>,
+    // bytes written after the last complete line, and the group of the line
+    partial: Vec<u8>,
+    group: u8,
+    // ranking of the lines if only the most severe line of each id is kept
+    dedup: Option<Ranking>,
+    // position of each chromosome in the sort order, byte order if empty
+    contig_order: HashMap<String, usize>
+}
+
+// a sorted chunk read back from its temporary file, holding its next line - the lines of the file are each
+// after their group and a tab
+struct Chunk {
+    lines: Lines<BufReader<File>>,
+    current: Option<(Key, u8, String)>
+}
+
+impl Chunk {
+    fn advance(&mut self, delimiter: &str, contig_order: &HashMap<String, usize>) -> io::Result<()> {
+        let Some(line) = self.lines.next().transpose()? else {
+            self.current = None;
+            return Ok(());
+        };
+        let (group, line) = line.split_once('\t').and_then(|(group, line)| Some((group.parse().ok()?, line)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("cannot read sorted chunk line: {}", line)))?;
+        self.current = Some((key(line, delimiter, contig_order)?, group, line.to_string()));
+        Ok(())
+    }
+}
+
+fn key(line: &str, delimiter: &str, contig_order: &HashMap<String, usize>) -> io::Result<Key> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("cannot sort BED line: {}", line));
+    let mut fields = line.split(delimiter);
+    let chromosome = fields.next().ok_or_else(invalid)?.to_string();
+    let start = fields.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+    let end = fields.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+    let rank = contig_order.get(&chromosome).copied().unwrap_or(usize::MAX);
+    Ok((rank, chromosome, start, end))
+}
+
+/// How severe a BED line is, lower being more severe: the rank of its consequence column, then its variant group
+///
+/// Consequences the ranks leave out are the least severe. Lines of the same rank are compared by variant group as
+/// [`TieBreak::Group`](crate::TieBreak::Group) compares terms, group 0, no group, coming after the others.
+#[derive(Clone)]
+pub struct Ranking {
+    ranks: HashMap<String, u8>
+}
+
+impl Ranking {
+    /// Ranking by the severity rank of each consequence term
+    pub fn new(ranks: HashMap<String, u8>) -> Self {
+        Ranking { ranks }
+    }
+
+    /// Rank of the fields of a line, none if it has no consequence column
+    pub fn rank(&self, fields: &[&str]) -> Option<(u8, u8)> {
+        let rank = self.ranks.get(*fields.get(8)?).copied().unwrap_or(u8::MAX);
+        let group = fields[7].parse::<u8>().map_or(u8::MAX, |group| group.wrapping_sub(1));
+        Some((rank, group))
+    }
+}
+
+/// Order of the contigs of a FASTA index (.fai), the first column of its lines
+pub fn fai_order(path: &str) -> io::Result<HashMap<String, usize>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content.lines()
+        .filter_map(|line| line.split('\t').next().filter(|name| !name.is_empty()))
+        .enumerate()
+        .map(|(index, name)| (name.to_string(), index))
+        .collect())
+}
+
+/// Lengths of the contigs of a chrom.sizes file of name and length separated by a tab, or of a FASTA index (.fai)
+pub fn read_chrom_sizes(path: &str) -> io::Result<HashMap<String, u32>> {
+    std::fs::read_to_string(path)?.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next().unwrap_or_default();
+            let size = fields.next().and_then(|size| size.parse::<u32>().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("expected a name and a length separated by a tab: {}", line)))?;
+            Ok((name.to_string(), size))
+        })
+        .collect()
+}
+
+impl Sorter {
+    /// Sorter holding at most chunk_size lines in memory
+    pub fn new(delimiter: &str, chunk_size: usize) -> Self {
+        Sorter {
+            delimiter: delimiter.to_string(),
+            chunk_size: chunk_size.max(1),
+            lines: Vec::new(),
+            chunks: Vec::new(),
+            partial: Vec::new(),
+            group: 0,
+            dedup: None,
+            contig_order: HashMap::new()
+        }
+    }
+
+    /// Sort chromosomes by their position in the order, e.g. from [`fai_order`]
+    pub fn contig_order(mut self, contig_order: HashMap<String, usize>) -> Self {
+        self.contig_order = contig_order;
+        self
+    }
+
+    /// Keep only the most severe line of each id by the ranking, on a tie the line sorted first
+    pub fn dedup(mut self, ranking: Ranking) -> Self {
+        self.dedup = Some(ranking);
+        self
+    }
+
+    /// Sink writing to the sorter that keeps the group of each line, for [`Sorter::finish`] to write the line to
+    pub fn grouped(&mut self) -> Grouped<'_> {
+        Grouped(self)
+    }
+
+    fn push(&mut self, line: String) -> io::Result<()> {
+        self.lines.push((key(&line, &self.delimiter, &self.contig_order)?, self.group, line));
+        if self.lines.len() >= self.chunk_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.lines.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut chunk = BufWriter::new(tempfile::tempfile()?);
+        for (_, group, line) in self.lines.drain(..) {
+            writeln!(chunk, "{}\t{}", group, line)?;
+        }
+        let mut chunk = chunk.into_inner().map_err(|e| e.into_error())?;
+        chunk.rewind()?;
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Write all the lines in order, each to the writer of its group
+    pub fn finish(self, out: &mut dyn LineSink) -> io::Result<()> {
+        self.finish_with(&mut |group, line| writeln!(out.writer(group)?, "{}", line))
+    }
+
+    fn finish_with(mut self, write: &mut dyn FnMut(u8, &str) -> io::Result<()>) -> io::Result<()> {
+        if let Some(ranking) = self.dedup.take() {
+            return self.finish_dedup(&ranking, write);
+        }
+        if !self.partial.is_empty() {
+            let line = String::from_utf8(std::mem::take(&mut self.partial))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.push(line)?;
+        }
+
+        if self.chunks.is_empty() {
+            self.lines.sort_by(|a, b| a.0.cmp(&b.0));
+            for (_, group, line) in &self.lines {
+                write(*group, line)?;
+            }
+            return Ok(());
+        }
+
+        if !self.lines.is_empty() {
+            self.spill()?;
+        }
+        let mut chunks = Vec::new();
+        for file in self.chunks {
+            let mut chunk = Chunk { lines: BufReader::new(file).lines(), current: None };
+            chunk.advance(&self.delimiter, &self.contig_order)?;
+            chunks.push(chunk);
+        }
+
+        loop {
+            // smallest key among the chunks - on ties the earlier chunk wins to keep the input order
+            let next = chunks.iter_mut()
+                .filter(|chunk| chunk.current.is_some())
+                .min_by(|a, b| a.current.as_ref().map(|c| &c.0).cmp(&b.current.as_ref().map(|c| &c.0)));
+            let Some(chunk) = next else { break };
+
+            if let Some((_, group, line)) = &chunk.current {
+                write(*group, line)?;
+            }
+            chunk.advance(&self.delimiter, &self.contig_order)?;
+        }
+        Ok(())
+    }
+
+    // sorted lines are prefixed with their id, rank and number for the second sort and with their number
+    // for the third, the prefixes are tab separated whatever the delimiter of the lines - the rank of the
+    // consequence and the group are one number, taking the place of the start in the key of the second sort
+    fn finish_dedup(self, ranking: &Ranking, write: &mut dyn FnMut(u8, &str) -> io::Result<()>) -> io::Result<()> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("cannot deduplicate BED line: {}", line));
+        let delimiter = self.delimiter.clone();
+        let chunk_size = self.chunk_size;
+
+        let mut by_id = Sorter::new("\t", chunk_size);
+        let mut number = 0;
+        self.finish_with(&mut |group, line| {
+            let fields = line.split(delimiter.as_str()).collect::<Vec<_>>();
+            let Some((rank, group_rank)) = ranking.rank(&fields) else { return Err(invalid(line)) };
+            let rank = u16::from(rank) << 8 | u16::from(group_rank);
+            number += 1;
+            by_id.group = group;
+            writeln!(by_id, "{}\t{}\t{}\t{}", fields[3], rank, number, line)
+        })?;
+
+        let mut by_number = Sorter::new("\t", chunk_size);
+        let mut previous_id = String::new();
+        by_id.finish_with(&mut |group, line| {
+            let mut prefix = line.splitn(4, '\t');
+            let (Some(id), Some(_), Some(number), Some(line)) = (prefix.next(), prefix.next(), prefix.next(), prefix.next()) else {
+                return Err(invalid(line))
+            };
+            if id == previous_id {
+                return Ok(());
+            }
+            previous_id = id.to_string();
+            by_number.group = group;
+            writeln!(by_number, "\t{}\t0\t{}", number, line)
+        })?;
+
+        by_number.finish_with(&mut |group, line| match line.splitn(4, '\t').nth(3) {
+            Some(line) => write(group, line),
+            None => Err(invalid(line))
+        })
+    }
+}
+
+/// The sorter as a sink, see [`Sorter::grouped`]
+pub struct Grouped<'a>(&'a mut Sorter);
+
+impl LineSink for Grouped<'_> {
+    fn writer(&mut self, group: u8) -> io::Result<&mut dyn Write> {
+        self.0.group = group;
+        Ok(self.0)
+    }
+}
+
+impl Write for Sorter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line = self.partial.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8(line[..end].to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.push(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(lines: &[&str], chunk_size: usize) -> String {
+        let mut sorter = Sorter::new(" ", chunk_size);
+        for line in lines {
+            writeln!(sorter, "{}", line).unwrap();
+        }
+
+        let mut out = Vec::new();
+        sorter.finish(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lines_are_sorted_in_memory_and_through_chunks() {
+        let lines = ["2 5 6 rs4", "1 100 101 rs2", "1 20 21 rs1", "10 1 2 rs5", "1 100 101 rs3"];
+        let expected = "1 20 21 rs1\n1 100 101 rs2\n1 100 101 rs3\n10 1 2 rs5\n2 5 6 rs4\n";
+
+        assert_eq!(sorted(&lines, 100), expected);
+        assert_eq!(sorted(&lines, 2), expected);
+        assert_eq!(sorted(&lines, 1), expected);
+    }
+
+    #[test]
+    fn contigs_are_sorted_in_the_given_order() {
+        let mut sorter = Sorter::new(" ", 2).contig_order(HashMap::from([("2".to_string(), 0), ("10".to_string(), 1)]));
+        for line in ["MT 1 2 rs1", "10 5 6 rs2", "1 7 8 rs3", "2 9 10 rs4", "10 1 2 rs5"] {
+            writeln!(sorter, "{}", line).unwrap();
+        }
+
+        let mut out = Vec::new();
+        sorter.finish(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "2 9 10 rs4\n10 1 2 rs5\n10 5 6 rs2\n1 7 8 rs3\nMT 1 2 rs1\n");
+    }
+
+    #[test]
+    fn most_severe_line_of_each_id_is_kept() {
+        // an upstream_gene_variant ranked with intron_variant is less severe by its group
+        let ranking = Ranking::new(HashMap::from([
+            ("missense_variant".to_string(), 13), ("intron_variant".to_string(), 28), ("upstream_gene_variant".to_string(), 28)
+        ]));
+        let lines = [
+            "1 300 301 rs1 SNV A T 1 missense_variant", "1 100 101 rs1 SNV A G 3 intron_variant",
+            "1 200 201 rs2 SNV A G 3 intron_variant", "1 250 251 rs2 SNV A C 3 intron_variant",
+            "1 50 51 rs3 SNV A C 0 unranked_variant",
+            "1 400 401 rs4 SNV A C 5 upstream_gene_variant", "1 450 451 rs4 SNV A G 3 intron_variant",
+        ];
+        for chunk_size in [100, 1] {
+            let mut sorter = Sorter::new(" ", chunk_size).dedup(ranking.clone());
+            for line in lines {
+                writeln!(sorter, "{}", line).unwrap();
+            }
+
+            let mut out = Vec::new();
+            sorter.finish(&mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), concat!(
+                "1 50 51 rs3 SNV A C 0 unranked_variant\n",
+                "1 200 201 rs2 SNV A G 3 intron_variant\n",
+                "1 300 301 rs1 SNV A T 1 missense_variant\n",
+                "1 450 451 rs4 SNV A G 3 intron_variant\n"
+            ));
+        }
+    }
+}
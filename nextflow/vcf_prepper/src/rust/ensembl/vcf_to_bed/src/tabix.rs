@@ -0,0 +1,271 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+ 
+//! Minimal reader for tabix (.tbi) and CSI (.csi) indexes of bgzipped VCFs
+//!
+//! Only what is needed to jump to the records of a contig or region is read from the index:
+//! the contig names and, for each contig, the bins with their chunks of virtual offsets.
+//! A virtual offset is the offset of a bgzf block in the compressed file in the upper 48 bits
+//! and the offset of the record within the uncompressed block in the lower 16 bits.
+//!
+//! Both formats use the same binning scheme: level 0 is one bin spanning the whole contig and each
+//! level splits the bins of the one above into 8, down to bins of `1 << min_shift` bases.
+//! Tabix fixes min_shift at 14 and depth at 5, CSI stores them in its header.
+
+use std::{io::{self, BufRead, BufReader, Read, Seek, SeekFrom}, fs::File, path::Path};
+use flate2::read::MultiGzDecoder;
+
+pub struct Index {
+    min_shift: u32,
+    depth: u32,
+    contigs: Vec<Contig>
+}
+
+struct Contig {
+    name: String,
+    // bins holding records with the smallest virtual offset among their chunks
+    bins: Vec<(u32, u64)>
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_count(reader: &mut impl Read) -> io::Result<usize> {
+    usize::try_from(read_i32(reader)?).map_err(|_| invalid("negative count"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid tabix index: {}", message))
+}
+
+// contig names of the tabix header (format, col_seq, col_beg, col_end, meta, skip, names)
+fn read_names(reader: &mut impl Read) -> io::Result<Vec<String>> {
+    for _ in 0..6 {
+        read_i32(reader)?;
+    }
+    let mut names = vec![0; read_count(reader)?];
+    reader.read_exact(&mut names)?;
+    Ok(names.split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect())
+}
+
+// first bin number of a level
+fn level_offset(level: u32) -> u32 {
+    ((1 << (3 * level)) - 1) / 7
+}
+
+impl Index {
+    /// Index of the given VCF, from the .tbi or else .csi file next to it if there is one
+    pub fn for_vcf(vcf: &str) -> io::Result<Option<Index>> {
+        if vcf == "-" {
+            return Ok(None);
+        }
+
+        for extension in ["tbi", "csi"] {
+            let path = format!("{}.{}", vcf, extension);
+            if Path::new(&path).exists() {
+                return Index::read(File::open(path)?).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read a tabix or CSI index, telling them apart by their magic
+    pub fn read(input: impl Read) -> io::Result<Index> {
+        let mut reader = BufReader::new(MultiGzDecoder::new(input));
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let (min_shift, depth, names, n_ref, csi) = match &magic {
+            b"TBI\x01" => {
+                let n_ref = read_count(&mut reader)?;
+                (14, 5, read_names(&mut reader)?, n_ref, false)
+            },
+            b"CSI\x01" => {
+                let min_shift = read_count(&mut reader)? as u32;
+                let depth = read_count(&mut reader)? as u32;
+                let mut aux = vec![0; read_count(&mut reader)?];
+                reader.read_exact(&mut aux)?;
+                let names = if aux.is_empty() { Vec::new() } else { read_names(&mut &aux[..])? };
+                (min_shift, depth, names, read_count(&mut reader)?, true)
+            },
+            _ => return Err(invalid("bad magic"))
+        };
+        if names.len() != n_ref {
+            return Err(invalid("number of contig names does not match"));
+        }
+
+        // bin htslib uses to store the offsets and record counts of a contig
+        let pseudo_bin = level_offset(depth + 1) + 1;
+        let mut contigs = Vec::with_capacity(n_ref);
+        for name in names {
+            let mut bins = Vec::new();
+            for _ in 0..read_count(&mut reader)? {
+                let bin = read_i32(&mut reader)? as u32;
+                if csi {
+                    // offset of the first record overlapping the bin, the chunks give the same
+                    read_u64(&mut reader)?;
+                }
+                let mut first = u64::MAX;
+                for _ in 0..read_count(&mut reader)? {
+                    first = first.min(read_u64(&mut reader)?);
+                    read_u64(&mut reader)?;
+                }
+                if bin != pseudo_bin && first != u64::MAX {
+                    bins.push((bin, first));
+                }
+            }
+            if !csi {
+                // linear index is not needed, the bins are enough to find the records
+                for _ in 0..read_count(&mut reader)? {
+                    read_u64(&mut reader)?;
+                }
+            }
+
+            contigs.push(Contig { name, bins });
+        }
+
+        Ok(Index { min_shift, depth, contigs })
+    }
+
+    /// Contig names in the order they appear in the VCF
+    pub fn contigs(&self) -> impl Iterator<Item = &str> {
+        self.contigs.iter().map(|contig| contig.name.as_str())
+    }
+
+    // 0-based half-open span of a bin
+    fn bin_span(&self, bin: u32) -> (u64, u64) {
+        let level = (0..=self.depth).rev().find(|level| bin >= level_offset(*level)).unwrap_or(0);
+        let shift = self.min_shift + 3 * (self.depth - level);
+        let start = u64::from(bin - level_offset(level)) << shift;
+        (start, start + (1 << shift))
+    }
+
+    // smallest virtual offset of the bins of the contig overlapping the 0-based half-open span
+    fn first_offset(&self, contig: &str, start: u64, end: u64) -> Option<u64> {
+        let contig = self.contigs.iter().find(|c| c.name == contig)?;
+        contig.bins.iter()
+            .filter(|(bin, _)| {
+                let (bin_start, bin_end) = self.bin_span(*bin);
+                bin_start < end && start < bin_end
+            })
+            .map(|(_, offset)| *offset)
+            .min()
+    }
+
+    /// Reader over the records (without header) of one contig of the indexed bgzipped VCF
+    pub fn contig_reader(&self, vcf: &str, contig: &str) -> io::Result<ContigReader> {
+        let first = self.first_offset(contig, 0, u64::MAX);
+        self.reader(vcf, contig, first, None)
+    }
+
+    /// Reader over the records of one contig starting at or before the 1-based start and ending at the first record after end,
+    /// so it has every record overlapping start..=end but may have others around them
+    pub fn region_reader(&self, vcf: &str, contig: &str, start: u64, end: u64) -> io::Result<ContigReader> {
+        let first = self.first_offset(contig, start.saturating_sub(1), end);
+        self.reader(vcf, contig, first, Some(end))
+    }
+
+    fn reader(&self, vcf: &str, contig: &str, offset: Option<u64>, end: Option<u64>) -> io::Result<ContigReader> {
+        let lines: Box<dyn BufRead + Send> = match offset {
+            Some(offset) => {
+                let mut file = File::open(vcf)?;
+                file.seek(SeekFrom::Start(offset >> 16))?;
+                let mut lines = BufReader::new(MultiGzDecoder::new(file));
+                io::copy(&mut (&mut lines).take(offset & 0xffff), &mut io::sink())?;
+                Box::new(lines)
+            },
+            None => Box::new(io::empty())
+        };
+
+        Ok(ContigReader {
+            lines,
+            contig: contig.as_bytes().to_vec(),
+            end,
+            line: Vec::new(),
+            pos: 0,
+            seen: false,
+            done: false
+        })
+    }
+}
+
+/// Yields the lines of a single contig, stopping at the first line of the next contig
+/// or the first line after the end position
+pub struct ContigReader {
+    lines: Box<dyn BufRead + Send>,
+    contig: Vec<u8>,
+    end: Option<u64>,
+    line: Vec<u8>,
+    pos: usize,
+    seen: bool,
+    done: bool
+}
+
+impl ContigReader {
+    fn next_line(&mut self) -> io::Result<()> {
+        loop {
+            self.line.clear();
+            self.pos = 0;
+            if self.lines.read_until(b'\n', &mut self.line)? == 0 {
+                self.done = true;
+                return Ok(());
+            }
+
+            let mut fields = self.line.split(|b| *b == b'\t');
+            let chromosome = fields.next().unwrap_or(&[]);
+            if chromosome == self.contig.as_slice() {
+                self.seen = true;
+                let past_end = self.end.is_some_and(|end| {
+                    let position = fields.next().and_then(|p| std::str::from_utf8(p).ok()?.parse::<u64>().ok());
+                    position.is_some_and(|position| position > end)
+                });
+                if !past_end {
+                    return Ok(());
+                }
+            }
+            if self.seen {
+                self.line.clear();
+                self.done = true;
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Read for ContigReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() && !self.done {
+            self.next_line()?;
+        }
+
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
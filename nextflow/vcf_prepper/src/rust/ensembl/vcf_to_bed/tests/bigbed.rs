@@ -0,0 +1,208 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! BigBed written by --bigbed read back through its chromosome tree and R-tree index
+
+#![cfg(feature = "bigbed")]
+
+use std::{collections::{BTreeMap, HashMap}, fs, io::{Read, Write}, path::PathBuf, process::Command};
+use flate2::read::ZlibDecoder;
+use tempfile::TempDir;
+use vcf_to_bed::bigbed::{BigBedWriter, MAGIC};
+
+const TEST_VCF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../tests/data/test_vcftobed.vcf.gz");
+const RANK_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../assets/variation_consequnce_rank.json");
+
+// just enough of a BigBed reader to query it, following the offsets of the header
+struct BigBed(Vec<u8>);
+
+impl BigBed {
+    fn u16(&self, at: usize) -> usize {
+        u16::from_le_bytes(self.0[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.0[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64(&self, at: usize) -> usize {
+        u64::from_le_bytes(self.0[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    fn chromosome_id(&self, name: &str) -> Option<u32> {
+        let tree = self.u64(8);
+        assert_eq!(self.u32(tree), 0x78ca_8c91);
+        let key_size = self.u32(tree + 8) as usize;
+        let mut key = name.as_bytes().to_vec();
+        key.resize(key_size, 0);
+
+        let mut node = tree + 32;
+        loop {
+            let (leaf, count) = (self.0[node] == 1, self.u16(node + 2));
+            let item = |index: usize| node + 4 + index * (key_size + 8);
+            if leaf {
+                return (0..count).find(|i| self.0[item(*i)..item(*i) + key_size] == key[..]).map(|i| self.u32(item(i) + key_size));
+            }
+            let child = (0..count).rev().find(|i| self.0[item(*i)..item(*i) + key_size] <= key[..])?;
+            node = self.u64(item(child) + key_size);
+        }
+    }
+
+    // the lines overlapping the region, with the columns after the end joined by tabs
+    fn query(&self, name: &str, start: u32, end: u32) -> Vec<String> {
+        let Some(chromosome) = self.chromosome_id(name) else {
+            return Vec::new();
+        };
+        let index = self.u64(24);
+        assert_eq!(self.u32(index), 0x2468_ace0);
+        let mut blocks = Vec::new();
+        let mut nodes = vec![index + 48];
+        while let Some(node) = nodes.pop() {
+            let (leaf, count) = (self.0[node] == 1, self.u16(node + 2));
+            for i in 0..count {
+                let item = node + 4 + i * if leaf { 32 } else { 24 };
+                let bounds = [self.u32(item), self.u32(item + 4), self.u32(item + 8), self.u32(item + 12)];
+                if (chromosome, start) >= (bounds[2], bounds[3]) || (bounds[0], bounds[1]) >= (chromosome, end) {
+                    continue;
+                }
+                if leaf {
+                    blocks.push((self.u64(item + 16), self.u64(item + 24)));
+                } else {
+                    nodes.push(self.u64(item + 16));
+                }
+            }
+        }
+
+        blocks.sort();
+        let mut lines = Vec::new();
+        for (offset, size) in blocks {
+            let mut block = Vec::new();
+            ZlibDecoder::new(&self.0[offset..offset + size]).read_to_end(&mut block).unwrap();
+            let block = BigBed(block);
+            let mut at = 0;
+            while at < block.0.len() {
+                let (id, record_start, record_end) = (block.u32(at), block.u32(at + 4), block.u32(at + 8));
+                let rest = block.0[at + 12..].iter().position(|b| *b == 0).unwrap();
+                if id == chromosome && record_start < end && record_end > start {
+                    let rest = String::from_utf8_lossy(&block.0[at + 12..at + 12 + rest]);
+                    lines.push(format!("{}\t{}\t{}\t{}", name, record_start, record_end, rest));
+                }
+                at += 12 + rest + 1;
+            }
+        }
+        lines
+    }
+}
+
+// the sorted BED of the test VCF, and the BigBed written from it with the sizes of its chromosomes
+fn write_bigbed(dir: &TempDir) -> (String, BTreeMap<String, u32>, PathBuf) {
+    let output = Command::new(env!("CARGO_BIN_EXE_vcf_to_bed"))
+        .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--sort", "--quiet", "--output", "-"])
+        .output().unwrap();
+    assert!(output.status.success());
+    let bed = String::from_utf8(output.stdout).unwrap();
+
+    let mut sizes = BTreeMap::new();
+    for line in bed.lines() {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let end = fields[2].parse::<u32>().unwrap();
+        let size = sizes.entry(fields[0].to_string()).or_insert(0);
+        *size = end.max(*size) + 1000;
+    }
+    let chrom_sizes = dir.path().join("chrom.sizes");
+    fs::write(&chrom_sizes, sizes.iter().map(|(name, size)| format!("{}\t{}\n", name, size)).collect::<String>()).unwrap();
+
+    let bb = dir.path().join("variants.bb");
+    let status = Command::new(env!("CARGO_BIN_EXE_vcf_to_bed"))
+        .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--quiet", "--bigbed"]).arg(&bb)
+        .arg("--chrom-sizes").arg(&chrom_sizes)
+        .status().unwrap();
+    assert!(status.success());
+    (bed, sizes, bb)
+}
+
+// whether a tool can be run, for the UCSC utilities that need not be installed
+fn on_path(tool: &str) -> bool {
+    Command::new(tool).output().is_ok()
+}
+
+#[test]
+fn bigbed_is_read_back() {
+    let dir = TempDir::new().unwrap();
+    let (bed, sizes, bb) = write_bigbed(&dir);
+
+    let bigbed = BigBed(fs::read(&bb).unwrap());
+    assert_eq!(bigbed.u32(0), MAGIC);
+    assert_eq!(bigbed.u32(bigbed.0.len() - 4), MAGIC);
+    assert_eq!(bigbed.u64(64), bed.lines().count());
+    assert!(String::from_utf8_lossy(&bigbed.0[bigbed.u64(36)..]).starts_with("table vep_consequences"));
+    for (name, size) in &sizes {
+        let expected = bed.lines().filter(|line| line.split('\t').next() == Some(name)).collect::<Vec<_>>();
+        assert_eq!(bigbed.query(name, 0, *size), expected, "{}", name);
+    }
+    let (name, _) = sizes.iter().next().unwrap();
+    let first = bed.lines().find(|line| line.starts_with(&format!("{}\t", name))).unwrap();
+    let start = first.split('\t').nth(1).unwrap().parse::<u32>().unwrap();
+    assert_eq!(bigbed.query(name, start, start + 1).first().map(String::as_str), Some(first));
+    assert!(bigbed.query("absent", 0, 100).is_empty());
+}
+
+#[test]
+fn indexes_of_many_chromosomes_and_blocks_have_levels() {
+    let dir = TempDir::new().unwrap();
+    let bb = dir.path().join("many.bb");
+    let names = (0..300).map(|i| format!("contig{:03}", i)).collect::<Vec<_>>();
+    let sizes = names.iter().map(|name| (name.clone(), 1_000_000)).collect::<HashMap<_, _>>();
+    let mut writer = BigBedWriter::create(bb.to_str().unwrap(), sizes, "\t").unwrap();
+    for name in &names {
+        for start in 0..500 {
+            writeln!(writer, "{}\t{}\t{}\trs{}\tSNV\tA\tT\t3\tintron_variant", name, start * 10, start * 10 + 1, start).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let bigbed = BigBed(fs::read(&bb).unwrap());
+    assert_eq!(bigbed.u64(64), 300 * 500);
+    assert_eq!(bigbed.query("contig000", 0, 25), [
+        "contig000\t0\t1\trs0\tSNV\tA\tT\t3\tintron_variant",
+        "contig000\t10\t11\trs1\tSNV\tA\tT\t3\tintron_variant",
+        "contig000\t20\t21\trs2\tSNV\tA\tT\t3\tintron_variant"
+    ]);
+    assert_eq!(bigbed.query("contig299", 4990, 5000), ["contig299\t4990\t4991\trs499\tSNV\tA\tT\t3\tintron_variant"]);
+    assert_eq!(bigbed.query("contig150", 0, 1_000_000).len(), 500);
+}
+
+// bigBedInfo and bigBedToBed read the file independently of the reader above, so are run when they are installed
+#[test]
+fn bigbed_is_read_by_the_ucsc_tools() {
+    if !on_path("bigBedInfo") || !on_path("bigBedToBed") {
+        eprintln!("skipping: bigBedInfo and bigBedToBed are not on PATH");
+        return;
+    }
+    let dir = TempDir::new().unwrap();
+    let (bed, sizes, bb) = write_bigbed(&dir);
+
+    let info = Command::new("bigBedInfo").arg(&bb).output().unwrap();
+    assert!(info.status.success(), "{}", String::from_utf8_lossy(&info.stderr));
+    let info = String::from_utf8(info.stdout).unwrap();
+    assert!(info.contains(&format!("itemCount: {}\n", bed.lines().count())), "{}", info);
+    assert!(info.contains(&format!("chromCount: {}\n", sizes.len())), "{}", info);
+
+    let out = dir.path().join("variants.bed");
+    let status = Command::new("bigBedToBed").arg(&bb).arg(&out).status().unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&out).unwrap(), bed);
+}
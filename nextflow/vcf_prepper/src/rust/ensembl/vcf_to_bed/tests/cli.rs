@@ -0,0 +1,1774 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{collections::{HashMap, HashSet}, fs, fs::File, io::{Read, Write}, path::Path, process::{Command, Stdio}};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use tempfile::TempDir;
+use vcf_to_bed::{binary, tabix::Index};
+
+const TEST_VCF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../tests/data/test_vcftobed.vcf.gz");
+const RANK_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../assets/variation_consequnce_rank.json");
+
+const CSQ_HEADER: &str = "##INFO=<ID=CSQ,Number=.,Type=String,Description=\"Consequence annotations from Ensembl VEP. \
+    Format: Allele|Consequence|IMPACT|SYMBOL|Gene|Feature_type|Feature|BIOTYPE|EXON|INTRON|HGVSc|HGVSp|cDNA_position|\
+    CDS_position|Protein_position|Amino_acids|Codons|Existing_variation|DISTANCE|STRAND|FLAGS|VARIANT_CLASS|SYMBOL_SOURCE|HGNC_ID\">";
+
+fn vcf_to_bed() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_vcf_to_bed"))
+}
+
+// a CSQ entry in the default VEP layout, with VARIANT_CLASS at index 21
+fn csq(allele: &str, consequence: &str, class: &str) -> String {
+    format!("{}|{}{}{}", allele, consequence, "|".repeat(20), class)
+}
+
+// write a VCF with a minimal header and the given tab-separated records, gzipped if the name ends with .gz
+fn write_vcf(dir: &TempDir, name: &str, records: &[impl AsRef<str>]) -> String {
+    write_vcf_with_header(dir, name, &[] as &[&str], records)
+}
+
+fn write_vcf_with_header(dir: &TempDir, name: &str, header: &[impl AsRef<str>], records: &[impl AsRef<str>]) -> String {
+    let mut content = String::from("##fileformat=VCFv4.2\n");
+    for line in header {
+        content += &format!("{}\n", line.as_ref());
+    }
+    content += "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n";
+    for record in records {
+        content += &format!("{}\n", record.as_ref());
+    }
+
+    let path = dir.path().join(name);
+    if name.ends_with(".gz") {
+        let mut vcf = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        vcf.write_all(content.as_bytes()).unwrap();
+        vcf.finish().unwrap();
+    } else {
+        fs::write(&path, content).unwrap();
+    }
+
+    path.to_str().unwrap().to_string()
+}
+
+// typed vector of BCF, the type in the lower 4 bits of its first byte and the count in the upper 4
+fn bcf_typed(out: &mut Vec<u8>, kind: u8, count: usize, values: &[u8]) {
+    if count < 15 {
+        out.push((count as u8) << 4 | kind);
+    } else {
+        out.extend([0xf0 | kind, 0x12]);
+        out.extend((count as i16).to_le_bytes());
+    }
+    out.extend(values);
+}
+
+fn bcf_text(out: &mut Vec<u8>, text: &str) {
+    bcf_typed(out, 7, text.len(), text.as_bytes());
+}
+
+// a record of a BCF: contig number, 0-based position, id, alleles, quality, filter key numbers and INFO fields by
+// key number with their value as an int32, a text or none for a flag
+type BcfRecord<'a> = (i32, i32, &'a str, &'a [&'a str], Option<f32>, &'a [u8], &'a [(u8, Option<i32>, Option<&'a str>)]);
+
+// write a gzipped BCF of the header and records, with a GT of 0/1 for one sample
+fn write_bcf(dir: &TempDir, name: &str, header: &str, records: &[BcfRecord]) -> String {
+    let mut bcf = b"BCF\x02\x02".to_vec();
+    bcf.extend((header.len() as u32 + 1).to_le_bytes());
+    bcf.extend(header.as_bytes());
+    bcf.push(0);
+    for (contig, position, id, alleles, quality, filters, info) in records {
+        let mut shared = Vec::new();
+        shared.extend(contig.to_le_bytes());
+        shared.extend(position.to_le_bytes());
+        shared.extend((alleles[0].len() as i32).to_le_bytes());
+        shared.extend(quality.map_or(0x7f80_0001, f32::to_bits).to_le_bytes());
+        shared.extend(((alleles.len() as u32) << 16 | info.len() as u32).to_le_bytes());
+        shared.extend((1u32 << 24 | 1).to_le_bytes());
+        bcf_text(&mut shared, id);
+        for allele in alleles.iter() {
+            bcf_text(&mut shared, allele);
+        }
+        bcf_typed(&mut shared, 1, filters.len(), filters);
+        for (key, number, text) in info.iter() {
+            bcf_typed(&mut shared, 1, 1, &[*key]);
+            match (number, text) {
+                (Some(number), _) => bcf_typed(&mut shared, 3, 1, &number.to_le_bytes()),
+                (None, Some(text)) => bcf_text(&mut shared, text),
+                (None, None) => shared.push(0)
+            }
+        }
+        let individual = [0x11, 5, 0x21, 0x02, 0x04];
+        bcf.extend((shared.len() as u32).to_le_bytes());
+        bcf.extend((individual.len() as u32).to_le_bytes());
+        bcf.extend(shared);
+        bcf.extend(individual);
+    }
+
+    let path = dir.path().join(name);
+    let mut out = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    out.write_all(&bcf).unwrap();
+    out.finish().unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+// run vcf_to_bed on the given input and return the output BED content
+fn convert(dir: &TempDir, input: &str, name: &str) -> String {
+    convert_with(dir, input, name, &[])
+}
+
+fn convert_with(dir: &TempDir, input: &str, name: &str, extra: &[&str]) -> String {
+    let bed = dir.path().join(name);
+    let status = vcf_to_bed()
+        .args(["--input", input, "--severity", RANK_FILE])
+        .arg("--output").arg(&bed)
+        .args(extra)
+        .status().unwrap();
+    assert!(status.success());
+
+    read(&bed)
+}
+
+// expected output written with spaces for readability
+fn tabs(bed: &str) -> String {
+    bed.replace(' ', "\t")
+}
+
+// decompressed if gzipped
+fn read(path: &Path) -> String {
+    let bytes = fs::read(path).unwrap();
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        return String::from_utf8(bytes).unwrap();
+    }
+
+    let mut text = String::new();
+    MultiGzDecoder::new(&bytes[..]).read_to_string(&mut text).unwrap();
+    text
+}
+
+#[test]
+fn missing_arguments_give_usage_error() {
+    let output = vcf_to_bed().output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("--input"), "{}", stderr);
+    assert!(stderr.contains("Usage"), "{}", stderr);
+}
+
+#[test]
+fn output_is_reproducible() {
+    let dir = TempDir::new().unwrap();
+    let first = convert(&dir, TEST_VCF, "first.bed");
+    let second = convert(&dir, TEST_VCF, "second.bed");
+
+    assert_eq!(first, second);
+    assert!(first.contains(&tabs("GL000214.1 17029 17030 rs4023684 SNV C G,T 5 intergenic_variant\n")));
+}
+
+#[test]
+fn csq_field_indices_are_configurable() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "layout.vcf.gz", &[
+        "1\t100\trs1\tA\tT\t.\t.\tCSQ=missense_variant|T|SNV,intron_variant|T|SNV",
+    ]);
+    let bed = convert_with(&dir, &vcf, "layout.bed", &["--consequence-field", "0", "--class-field", "2"]);
+
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn allele_field_pairs_csq_entries_with_alts() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "allele.vcf.gz", &[
+        "1\t100\trs1\tA\tG,T\t.\t.\tCSQ=intron_variant|SNV|G,missense_variant|SNV|T",
+    ]);
+    let bed = convert_with(&dir, &vcf, "allele.bed", &["--allele-field", "2", "--consequence-field", "0", "--class-field", "1", "--split-alts"]);
+
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G 3 intron_variant\n1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn csq_field_indices_are_read_from_header() {
+    let dir = TempDir::new().unwrap();
+    let header = CSQ_HEADER
+        .replace("Allele|Consequence|IMPACT", "Allele|IMPACT|Consequence")
+        .replace("FLAGS|VARIANT_CLASS", "VARIANT_CLASS|FLAGS");
+    let csq = format!("T|MODERATE|missense_variant{}SNV|||", "|".repeat(18));
+    let vcf = write_vcf_with_header(&dir, "header.vcf.gz", &[header], &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq),
+    ]);
+    let bed = convert(&dir, &vcf, "header.bed");
+
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn csq_format_is_printed_with_indices() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf_with_header(&dir, "header.vcf", &[CSQ_HEADER], &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+    let output = vcf_to_bed().args(["--input", &vcf, "--print-csq-format"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..3], ["0\tAllele", "1\tConsequence", "2\tIMPACT"]);
+    assert_eq!(lines[6], "6\tFeature");
+    assert_eq!(lines[21], "21\tVARIANT_CLASS");
+
+    let vcf = write_vcf(&dir, "no_header.vcf", &[format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV"))]);
+    let output = vcf_to_bed().args(["--input", &vcf, "--print-csq-format"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not describe the Format of CSQ"));
+}
+
+#[test]
+fn plain_and_gzipped_input_give_same_output() {
+    let dir = TempDir::new().unwrap();
+    let records = [
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tAC\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+    ];
+    let plain = write_vcf(&dir, "plain.vcf", &records);
+    let gzipped = write_vcf(&dir, "gzipped.vcf.gz", &records);
+
+    let bed = convert(&dir, &plain, "plain.bed");
+    assert_eq!(bed, convert(&dir, &gzipped, "gzipped.bed"));
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n1 199 201 rs2 deletion AC A 3 intron_variant\n"));
+}
+
+#[test]
+fn output_can_be_written_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--output", "-", "--severity", RANK_FILE])
+        .output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), convert(&dir, TEST_VCF, "file.bed"));
+}
+
+#[test]
+fn input_can_be_read_from_stdin() {
+    let dir = TempDir::new().unwrap();
+    let bed = dir.path().join("stdin.bed");
+    let mut child = vcf_to_bed()
+        .args(["--input", "-", "--severity", RANK_FILE])
+        .arg("--output").arg(&bed)
+        .stdin(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(&fs::read(TEST_VCF).unwrap()).unwrap();
+
+    assert!(child.wait().unwrap().success());
+    assert_eq!(read(&bed), convert(&dir, TEST_VCF, "file.bed"));
+}
+
+#[test]
+fn long_reference_is_skipped_over_max_ref_length() {
+    let dir = TempDir::new().unwrap();
+    let reference = "A".repeat(32);
+    let vcf = write_vcf(&dir, "long.vcf.gz", &[
+        format!("1\t100\trs1\t{}\tA\t.\t.\tCSQ={}", reference, csq("-", "intron_variant", "deletion")),
+    ]);
+
+    assert_eq!(convert_with(&dir, &vcf, "default.bed", &[]).lines().count(), 1);
+    assert_eq!(convert_with(&dir, &vcf, "capped.bed", &["--max-ref-length", "31"]).lines().count(), 0);
+    assert_eq!(convert_with(&dir, &vcf, "raised.bed", &["--max-ref-length", "32"]).lines().count(), 1);
+}
+
+#[test]
+fn multiallelic_site_is_skipped_over_max_alts() {
+    let dir = TempDir::new().unwrap();
+    let alts = (1..=500).map(|length| format!("A{}", "C".repeat(length))).collect::<Vec<_>>();
+    let vcf = write_vcf(&dir, "multiallelic.vcf", &[
+        format!("1\t100\trs1\tA\t{}\t.\t.\tCSQ={}", alts.join(","), csq("C", "intron_variant", "insertion")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+
+    assert_eq!(convert_with(&dir, &vcf, "default.bed", &[]).lines().count(), 2);
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-", "--max-alts", "10"])
+        .output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), tabs("1 199 200 rs2 SNV A T 1 missense_variant\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipped 1 records: 0 long-ref, 0 no-csq, 0 bad-span, 0 no-id, 1 many-alts"), "{}", stderr);
+}
+
+#[test]
+fn skipped_records_are_reported() {
+    let dir = TempDir::new().unwrap();
+    let long = "A".repeat(5);
+    let vcf = write_vcf(&dir, "skipped.vcf.gz", &[
+        format!("1\t100\trs1\t{}\tA\t.\t.\tCSQ={}", long, csq("-", "intron_variant", "deletion")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t300\trs3\t{}\tA\t.\t.\tCSQ={}", long, csq("-", "intron_variant", "deletion")),
+        "1\t400\trs4\tA\tT\t.\t.\tAF=0.1".to_string(),
+    ]);
+    let run = |extra: &[&str]| {
+        vcf_to_bed()
+            .args(["--input", &vcf, "--output", "-", "--severity", RANK_FILE, "--max-ref-length", "4"])
+            .args(extra)
+            .output().unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 3 records: 2 long-ref, 1 no-csq"));
+
+    let output = run(&["--quiet"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("skipped"));
+}
+
+#[test]
+fn end_tag_overrides_reference_length() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "end.vcf", &[
+        format!("1\t100\trs1\tACG\tA\t.\t.\tEND=150;CSQ={}", csq("-", "intron_variant", "deletion")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+
+    let bed = convert(&dir, &vcf, "end.bed");
+    assert_eq!(bed, tabs(concat!(
+        "1 99 150 rs1 deletion ACG A 3 intron_variant\n",
+        "1 199 200 rs2 SNV A T 1 missense_variant\n"
+    )));
+}
+
+#[test]
+fn records_that_cannot_be_placed_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "bad_span.vcf", &[
+        format!("1\t0\trs0\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t{}\trs3\tAC\tA\t.\t.\tCSQ={}", u64::MAX, csq("-", "intron_variant", "deletion")),
+    ]);
+
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-"])
+        .output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), tabs("1 199 200 rs2 SNV A T 1 missense_variant\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("skipping 1:0 - reference allele of 1 bases cannot be placed"), "{}", stderr);
+    assert!(stderr.contains("skipped 2 records: 0 long-ref, 0 no-csq, 2 bad-span"), "{}", stderr);
+}
+
+#[test]
+fn ids_can_come_from_an_info_tag() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "rs_tag.vcf", &[
+        format!("1\t100\t.\tA\tT\t.\t.\tRS=123;CSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t300\t.\tA\tT\t.\t.\tRS=COSV1;CSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+
+    let run = |extra: &[&str]| {
+        let output = vcf_to_bed()
+            .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-", "--id-source", "info:RS"])
+            .args(extra)
+            .output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    assert_eq!(run(&[]), tabs(concat!(
+        "1 99 100 rs123 SNV A T 1 missense_variant\n",
+        "1 299 300 COSV1 SNV A T 1 missense_variant\n"
+    )));
+    assert_eq!(run(&["--missing-id", "dot"]).lines().nth(1), Some(tabs("1 199 200 . SNV A T 1 missense_variant").as_str()));
+
+    let output = vcf_to_bed().args(["--input", &vcf, "--output", "-", "--id-source", "info"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn records_without_an_id_are_skipped_or_named_by_position() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "no_id.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\t.\tA\tG,T\t.\t.\tCSQ={},{}", csq("G", "missense_variant", "SNV"), csq("T", "missense_variant", "SNV")),
+    ]);
+
+    let run = |flag: &str| {
+        let output = vcf_to_bed()
+            .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-", flag])
+            .output().unwrap();
+        assert!(output.status.success());
+        (String::from_utf8_lossy(&output.stdout).to_string(), String::from_utf8_lossy(&output.stderr).to_string())
+    };
+    let (required, stderr) = run("--require-id");
+    assert_eq!(required, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+    assert!(stderr.contains("skipped 1 records: 0 long-ref, 0 no-csq, 0 bad-span, 1 no-id"), "{}", stderr);
+    let (synthesized, _) = run("--synthesize-id");
+    assert_eq!(synthesized.lines().nth(1), Some(tabs("1 199 200 1_200_A_G,T SNV A G,T 1 missense_variant").as_str()));
+}
+
+#[test]
+fn lines_past_the_contig_end_are_reported_or_dropped() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "short_contig.vcf", &[
+        format!("1\t8\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t9\trs2\tACGT\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+        format!("2\t9\trs3\tACGT\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+    ]);
+    let chrom_sizes = dir.path().join("chrom.sizes");
+    fs::write(&chrom_sizes, "1\t10\n").unwrap();
+
+    let run = |past_contig_end: &str| {
+        let output = vcf_to_bed()
+            .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-", "--past-contig-end", past_contig_end])
+            .arg("--chrom-sizes").arg(&chrom_sizes)
+            .output().unwrap();
+        assert!(output.status.success());
+        (String::from_utf8_lossy(&output.stdout).to_string(), String::from_utf8_lossy(&output.stderr).to_string())
+    };
+    let (warned, stderr) = run("warn");
+    assert_eq!(warned.lines().count(), 3);
+    assert!(stderr.contains("rs2 1:8-12 ends past 1 of length 10"), "{}", stderr);
+    let (dropped, stderr) = run("drop");
+    assert_eq!(dropped, tabs("1 7 8 rs1 SNV A T 1 missense_variant\n2 8 12 rs3 deletion ACGT A 3 intron_variant\n"));
+    assert!(stderr.contains("ends past 1 of length 10, dropping it"), "{}", stderr);
+}
+
+#[test]
+fn malformed_severity_file_gives_error() {
+    let dir = TempDir::new().unwrap();
+    let severity = dir.path().join("severity.json");
+    fs::write(&severity, "{\"missense_variant\": \"13\",").unwrap();
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--output", "-"])
+        .arg("--severity").arg(&severity)
+        .output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("cannot parse severity file"), "{}", stderr);
+    assert!(stderr.contains("severity.json"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn non_numeric_severity_rank_gives_error() {
+    let dir = TempDir::new().unwrap();
+    let severity = dir.path().join("severity.json");
+    fs::write(&severity, r#"{"missense_variant": "13", "intron_variant": "high"}"#).unwrap();
+    let bed = dir.path().join("out.bed");
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF])
+        .arg("--output").arg(&bed)
+        .arg("--severity").arg(&severity)
+        .output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("severity rank of intron_variant"), "{}", stderr);
+    assert!(stderr.contains("is not a number: high"), "{}", stderr);
+    assert!(!bed.exists());
+}
+
+#[test]
+fn merge_decisions_are_logged_at_debug_level() {
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--output", "-", "--severity", RANK_FILE])
+        .env("RUST_LOG", "debug")
+        .output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success());
+    assert!(stderr.contains("merging rs1260281513 chr19:60557 into the current line"), "{}", stderr);
+    assert!(stderr.contains("skipping rs754065641 deletion - redundant with SNV"), "{}", stderr);
+}
+
+#[test]
+fn parallel_output_matches_serial() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "serial.bed");
+
+    assert_eq!(convert_with(&dir, TEST_VCF, "parallel.bed", &["--parallel", "4"]), bed);
+    let contigs = bed.lines().map(|line| line.split('\t').next().unwrap()).collect::<HashSet<_>>();
+    assert_eq!(contigs.len(), 8);
+}
+
+#[test]
+fn threaded_output_matches_single_threaded() {
+    let dir = TempDir::new().unwrap();
+    assert_eq!(convert_with(&dir, TEST_VCF, "threaded.bed", &["--threads", "4"]), convert(&dir, TEST_VCF, "serial.bed"));
+
+    // more records than fit in a batch, with an id on two records at the same position that merge across batch
+    // boundaries, some records without CSQ and some out of order
+    let records = (0..3000).map(|i| {
+        let first = if i % 8 == 0 && i > 0 { i - 1 } else { i };
+        let info = match i % 5 {
+            4 => "DP=1".to_string(),
+            _ => format!("CSQ={}", csq("T", if i % 3 == 0 { "missense_variant" } else { "intron_variant" }, "SNV"))
+        };
+        let position = if i % 1000 == 999 { 50 } else { 100 + first * 2 };
+        format!("1\t{}\trs{}\tA\tT\t.\t.\t{}", position, first, info)
+    }).collect::<Vec<_>>();
+    let vcf = write_vcf(&dir, "many.vcf", &records);
+
+    // warnings and the summary without the timestamps of the log lines
+    let run = |extra: &[&str]| {
+        let output = vcf_to_bed().args(["--input", &vcf, "--output", "-", "--severity", RANK_FILE]).args(extra).output().unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap().lines()
+            .map(|line| line.split_once("] ").map_or(line, |(_, message)| message).to_string())
+            .collect::<Vec<_>>();
+        (String::from_utf8(output.stdout).unwrap(), stderr)
+    };
+    let (bed, stderr) = run(&[]);
+    assert!(bed.lines().count() < 2400);
+    assert!(stderr.iter().any(|line| line.contains("is not sorted")), "{:?}", stderr);
+    assert_eq!(run(&["--threads", "2"]), (bed.clone(), stderr.clone()));
+    assert_eq!(run(&["--threads", "4"]), (bed, stderr));
+}
+
+#[test]
+fn progress_is_left_out_when_stderr_is_not_a_terminal() {
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--output", "-", "--severity", RANK_FILE, "--progress", "0"])
+        .output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 14);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("records read"));
+}
+
+#[test]
+fn snpeff_annotations_are_read_from_ann() {
+    let dir = TempDir::new().unwrap();
+    let header = ["##INFO=<ID=ANN,Number=.,Type=String,Description=\"Functional annotations: 'Allele | Annotation | \
+        Annotation_Impact | Gene_Name | Gene_ID | Feature_Type | Feature_ID | Transcript_BioType | Rank | HGVS.c | HGVS.p | \
+        cDNA.pos / cDNA.length | CDS.pos / CDS.length | AA.pos / AA.length | Distance | ERRORS / WARNINGS / INFO' \">"];
+    let ann = |allele: &str, annotation: &str, impact: &str| {
+        format!("{}|{}|{}|GENE1|ENSG1|transcript|ENST1|protein_coding|1/2|c.1A>G||||||", allele, annotation, impact)
+    };
+    let vcf = write_vcf_with_header(&dir, "snpeff.vcf", &header, &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tANN={},{}", ann("G", "missense_variant&splice_region_variant", "MODERATE"), ann("G", "upstream_gene_variant", "MODIFIER")),
+        format!("1\t200\trs2\tAC\tA\t.\t.\tANN={}", ann("A", "frameshift_variant", "HIGH")),
+        format!("1\t300\trs3\tA\tAT,G\t.\t.\tANN={},{}", ann("AT", "intron_variant", "MODIFIER"), ann("G", "synonymous_variant", "LOW")),
+    ]);
+
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--output", "-", "--severity", RANK_FILE, "--annotation-key", "ANN"])
+        .output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), tabs(concat!(
+        "1 99 100 rs1 SNV A G 1 missense_variant\n",
+        "1 199 201 rs2 deletion AC A 1 frameshift_variant\n",
+        "1 299 300 rs3 sequence_alteration A AT,G 3 synonymous_variant\n"
+    )));
+    // the layout is read from the header
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Format") && !stderr.contains("fields"), "{}", stderr);
+}
+
+#[test]
+fn parallel_needs_index() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "unindexed.vcf.gz", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--output", "-", "--severity", RANK_FILE, "--parallel", "2"])
+        .output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs a tabix (.tbi) or CSI (.csi) index"));
+}
+
+#[test]
+fn bcf_converts_as_its_vcf() {
+    let dir = TempDir::new().unwrap();
+    let header = [
+        "##fileformat=VCFv4.2",
+        "##FILTER=<ID=PASS,Description=\"All filters passed\">",
+        "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">",
+        "##INFO=<ID=DB,Number=0,Type=Flag,Description=\"dbSNP membership\">",
+        CSQ_HEADER,
+        "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+        "##contig=<ID=1>",
+        "##contig=<ID=2>",
+    ];
+    let first = csq("T", "missense_variant", "SNV");
+    let second = format!("{},{}", csq("-", "intron_variant", "deletion"), csq("T", "splice_region_variant", "insertion"));
+    let vcf = write_vcf_with_header(&dir, "sites.vcf", &header, &[
+        format!("1\t100\trs1;rs2\tA\tT\t50\tPASS\tDP=10;CSQ={}", first),
+        format!("2\t200\trs3\tAC\tA,ACT\t.\t.\tDB;CSQ={}", second),
+    ]);
+    let mut text = header.join("\n");
+    text += "\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tsample\n";
+    let bcf = write_bcf(&dir, "sites.bcf", &text, &[
+        (0, 99, "rs1;rs2", &["A", "T"], Some(50.0), &[0], &[(1, Some(10), None), (3, None, Some(&first))]),
+        (1, 199, "rs3", &["AC", "A", "ACT"], None, &[], &[(2, None, None), (3, None, Some(&second))]),
+    ]);
+
+    let passthrough = ["--info-passthrough", "DP,DB", "--extra-columns", "length"];
+    let expected = convert_with(&dir, &vcf, "vcf.bed", &passthrough);
+    assert_eq!(expected.lines().count(), 3);
+    assert_eq!(convert_with(&dir, &bcf, "bcf.bed", &passthrough), expected);
+    assert_eq!(convert_with(&dir, &bcf, "region.bed", &["--region", "2"]).lines().count(), 1);
+}
+
+#[test]
+fn gzipped_output_matches_plain() {
+    let dir = TempDir::new().unwrap();
+    let plain = convert(&dir, TEST_VCF, "plain.bed");
+
+    for (name, extra) in [("by_extension.bed.gz", &[][..]), ("by_flag.bed", &["--gzip"][..])] {
+        let decoded = convert_with(&dir, TEST_VCF, name, extra);
+        assert!(fs::read(dir.path().join(name)).unwrap().starts_with(&[0x1f, 0x8b]), "{}", name);
+        assert_eq!(decoded, plain, "{}", name);
+    }
+}
+
+#[test]
+fn empty_input_gives_empty_output() {
+    let dir = TempDir::new().unwrap();
+    let header_only = write_vcf(&dir, "header_only.vcf.gz", &[] as &[&str]);
+    let no_bytes = dir.path().join("no_bytes.vcf");
+    fs::write(&no_bytes, "").unwrap();
+
+    for input in [header_only.as_str(), no_bytes.to_str().unwrap()] {
+        for extra in [&[][..], &["--sort"][..], &["--threads", "2"][..], &["--output-format", "jsonl"][..], &["--gzip"][..]] {
+            assert_eq!(convert_with(&dir, input, "empty.bed", extra), "", "{} {:?}", input, extra);
+        }
+    }
+}
+
+#[test]
+fn fields_are_tab_delimited() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "tabs.bed");
+    assert!(!bed.is_empty());
+    for line in bed.lines() {
+        assert_eq!(line.split('\t').count(), 9, "{}", line);
+        assert!(!line.contains(' '), "{}", line);
+    }
+
+    let spaced = convert_with(&dir, TEST_VCF, "spaces.bed", &["--delimiter", " "]);
+    assert_eq!(spaced, bed.replace('\t', " "));
+}
+
+#[test]
+fn name_column_follows_the_template() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "named.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+    let bed = convert_with(&dir, &vcf, "named.bed", &["--name-template", "{id}({severity},{variety})"]);
+    assert_eq!(bed, tabs("1 99 100 rs1(missense_variant,SNV) SNV A T 1 missense_variant\n"));
+
+    for template in ["{id} {severity}", "{id}_{impact}", "{id", "id}"] {
+        let output = vcf_to_bed().args(["--input", &vcf, "--output", "-", "--name-template", template]).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", template);
+    }
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--output", "-", "--delimiter", ",", "--name-template", "{id},{severity}"])
+        .output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn sort_orders_out_of_order_records() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "unsorted.vcf", &[
+        format!("2\t50\trs3\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+        format!("1\t300\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+    let expected = tabs("1 99 100 rs1 SNV A T 1 missense_variant\n1 299 300 rs2 SNV A T 3 intron_variant\n2 49 50 rs3 SNV A T 3 intron_variant\n");
+
+    assert_eq!(convert_with(&dir, &vcf, "sorted.bed", &["--sort"]), expected);
+    assert_eq!(convert_with(&dir, &vcf, "spilled.bed", &["--sort", "--sort-buffer", "1"]), expected);
+}
+
+#[test]
+fn consequences_are_taken_from_entries_of_the_record_alleles() {
+    let dir = TempDir::new().unwrap();
+    // the stop_gained entries are for alleles the records do not have
+    let vcf = write_vcf(&dir, "alleles.vcf", &[
+        format!("1\t100\trs1\tA\tG,T\t.\t.\tCSQ={},{},{}",
+            csq("G", "intron_variant", "SNV"), csq("T", "missense_variant", "SNV"), csq("C", "stop_gained", "SNV")),
+        format!("1\t200\trs2\tAC\tA,ACC\t.\t.\tCSQ={},{},{}",
+            csq("-", "intron_variant", "indel"), csq("CC", "upstream_gene_variant", "indel"), csq("C", "stop_gained", "indel")),
+    ]);
+    let bed = convert(&dir, &vcf, "alleles.bed");
+
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant\n1 199 201 rs2 indel AC A,ACC 3 intron_variant\n"));
+}
+
+#[test]
+fn coordinates_are_zero_based_half_open() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "coords.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tACG\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+        format!("1\t300\trs3\tA\tATT\t.\t.\tCSQ={}", csq("TT", "intron_variant", "insertion")),
+    ]);
+    let bed = convert(&dir, &vcf, "coords.bed");
+    let spans = bed.lines().map(|line| line.split('\t').skip(1).take(2).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+
+    assert_eq!(spans, ["99 100", "199 202", "300 300"]);
+}
+
+#[test]
+fn insertion_span_is_selectable() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "insertions.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t300\trs3\tA\tATT\t.\t.\tCSQ={}", csq("TT", "intron_variant", "insertion")),
+        format!("1\t400\trs4\tN\t<INS>\t.\t.\tSVTYPE=INS;CSQ={}", csq("INS", "intron_variant", "insertion")),
+    ]);
+    for (insertion_span, expected) in [
+        ("empty", ["99 100", "300 300", "400 400"]),
+        ("anchor", ["99 100", "299 300", "399 400"]),
+        ("next", ["99 100", "300 301", "400 401"])
+    ] {
+        let bed = convert_with(&dir, &vcf, "insertions.bed", &["--insertion-span", insertion_span]);
+        let spans = bed.lines().map(|line| line.split('\t').skip(1).take(2).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+        assert_eq!(spans, expected, "{}", insertion_span);
+    }
+}
+
+#[test]
+fn structural_variant_spans_come_from_info() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "sv.vcf", &[
+        format!("1\t100\tsv1\tA\t<DEL>\t.\t.\tSVTYPE=DEL;END=250;CSQ={}", csq("deletion", "intron_variant", "deletion")),
+        format!("1\t400\tsv2\tC\t<INS>\t.\t.\tSVTYPE=INS;SVLEN=300;CSQ={}", csq("insertion", "intron_variant", "insertion")),
+        format!("1\t500\tsv3\tG\t<DUP>\t.\t.\tSVLEN=50;CSQ={}", csq("duplication", "intron_variant", "duplication")),
+    ]);
+    let bed = convert(&dir, &vcf, "sv.bed");
+
+    assert_eq!(bed, tabs(
+        "1 99 250 sv1 deletion A <DEL> 3 intron_variant\n\
+         1 400 400 sv2 insertion C <INS> 3 intron_variant\n\
+         1 499 550 sv3 duplication G <DUP> 3 intron_variant\n"
+    ));
+}
+
+#[test]
+fn each_of_multiple_ids_gets_a_line() {
+    let dir = TempDir::new().unwrap();
+    // the second alt of the variants is on its own record
+    let vcf = write_vcf(&dir, "ids.vcf", &[
+        format!("1\t100\trs1;rs2\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+        format!("1\t100\trs1;rs2\tA\tG\t.\t.\tCSQ={}", csq("G", "missense_variant", "SNV")),
+        format!("1\t200\trs3\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+    ]);
+    let bed = convert(&dir, &vcf, "ids.bed");
+
+    assert_eq!(bed, tabs(
+        "1 99 100 rs1 SNV A G,T 1 missense_variant\n\
+         1 99 100 rs2 SNV A G,T 1 missense_variant\n\
+         1 199 200 rs3 SNV A T 3 intron_variant\n"
+    ));
+}
+
+#[test]
+fn final_redundant_record_leaves_the_last_variant_written_once() {
+    let dir = TempDir::new().unwrap();
+    // the last record has the id of the one before with another variety
+    let vcf = write_vcf(&dir, "redundant.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tAT\tA\t.\t.\tCSQ={}", csq("-", "frameshift_variant", "deletion")),
+    ]);
+
+    for extra in [&[][..], &["--threads", "2"][..], &["--sort"][..]] {
+        assert_eq!(convert_with(&dir, &vcf, "redundant.bed", extra), tabs(
+            "1 99 100 rs1 SNV A T 3 intron_variant\n\
+             1 199 200 rs2 SNV A T 1 missense_variant\n"
+        ), "{:?}", extra);
+    }
+}
+
+#[test]
+fn jsonl_output_matches_bed() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "lines.bed");
+    let jsonl = convert_with(&dir, TEST_VCF, "lines.jsonl", &["--output-format", "jsonl"]);
+    assert_eq!(bed.lines().count(), jsonl.lines().count());
+
+    for (bed_line, json_line) in bed.lines().zip(jsonl.lines()) {
+        let fields = bed_line.split('\t').collect::<Vec<_>>();
+        let json = serde_json::from_str::<serde_json::Value>(json_line).unwrap();
+        let alts = json["alts"].as_array().unwrap().iter().map(|alt| alt.as_str().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(json["chromosome"], fields[0]);
+        assert_eq!(json["start"].to_string(), fields[1]);
+        assert_eq!(json["end"].to_string(), fields[2]);
+        assert_eq!(json["id"], fields[3]);
+        assert_eq!(json["variety"], fields[4]);
+        assert_eq!(json["reference"], fields[5]);
+        assert_eq!(alts.join(","), fields[6]);
+        assert_eq!(json["group"].to_string(), fields[7]);
+        assert_eq!(json["severity"], fields[8]);
+    }
+}
+
+#[test]
+fn built_in_severity_is_used_without_severity_file() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "default.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={},{}", csq("T", "intron_variant", "SNV"), csq("T", "missense_variant", "SNV")),
+    ]);
+    let bed = dir.path().join("default.bed");
+    let status = vcf_to_bed().args(["--input", &vcf]).arg("--output").arg(&bed).status().unwrap();
+
+    assert!(status.success());
+    assert_eq!(read(&bed), tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn built_in_severity_matches_rank_file() {
+    let ranks = serde_json::from_str::<HashMap<String, String>>(&fs::read_to_string(RANK_FILE).unwrap()).unwrap();
+    let built_in = vcf_to_bed::SEVERITY.iter().map(|(csq, rank)| (csq.to_string(), rank.to_string())).collect::<HashMap<_, _>>();
+    assert_eq!(built_in, ranks);
+}
+
+#[test]
+fn severity_file_missing_terms_is_reported() {
+    let dir = TempDir::new().unwrap();
+    let mut ranks = serde_json::from_str::<HashMap<String, String>>(&fs::read_to_string(RANK_FILE).unwrap()).unwrap();
+    ranks.remove("missense_variant");
+    let severity = dir.path().join("severity.json");
+    fs::write(&severity, serde_json::to_string(&ranks).unwrap()).unwrap();
+    let run = |extra: &[&str]| {
+        vcf_to_bed()
+            .args(["--input", TEST_VCF, "--output", "-", "--severity", severity.to_str().unwrap()])
+            .args(extra)
+            .output().unwrap()
+    };
+
+    let output = run(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert!(stderr.contains("does not rank") && stderr.contains("missense_variant"), "{}", stderr);
+
+    let output = run(&["--strict"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("missense_variant"), "{}", stderr);
+}
+
+#[test]
+fn unranked_terms_of_the_records_are_reported_once() {
+    let dir = TempDir::new().unwrap();
+    let records = (1..=20).map(|i| {
+        format!("1\t{}\trs{}\tA\tT\t.\t.\tCSQ={}", i * 100, i, csq("T", "made_up_variant&intron_variant", "SNV"))
+    }).collect::<Vec<_>>();
+    let vcf = write_vcf(&dir, "unranked.vcf", &records);
+
+    for extra in [&[][..], &["--threads", "3"][..]] {
+        let output = vcf_to_bed()
+            .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-"])
+            .args(extra)
+            .output().unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr.matches("made_up_variant").count(), 1, "{}", stderr);
+        assert!(stderr.contains("not in the severity ranks, ranked least severe: made_up_variant"), "{}", stderr);
+    }
+}
+
+#[test]
+fn variant_groups_can_be_loaded_from_a_file() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "groups.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "splice_region_variant", "SNV")),
+    ]);
+    let json = dir.path().join("groups.json");
+    fs::write(&json, r#"{"splice_region_variant": 5}"#).unwrap();
+    let tsv = dir.path().join("groups.tsv");
+    fs::write(&tsv, "# term\tgroup\nsplice_region_variant\t5\n").unwrap();
+
+    assert_eq!(convert(&dir, &vcf, "default.bed"), tabs("1 99 100 rs1 SNV A T 2 splice_region_variant\n"));
+    for groups in [&json, &tsv] {
+        let bed = convert_with(&dir, &vcf, "groups.bed", &["--variant-groups", groups.to_str().unwrap()]);
+        assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 5 splice_region_variant\n"));
+    }
+
+    fs::write(&json, r#"{"splice_region_variant": 9}"#).unwrap();
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--output", "-", "--variant-groups", json.to_str().unwrap()])
+        .output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not between 1 and 5"));
+}
+
+#[test]
+fn only_requested_groups_are_written() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "all.bed");
+    let groups = |bed: &str| bed.lines().map(|line| line.split('\t').nth(7).unwrap().to_string()).collect::<HashSet<_>>();
+    assert!(groups(&bed).len() > 2);
+
+    let filtered = convert_with(&dir, TEST_VCF, "filtered.bed", &["--groups", "1,3"]);
+    assert!(!filtered.is_empty());
+    assert!(groups(&filtered).iter().all(|group| group == "1" || group == "3"), "{}", filtered);
+    assert_eq!(filtered, bed.lines().filter(|line| {
+        let group = line.split('\t').nth(7).unwrap();
+        group == "1" || group == "3"
+    }).map(|line| format!("{}\n", line)).collect::<String>());
+}
+
+#[test]
+fn only_lines_at_least_as_severe_as_the_rank_are_written() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "impact.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "synonymous_variant", "SNV")),
+        format!("1\t300\trs3\tA\tT\t.\t.\tCSQ={},{}", csq("T", "intron_variant", "SNV"), csq("T", "stop_gained", "SNV")),
+        format!("1\t400\trs4\tA\tT\t.\t.\tCSQ={}", csq("T", "protein_altering_variant", "SNV")),
+    ]);
+
+    // protein_altering_variant is ranked 14, below missense_variant and stop_gained and above synonymous_variant
+    let bed = convert_with(&dir, &vcf, "coding.bed", &["--min-severity-rank", "14"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 1 missense_variant\n",
+        "1 299 300 rs3 SNV A T 1 stop_gained\n",
+        "1 399 400 rs4 SNV A T 1 protein_altering_variant\n"
+    )));
+}
+
+#[test]
+fn adjacent_lines_of_a_consequence_are_collapsed() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "runs.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+        format!("1\t101\trs2\tC\tG\t.\t.\tCSQ={}", csq("G", "intron_variant", "SNV")),
+        format!("1\t102\trs3\tG\tA\t.\t.\tCSQ={}", csq("A", "intron_variant", "SNV")),
+        format!("1\t103\trs4\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "runs.bed", &["--collapse-runs"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 102 rs1,rs2,rs3 SNV . . 3 intron_variant\n",
+        "1 102 103 rs4 SNV A T 1 missense_variant\n"
+    )));
+}
+
+#[test]
+fn lines_are_coloured_by_group() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "colour.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "intergenic_variant", "SNV")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "colour.bed", &["--color-by-group", "--extra-columns", "length"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 1 missense_variant 99 100 255,0,0 1\n",
+        "1 199 200 rs2 SNV A T 5 intergenic_variant 199 200 99,99,99 1\n"
+    )));
+
+    let colours = dir.path().join("colours.tsv");
+    fs::write(&colours, "1\t0,128,0\n").unwrap();
+    let bed = convert_with(&dir, &vcf, "override.bed", &["--color-by-group", "--group-colors", colours.to_str().unwrap()]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 1 missense_variant 99 100 0,128,0\n",
+        "1 199 200 rs2 SNV A T 5 intergenic_variant 199 200 99,99,99\n"
+    )));
+
+    fs::write(&colours, "1\t0,128\n").unwrap();
+    let output = vcf_to_bed()
+        .args(["--input", &vcf, "--severity", RANK_FILE, "--output", "-", "--color-by-group", "--group-colors", colours.to_str().unwrap()])
+        .output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("colour of group 1 is not R,G,B"));
+}
+
+#[test]
+fn lines_are_split_into_a_file_per_group() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "all.bed");
+    let prefix = dir.path().join("group_");
+    let status = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--sort", "--split-by-group"]).arg(&prefix)
+        .status().unwrap();
+    assert!(status.success());
+
+    let mut sorted = bed.lines().collect::<Vec<_>>();
+    sorted.sort_by_key(|line| {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        (fields[0].to_string(), fields[1].parse::<u64>().unwrap(), fields[2].parse::<u64>().unwrap())
+    });
+    for group in 1..=5 {
+        let expected = sorted.iter()
+            .filter(|line| line.split('\t').nth(7) == Some(&group.to_string()))
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        assert_eq!(read(&dir.path().join(format!("group_{}.bed", group))), expected, "group {}", group);
+    }
+    assert!(!dir.path().join("group_0.bed").exists());
+
+    let status = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--output-format", "jsonl", "--gzip", "--split-by-group"]).arg(&prefix)
+        .status().unwrap();
+    assert!(status.success());
+    let jsonl = read(&dir.path().join("group_5.jsonl.gz"));
+    assert!(!jsonl.is_empty());
+    assert!(jsonl.lines().all(|line| line.contains("\"group\":5")), "{}", jsonl);
+}
+
+#[test]
+fn group_files_hold_the_lines_of_the_contigs_in_parallel_and_deduplicated() {
+    let dir = TempDir::new().unwrap();
+    for extra in [&["--parallel", "2"][..], &["--dedup", "--sort-buffer", "2"]] {
+        let bed = convert_with(&dir, TEST_VCF, "all.bed", extra);
+        let prefix = dir.path().join("group_");
+        let status = vcf_to_bed()
+            .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--split-by-group"]).arg(&prefix)
+            .args(extra)
+            .status().unwrap();
+        assert!(status.success());
+
+        for group in 1..=5 {
+            let expected = bed.lines()
+                .filter(|line| line.split('\t').nth(7) == Some(&group.to_string()))
+                .map(|line| format!("{}\n", line))
+                .collect::<String>();
+            assert_eq!(read(&dir.path().join(format!("group_{}.bed", group))), expected, "{:?} group {}", extra, group);
+        }
+    }
+}
+
+#[test]
+fn regions_are_converted_with_and_without_index() {
+    let dir = TempDir::new().unwrap();
+    let mut plain = String::new();
+    MultiGzDecoder::new(File::open(TEST_VCF).unwrap()).read_to_string(&mut plain).unwrap();
+    let plain_vcf = dir.path().join("plain.vcf");
+    fs::write(&plain_vcf, plain).unwrap();
+
+    let regions = ["--region", "chr13:57000000-58000000", "--region", "GL000214.1:17000-40000", "--region", "KI270521.1:1784"];
+    let expected = convert(&dir, TEST_VCF, "all.bed").lines()
+        .filter(|line| line.starts_with("chr13\t57932508") || line.starts_with("GL000214.1\t17029") ||
+            line.starts_with("GL000214.1\t39434") || line.starts_with("KI270521.1\t1784"))
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    assert_eq!(expected.lines().count(), 5);
+
+    assert_eq!(convert_with(&dir, TEST_VCF, "indexed.bed", &regions), expected);
+    assert_eq!(convert_with(&dir, plain_vcf.to_str().unwrap(), "linear.bed", &regions), expected);
+    assert_eq!(convert_with(&dir, TEST_VCF, "parallel.bed", &[&regions[..], &["--parallel", "2"]].concat()), expected);
+}
+
+// CSI index with the same content as a tabix one - with min_shift 14 and depth 5 the bins are the same
+fn tbi_to_csi(tbi: &[u8]) -> Vec<u8> {
+    let mut tbi_bytes = Vec::new();
+    MultiGzDecoder::new(tbi).read_to_end(&mut tbi_bytes).unwrap();
+    let mut tbi = &tbi_bytes[4..];
+    let mut take = |n: usize| {
+        let (bytes, rest) = tbi.split_at(n);
+        tbi = rest;
+        bytes.to_vec()
+    };
+    let int = |bytes: &[u8]| i32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+
+    let n_ref = take(4);
+    let mut aux = take(24);
+    let names = take(4);
+    aux.extend(&names);
+    aux.extend(take(int(&names)));
+
+    let mut csi = b"CSI\x01".to_vec();
+    csi.extend(14i32.to_le_bytes());
+    csi.extend(5i32.to_le_bytes());
+    csi.extend((aux.len() as i32).to_le_bytes());
+    csi.extend(aux);
+    csi.extend(&n_ref);
+    for _ in 0..int(&n_ref) {
+        let n_bin = take(4);
+        csi.extend(&n_bin);
+        for _ in 0..int(&n_bin) {
+            csi.extend(take(4));
+            let n_chunk = take(4);
+            let chunks = take(16 * int(&n_chunk));
+            // offset of the first record overlapping the bin
+            csi.extend(chunks.get(..8).unwrap_or(&[0; 8]));
+            csi.extend(n_chunk);
+            csi.extend(chunks);
+        }
+        let n_intv = take(4);
+        take(8 * int(&n_intv));
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&csi).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn indexed_contig_is_read_on_its_own() {
+    let dir = TempDir::new().unwrap();
+    let vcf = dir.path().join("csi.vcf.gz");
+    fs::copy(TEST_VCF, &vcf).unwrap();
+    fs::write(dir.path().join("csi.vcf.gz.csi"), tbi_to_csi(&fs::read(format!("{}.tbi", TEST_VCF)).unwrap())).unwrap();
+    let vcf = vcf.to_str().unwrap();
+
+    let tbi = Index::for_vcf(TEST_VCF).unwrap().unwrap();
+    let csi = Index::for_vcf(vcf).unwrap().unwrap();
+    assert_eq!(tbi.contigs().collect::<Vec<_>>(), csi.contigs().collect::<Vec<_>>());
+
+    let mut all = String::new();
+    MultiGzDecoder::new(File::open(TEST_VCF).unwrap()).read_to_string(&mut all).unwrap();
+    let chr13 = all.lines().filter(|record| record.starts_with("chr13\t")).map(|record| format!("{}\n", record)).collect::<String>();
+    assert!(!chr13.is_empty() && chr13.len() < all.len());
+
+    for (index, path) in [(&tbi, TEST_VCF), (&csi, vcf)] {
+        let mut records = String::new();
+        index.contig_reader(path, "chr13").unwrap().read_to_string(&mut records).unwrap();
+        assert_eq!(records, chr13);
+
+        let mut records = String::new();
+        index.region_reader(path, "GL000214.1", 17000, 20000).unwrap().read_to_string(&mut records).unwrap();
+        assert!(records.lines().all(|record| record.starts_with("GL000214.1\t")), "{}", records);
+        assert!(records.contains("\t17030\t"), "{}", records);
+        assert!(!records.contains("\t39435\t"), "{}", records);
+    }
+
+    assert_eq!(convert_with(&dir, vcf, "csi.bed", &["--parallel", "2"]), convert(&dir, TEST_VCF, "tbi.bed"));
+}
+
+#[test]
+fn allele_frequency_column_is_opt_in() {
+    let dir = TempDir::new().unwrap();
+    let header = CSQ_HEADER.replace("|HGNC_ID", "|HGNC_ID|gnomADg_AF");
+    let entry = |allele: &str, consequence: &str, af: &str| format!("{}|||{}", csq(allele, consequence, "SNV"), af);
+    let vcf = write_vcf_with_header(&dir, "af.vcf", &[header], &[
+        format!("1\t100\trs1\tA\tG,T\t.\t.\tAF=0.5,0.25;CSQ={},{}", entry("G", "intron_variant", "0.001"), entry("T", "missense_variant", "0.02")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", entry("T", "intron_variant", "")),
+    ]);
+
+    assert_eq!(convert(&dir, &vcf, "plain.bed").lines().next().unwrap().split('\t').count(), 9);
+
+    let bed = convert_with(&dir, &vcf, "csq.bed", &["--extra-columns", "af", "--af-field", "gnomADg_AF"]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant 0.02\n1 199 200 rs2 SNV A T 3 intron_variant .\n"));
+
+    let bed = convert_with(&dir, &vcf, "info.bed", &["--extra-columns", "af"]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant 0.5\n1 199 200 rs2 SNV A T 3 intron_variant .\n"));
+}
+
+#[test]
+fn most_pathogenic_clinical_significance_is_written() {
+    let dir = TempDir::new().unwrap();
+    let header = CSQ_HEADER.replace("|HGNC_ID", "|HGNC_ID|CLIN_SIG");
+    let entry = |clin_sig: &str| format!("{}|||{}", csq("T", "missense_variant", "SNV"), clin_sig);
+    let vcf = write_vcf_with_header(&dir, "clin_sig.vcf", &[header], &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={},{}", entry("likely_benign"), entry("pathogenic&likely_pathogenic")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", entry("")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "clin_sig.bed", &["--extra-columns", "af,clin_sig"]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant . pathogenic\n1 199 200 rs2 SNV A T 1 missense_variant . .\n"));
+}
+
+#[test]
+fn alts_can_be_split_into_lines_of_their_own() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "alts.vcf", &[
+        format!("1\t100\trs1\tA\tG,T\t.\t.\tCSQ={},{}", csq("G", "intron_variant", "SNV"), csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tAT,G\t.\t.\tCSQ={},{}", csq("AT", "intron_variant", "insertion"), csq("G", "stop_gained", "SNV")),
+    ]);
+
+    let bed = convert(&dir, &vcf, "merged.bed");
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant\n1 200 200 rs2 insertion A AT,G 1 stop_gained\n"));
+
+    let bed = convert_with(&dir, &vcf, "split.bed", &["--split-alts"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A G 3 intron_variant\n",
+        "1 99 100 rs1 SNV A T 1 missense_variant\n",
+        "1 200 200 rs2 insertion A AT 3 intron_variant\n",
+        "1 199 200 rs2 SNV A G 1 stop_gained\n"
+    )));
+}
+
+#[test]
+fn consequence_delimiter_is_configurable() {
+    let dir = TempDir::new().unwrap();
+    // a , within an INFO value is percent-encoded
+    let vcf = write_vcf(&dir, "delimiter.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant%2Cmissense_variant%2C", "SNV")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "comma.bed", &["--consequence-delimiter", ","]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn check_validates_without_writing_output() {
+    let dir = TempDir::new().unwrap();
+    let bed = dir.path().join("check.bed");
+    let check = |vcf: &str| vcf_to_bed()
+        .args(["--check", "--input", vcf, "--severity", RANK_FILE])
+        .arg("--output").arg(&bed)
+        .output().unwrap();
+
+    let vcf = write_vcf_with_header(&dir, "csq.vcf", &[CSQ_HEADER], &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        "1\t200\trs2\tA\tT\t.\t.\t.".to_string(),
+    ]);
+    let output = check(&vcf);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("skipped 1 records: 0 long-ref, 1 no-csq"), "{}", stderr);
+    assert!(!bed.exists());
+
+    let output = check(TEST_VCF);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("does not define the CSQ INFO field"), "{}", stderr);
+    assert!(!bed.exists());
+}
+
+// run merge_bed of the sibling crate
+fn merge_bed(dir: &TempDir, input: &Path, name: &str, severity: &str) -> String {
+    let bed = dir.path().join(name);
+    let status = Command::new(env!("CARGO"))
+        .args(["run", "--quiet", "--manifest-path", concat!(env!("CARGO_MANIFEST_DIR"), "/../merge_bed/Cargo.toml"), "--"])
+        .args(["--severity", severity])
+        .arg(&bed).arg(input)
+        .status().unwrap();
+    assert!(status.success());
+
+    read(&bed)
+}
+
+#[test]
+fn dedup_matches_sort_then_merge_bed() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "duplicates.vcf", &[
+        format!("1\t300\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", csq("G", "intron_variant", "SNV")),
+        format!("1\t200\trs2\tA\tG\t.\t.\tCSQ={}", csq("G", "intron_variant", "SNV")),
+        format!("1\t250\trs2\tA\tC\t.\t.\tCSQ={}", csq("C", "intron_variant", "SNV")),
+    ]);
+
+    for (input, name) in [(vcf.as_str(), "duplicates"), (TEST_VCF, "test")] {
+        convert_with(&dir, input, &format!("{}.sorted.bed", name), &["--sort"]);
+        let merged = merge_bed(&dir, &dir.path().join(format!("{}.sorted.bed", name)), &format!("{}.merged.bed", name), RANK_FILE);
+        // a line replaced by a more severe one keeps the place of the first line of its id
+        let mut expected = merged.lines().collect::<Vec<_>>();
+        expected.sort_by_key(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            (fields[0].to_string(), fields[1].parse::<u64>().unwrap(), fields[2].parse::<u64>().unwrap())
+        });
+
+        let bed = convert_with(&dir, input, &format!("{}.dedup.bed", name), &["--dedup", "--sort-buffer", "2"]);
+        assert_eq!(bed.lines().collect::<Vec<_>>(), expected);
+    }
+}
+
+#[test]
+fn dedup_breaks_ties_by_group_as_merge_bed_does() {
+    let dir = TempDir::new().unwrap();
+    // upstream_gene_variant, group 5, ranked with intron_variant, group 3
+    let mut ranks: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&fs::read_to_string(RANK_FILE).unwrap()).unwrap();
+    ranks.insert("upstream_gene_variant".to_string(), ranks["intron_variant"].clone());
+    let severity = dir.path().join("tied.json");
+    fs::write(&severity, serde_json::to_string(&ranks).unwrap()).unwrap();
+    let severity = severity.to_str().unwrap();
+    let vcf = write_vcf(&dir, "tied.vcf", &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", csq("G", "upstream_gene_variant", "SNV")),
+        format!("1\t200\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+    ]);
+
+    let sorted = dir.path().join("tied.sorted.bed");
+    let dedup = dir.path().join("tied.dedup.bed");
+    for (output, extra) in [(&sorted, "--sort"), (&dedup, "--dedup")] {
+        let status = vcf_to_bed()
+            .args(["--input", &vcf, "--severity", severity, extra])
+            .arg("--output").arg(output)
+            .status().unwrap();
+        assert!(status.success());
+    }
+
+    let merged = merge_bed(&dir, &sorted, "tied.merged.bed", severity);
+    assert_eq!(merged, tabs("1 199 200 rs1 SNV A T 3 intron_variant\n"));
+    assert_eq!(read(&dedup), merged);
+}
+
+#[test]
+fn info_tags_are_passed_through() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "info.vcf", &[
+        format!("1\t100\trs1\tA\tG,T\t.\t.\tRS=1;dbSNPBuildID=52,129;CSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tRS=2;CSQ={}", csq("T", "intron_variant", "SNV")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "info.bed", &["--info-passthrough", "RS,dbSNPBuildID"]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant 1 52,129\n1 199 200 rs2 SNV A T 3 intron_variant 2 .\n"));
+}
+
+#[test]
+fn severity_can_be_written_as_so_accession() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "accession.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+    ]);
+    let accessions = dir.path().join("accessions.json");
+    fs::write(&accessions, r#"{"intron_variant": "SO:9999999"}"#).unwrap();
+
+    let bed = convert(&dir, &vcf, "term.bed");
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 missense_variant\n1 199 200 rs2 SNV A T 3 intron_variant\n"));
+
+    let bed = convert_with(&dir, &vcf, "accession.bed", &["--severity-as", "accession", "--so-accessions", accessions.to_str().unwrap()]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A T 1 SO:0001583\n1 199 200 rs2 SNV A T 3 SO:9999999\n"));
+}
+
+#[test]
+fn chromosome_names_follow_chr_style() {
+    let dir = TempDir::new().unwrap();
+    let record = |chromosome: &str, position: u32| {
+        format!("{}\t{}\trs{}\tA\tT\t.\t.\tCSQ={}", chromosome, position, position, csq("T", "missense_variant", "SNV"))
+    };
+    let vcf = write_vcf(&dir, "names.vcf", &[record("1", 100), record("chr2", 200), record("MT", 300), record("chrM", 400)]);
+    let chromosomes = |bed: String| bed.lines().map(|line| line.split('\t').next().unwrap().to_string()).collect::<Vec<_>>();
+
+    assert_eq!(chromosomes(convert(&dir, &vcf, "vcf.bed")), ["1", "chr2", "MT", "chrM"]);
+    assert_eq!(chromosomes(convert_with(&dir, &vcf, "ucsc.bed", &["--chr-style", "ucsc"])), ["chr1", "chr2", "chrM", "chrM"]);
+    assert_eq!(chromosomes(convert_with(&dir, &vcf, "ensembl.bed", &["--chr-style", "ensembl"])), ["1", "2", "MT", "MT"]);
+}
+
+#[test]
+fn contigs_can_be_included_and_excluded() {
+    let dir = TempDir::new().unwrap();
+    let record = |chromosome: &str| format!("{}\t100\trs1\tA\tT\t.\t.\tCSQ={}", chromosome, csq("T", "missense_variant", "SNV"));
+    let vcf = write_vcf(&dir, "contigs.vcf", &[record("chr1"), record("chr1_KI270706v1_random"), record("chr1_KI270762v1_alt"), record("chr2_KI270773v1_alt"), record("chrX")]);
+    let chromosomes = |bed: String| bed.lines().map(|line| line.split('\t').next().unwrap().to_string()).collect::<Vec<_>>();
+
+    let bed = convert_with(&dir, &vcf, "exclude.bed", &["--exclude-contigs", "*_alt"]);
+    assert_eq!(chromosomes(bed), ["chr1", "chr1_KI270706v1_random", "chrX"]);
+
+    let contigs = dir.path().join("contigs.txt");
+    fs::write(&contigs, "chr1*\nchrX\n").unwrap();
+    let bed = convert_with(&dir, &vcf, "include.bed", &["--include-contigs", contigs.to_str().unwrap(), "--exclude-contigs", "*_alt,*_random"]);
+    assert_eq!(chromosomes(bed), ["chr1", "chrX"]);
+}
+
+#[test]
+fn sorted_output_follows_genome_contig_order() {
+    let dir = TempDir::new().unwrap();
+    let record = |chromosome: &str, position: u32| {
+        format!("{}\t{}\trs{}{}\tA\tT\t.\t.\tCSQ={}", chromosome, position, chromosome, position, csq("T", "missense_variant", "SNV"))
+    };
+    let vcf = write_vcf(&dir, "contigs.vcf", &[record("chr10", 5), record("chr2", 9), record("chr1", 7), record("chr10", 1), record("chrM", 3)]);
+    let fai = dir.path().join("genome.fa.fai");
+    fs::write(&fai, "chr1\t248956422\t112\t70\t71\nchr2\t242193529\t252513167\t70\t71\nchr10\t133797422\t498166716\t70\t71\n").unwrap();
+    let positions = |bed: String| bed.lines().map(|line| line.split('\t').take(2).collect::<Vec<_>>().join(":")).collect::<Vec<_>>();
+
+    let bed = convert_with(&dir, &vcf, "bytes.bed", &["--sort"]);
+    assert_eq!(positions(bed), ["chr1:6", "chr10:0", "chr10:4", "chr2:8", "chrM:2"]);
+
+    let bed = convert_with(&dir, &vcf, "genome.bed", &["--sort", "--genome", fai.to_str().unwrap()]);
+    assert_eq!(positions(bed), ["chr1:6", "chr2:8", "chr10:0", "chr10:4", "chrM:2"]);
+}
+
+#[test]
+fn alleles_are_uppercased_before_merging() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "case.vcf", &[
+        format!("1\t100\trs1\tA\tt\t.\t.\tCSQ={}", csq("t", "intron_variant", "SNV")),
+        format!("1\t100\trs1\ta\tT,g\t.\t.\tCSQ={},{}", csq("T", "intron_variant", "SNV"), csq("g", "missense_variant", "SNV")),
+    ]);
+
+    let bed = convert(&dir, &vcf, "upper.bed");
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant\n"));
+
+    let bed = convert_with(&dir, &vcf, "preserved.bed", &["--preserve-case"]);
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV A t 3 intron_variant\n1 99 100 rs1 SNV a T,g 1 missense_variant\n"));
+}
+
+#[test]
+fn spanning_deletion_allele_is_left_out() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "star.vcf", &[
+        format!("1\t100\trs1\tG\tA,*\t.\t.\tCSQ={},{}", csq("A", "intron_variant", "SNV"), csq("*", "stop_gained", "sequence_alteration")),
+        format!("1\t200\trs2\tG\t*\t.\t.\tCSQ={}", csq("*", "stop_gained", "sequence_alteration")),
+    ]);
+
+    let bed = convert(&dir, &vcf, "star.bed");
+    assert_eq!(bed, tabs("1 99 100 rs1 SNV G A 3 intron_variant\n"));
+}
+
+#[test]
+fn a_line_is_written_for_each_transcript() {
+    let dir = TempDir::new().unwrap();
+    let entry = |consequence: &str, feature: &str| format!("T|{}|||||{}{}SNV", consequence, feature, "|".repeat(15));
+    let vcf = write_vcf_with_header(&dir, "transcripts.vcf", &[CSQ_HEADER], &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={},{},{},{}",
+            entry("synonymous_variant", "ENST1"), entry("intron_variant", "ENST2"),
+            entry("missense_variant", "ENST1"), entry("upstream_gene_variant", "")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "transcripts.bed", &["--per-transcript"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 5 upstream_gene_variant .\n",
+        "1 99 100 rs1 SNV A T 1 missense_variant ENST1\n",
+        "1 99 100 rs1 SNV A T 3 intron_variant ENST2\n"
+    )));
+    assert_eq!(convert(&dir, &vcf, "variant.bed"), tabs("1 99 100 rs1 SNV A T 1 missense_variant\n"));
+}
+
+#[test]
+fn distinct_consequences_are_counted() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "consequences.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={},{},{},{}",
+            csq("T", "missense_variant", "SNV"), csq("T", "missense_variant&splice_region_variant", "SNV"),
+            csq("T", "intron_variant", "SNV"), csq("T", "splice_region_variant", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "intergenic_variant", "SNV")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "consequences.bed", &["--extra-columns", "n_consequences"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 1 missense_variant 3\n",
+        "1 199 200 rs2 SNV A T 5 intergenic_variant 1\n"
+    )));
+}
+
+#[test]
+fn length_column_is_the_interval_length() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "length.vcf", &[
+        format!("1\t100\trs1\tA\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tA\tATT\t.\t.\tCSQ={}", csq("TT", "intron_variant", "insertion")),
+        format!("1\t300\trs3\tACG\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+    ]);
+
+    let bed = convert_with(&dir, &vcf, "length.bed", &["--extra-columns", "length"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A T 1 missense_variant 1\n",
+        "1 200 200 rs2 insertion A ATT 3 intron_variant 0\n",
+        "1 299 302 rs3 deletion ACG A 3 intron_variant 3\n"
+    )));
+}
+
+#[test]
+fn vcf_pos_column_is_the_record_position() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert_with(&dir, TEST_VCF, "vcf_pos.bed", &["--extra-columns", "vcf_pos"]);
+    assert!(!bed.is_empty());
+
+    let mut vcf = String::new();
+    MultiGzDecoder::new(File::open(TEST_VCF).unwrap()).read_to_string(&mut vcf).unwrap();
+    let records = vcf.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            (fields[0].to_string(), fields[2].to_string(), fields[1].parse::<u64>().unwrap())
+        })
+        .collect::<HashSet<_>>();
+    for line in bed.lines() {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let position = fields[9].parse::<u64>().unwrap();
+        let record = records.iter().find(|(chromosome, ids, pos)| {
+            chromosome == fields[0] && *pos == position && ids.split(';').any(|id| id == fields[3])
+        });
+        assert!(record.is_some(), "{}", line);
+    }
+}
+
+#[test]
+fn severity_can_be_read_from_tsv() {
+    let dir = TempDir::new().unwrap();
+    let ranks = serde_json::from_str::<HashMap<String, String>>(&fs::read_to_string(RANK_FILE).unwrap()).unwrap();
+    let tsv = dir.path().join("severity.tsv");
+    let lines = ranks.iter().map(|(term, rank)| format!("{}\t{}\n", term, rank)).collect::<String>();
+    fs::write(&tsv, format!("# term\trank\n{}", lines)).unwrap();
+
+    let json_bed = convert(&dir, TEST_VCF, "json.bed");
+    let output = vcf_to_bed().args(["--input", TEST_VCF, "--output", "-"]).arg("--severity").arg(&tsv).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), json_bed);
+
+    fs::write(&tsv, "missense_variant\t13\nintron_variant 28\n").unwrap();
+    let output = vcf_to_bed().args(["--input", TEST_VCF, "--output", "-"]).arg("--severity").arg(&tsv).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("severity.tsv line 2"), "{}", stderr);
+}
+
+#[test]
+fn gzipped_severity_file_is_read() {
+    let dir = TempDir::new().unwrap();
+    let gzipped = dir.path().join("severity.json.gz");
+    let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), Compression::default());
+    encoder.write_all(&fs::read(RANK_FILE).unwrap()).unwrap();
+    encoder.finish().unwrap();
+
+    let json_bed = convert(&dir, TEST_VCF, "json.bed");
+    let output = vcf_to_bed().args(["--input", TEST_VCF, "--output", "-"]).arg("--severity").arg(&gzipped).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), json_bed);
+}
+
+#[test]
+fn crlf_line_endings_are_read() {
+    let dir = TempDir::new().unwrap();
+    let records = [
+        format!("1\t100\trs1\tA\tG,T\t.\t.\tCSQ={},{}", csq("G", "intron_variant", "SNV"), csq("T", "missense_variant", "SNV")),
+        format!("1\t200\trs2\tAC\tA\t.\t.\tCSQ={}", csq("-", "intron_variant", "deletion")),
+    ];
+    let vcf = write_vcf(&dir, "lf.vcf", &records);
+    let crlf_vcf = dir.path().join("crlf.vcf");
+    fs::write(&crlf_vcf, fs::read_to_string(&vcf).unwrap().replace('\n', "\r\n")).unwrap();
+    let tsv = dir.path().join("severity.tsv");
+    fs::write(&tsv, "# term\trank\r\nmissense_variant\t13 \r\nintron_variant\t28\t\r\n").unwrap();
+
+    let expected = tabs("1 99 100 rs1 SNV A G,T 1 missense_variant\n1 199 201 rs2 deletion AC A 3 intron_variant\n");
+    assert_eq!(convert(&dir, &vcf, "lf.bed"), expected);
+    assert_eq!(convert(&dir, crlf_vcf.to_str().unwrap(), "crlf.bed"), expected);
+    assert_eq!(convert_with(&dir, crlf_vcf.to_str().unwrap(), "samples.bed", &["--ignore-samples", "false"]), expected);
+    let output = vcf_to_bed().args(["--input", &vcf, "--output", "-"]).arg("--severity").arg(&tsv).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn out_of_order_records_are_reported() {
+    let dir = TempDir::new().unwrap();
+    let record = |chromosome: &str, position: u32| {
+        format!("{}\t{}\trs{}\tA\tT\t.\t.\tCSQ={}", chromosome, position, position, csq("T", "missense_variant", "SNV"))
+    };
+    let vcf = write_vcf(&dir, "unsorted.vcf", &[record("1", 200), record("1", 100), record("2", 300), record("1", 400)]);
+    let run = |extra: &[&str]| vcf_to_bed().args(["--input", &vcf, "--output", "-"]).args(extra).output().unwrap();
+
+    let output = run(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert!(stderr.contains("variant at 1:100 comes after 1:200"), "{}", stderr);
+    assert!(stderr.contains("2 records of"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 4);
+
+    let output = run(&["--strict"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("is not sorted: variant at 1:100 comes after 1:200"), "{}", stderr);
+}
+
+#[test]
+fn sample_columns_are_ignored() {
+    let dir = TempDir::new().unwrap();
+    let records = [
+        ("1\t100\trs1\tA\tG,T\t.\t.\t", format!("CSQ={},{}", csq("G", "intron_variant", "SNV"), csq("T", "missense_variant", "SNV"))),
+        ("2\t200\trs2\tA\tT\t.\tPASS\t", format!("DP=10;CSQ={}", csq("T", "intron_variant", "SNV"))),
+    ];
+    let sites = records.iter().map(|(site, info)| format!("{}{}", site, info)).collect::<Vec<_>>();
+    let sites_vcf = write_vcf(&dir, "sites.vcf", &sites);
+
+    let mut content = String::from("##fileformat=VCFv4.2\n##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n");
+    content += "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2\tS3\n";
+    for site in &sites {
+        content += &format!("{}\tGT:CSQ\t0/1:stop_gained\t1|1:.\t./.:frameshift_variant\n", site);
+    }
+    let samples_vcf = dir.path().join("samples.vcf");
+    fs::write(&samples_vcf, content).unwrap();
+    let samples_vcf = samples_vcf.to_str().unwrap();
+
+    let expected = convert(&dir, &sites_vcf, "sites.bed");
+    assert_eq!(expected, tabs("1 99 100 rs1 SNV A G,T 1 missense_variant\n2 199 200 rs2 SNV A T 3 intron_variant\n"));
+    assert_eq!(convert(&dir, samples_vcf, "samples.bed"), expected);
+    assert_eq!(convert_with(&dir, samples_vcf, "parsed.bed", &["--ignore-samples", "false"]), expected);
+}
+
+#[test]
+fn inputs_are_converted_in_order_into_one_output() {
+    let dir = TempDir::new().unwrap();
+    let first = write_vcf(&dir, "1.vcf", &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", csq("G", "intron_variant", "SNV")),
+        format!("1\t300\trs2\tC\tT\t.\t.\tCSQ={}", csq("T", "missense_variant", "SNV"))
+    ]);
+    let second = write_vcf(&dir, "2.vcf.gz", &[
+        format!("2\t200\trs3\tG\tA\t.\t.\tCSQ={}", csq("A", "stop_gained", "SNV"))
+    ]);
+
+    let bed = convert_with(&dir, &first, "both.bed", &["--input", &second]);
+    assert_eq!(bed, convert(&dir, &first, "1.bed") + &convert(&dir, &second, "2.bed"));
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV A G 3 intron_variant\n",
+        "1 299 300 rs2 SNV C T 1 missense_variant\n",
+        "2 199 200 rs3 SNV G A 1 stop_gained\n"
+    )));
+
+    let other = write_vcf_with_header(&dir, "other.vcf", &[
+        "##INFO=<ID=CSQ,Number=.,Type=String,Description=\"Consequence annotations from Ensembl VEP. Format: Allele|Consequence\">"
+    ], &["3\t100\trs4\tA\tG\t.\t.\tCSQ=G|intron_variant"]);
+    let output = vcf_to_bed()
+        .args(["--input", &first, "--input", &other, "--severity", RANK_FILE, "--output", "-"])
+        .output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differs from that of"));
+}
+
+#[test]
+fn consequence_terms_are_lowercased_before_lookup() {
+    let dir = TempDir::new().unwrap();
+    let input = write_vcf(&dir, "upper.vcf", &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", csq("G", "MISSENSE_VARIANT", "SNV")),
+        format!("1\t200\trs2\tC\tT\t.\t.\tCSQ={}", csq("T", "3_PRIME_UTR_VARIANT&Intron_Variant", "SNV"))
+    ]);
+    let groups = dir.path().join("groups.txt");
+    fs::write(&groups, "Missense_Variant\t5\n").unwrap();
+    let groups = groups.to_str().unwrap();
+
+    // SO terms such as 3_prime_UTR_variant are not all lowercase, they are matched in their own case
+    let output = vcf_to_bed().args(["--input", &input, "--severity", RANK_FILE, "--output", "-"]).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), tabs(concat!(
+        "1 99 100 rs1 SNV A G 1 missense_variant\n",
+        "1 199 200 rs2 SNV C T 3 3_prime_UTR_variant\n"
+    )));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("3_prime_UTR_variant"));
+    let grouped = convert_with(&dir, &input, "grouped.bed", &["--variant-groups", groups]);
+    assert_eq!(grouped.lines().next(), Some(tabs("1 99 100 rs1 SNV A G 5 missense_variant").as_str()));
+    let kept = convert_with(&dir, &input, "kept.bed", &["--lowercase-consequences", "false"]);
+    assert_eq!(kept.lines().next(), Some(tabs("1 99 100 rs1 SNV A G 0 MISSENSE_VARIANT").as_str()));
+}
+
+#[test]
+fn skipping_more_than_the_max_skip_fraction_has_its_own_exit_status() {
+    let dir = TempDir::new().unwrap();
+    let input = write_vcf(&dir, "input.vcf", &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", csq("G", "intron_variant", "SNV")),
+        "1\t200\trs2\tC\tT\t.\t.\tDP=3".to_string(),
+        format!("1\t300\trs3\tG\tA\t.\t.\tCSQ={}", csq("A", "intron_variant", "SNV")),
+        format!("1\t400\trs4\tT\tC\t.\t.\tCSQ={}", csq("C", "intron_variant", "SNV"))
+    ]);
+
+    let bed = dir.path().join("output.bed");
+    for extra in [&[][..], &["--threads", "2"][..]] {
+        let output = vcf_to_bed()
+            .args(["--input", &input, "--severity", RANK_FILE, "--max-skip-fraction", "0.2"])
+            .arg("--output").arg(&bed)
+            .args(extra)
+            .output().unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 1 of 4 records, more than --max-skip-fraction 0.2"));
+        assert_eq!(read(&bed).lines().count(), 3);
+    }
+    assert_eq!(convert_with(&dir, &input, "within.bed", &["--max-skip-fraction", "0.25"]).lines().count(), 3);
+
+    let output = vcf_to_bed().args(["--input", &input, "--severity", RANK_FILE, "--output", "bad.bed", "--max-skip-fraction", "2"])
+        .output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let output = vcf_to_bed().args(["--input", "missing.vcf", "--severity", RANK_FILE, "--output", "-"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn csq_entries_of_another_width_than_the_format_are_reported() {
+    let dir = TempDir::new().unwrap();
+    let full = format!("{}||", csq("G", "missense_variant", "SNV"));
+    // Allele and Consequence but no VARIANT_CLASS, so the Format has more subfields than the entry
+    let short = "G|intron_variant";
+    let input = write_vcf_with_header(&dir, "input.vcf", &[CSQ_HEADER], &[
+        format!("1\t100\trs1\tA\tG\t.\t.\tCSQ={}", full),
+        format!("1\t200\trs2\tA\tG\t.\t.\tCSQ={},{}", full, short)
+    ]);
+
+    let output = vcf_to_bed().args(["--input", &input, "--severity", RANK_FILE, "--output", "-"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("CSQ entry of variant at 1:200 has 2 subfields, the header Format has 24"), "{}", stderr);
+    assert!(!stderr.contains("1:100"), "{}", stderr);
+
+    let output = vcf_to_bed().args(["--input", &input, "--severity", RANK_FILE, "--output", "-", "--strict"]).output().unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn binary_records_read_back_as_the_bed_lines() {
+    let dir = TempDir::new().unwrap();
+    let extra = ["--extra-columns", "length,vcf_pos"];
+    let bed = convert_with(&dir, TEST_VCF, "lines.bed", &extra);
+    for gzip in [&[][..], &["--gzip"][..]] {
+        let path = dir.path().join("lines.bin");
+        let status = vcf_to_bed()
+            .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--output-format", "bin"])
+            .arg("--output").arg(&path)
+            .args(extra).args(gzip)
+            .status().unwrap();
+        assert!(status.success());
+
+        let mut content = fs::read(&path).unwrap();
+        if !gzip.is_empty() {
+            let mut plain = Vec::new();
+            MultiGzDecoder::new(content.as_slice()).read_to_end(&mut plain).unwrap();
+            content = plain;
+        }
+        let mut input = content.as_slice();
+        binary::read_magic(&mut input).unwrap();
+        let mut lines = String::new();
+        while let Some(record) = binary::read_record(&mut input).unwrap() {
+            lines += &format!("{}\t{}\t{}\t{}\n", record.chromosome, record.start, record.end, record.columns.join("\t"));
+        }
+        assert_eq!(lines, bed);
+    }
+
+    let output = vcf_to_bed()
+        .args(["--input", TEST_VCF, "--severity", RANK_FILE, "--output-format", "bin", "--split-by-group", "group"])
+        .output().unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn impact_column_is_the_impact_of_the_most_severe_consequence() {
+    let dir = TempDir::new().unwrap();
+    let vcf = write_vcf(&dir, "impact.vcf", &[
+        format!("1\t100\trs1\tC\tT\t.\t.\tCSQ={},{}", csq("T", "missense_variant", "SNV"), csq("T", "stop_gained", "SNV")),
+        format!("1\t200\trs2\tA\tT\t.\t.\tCSQ={}", csq("T", "synonymous_variant", "SNV")),
+        format!("1\t300\trs3\tA\tT\t.\t.\tCSQ={}", csq("T", "intron_variant", "SNV")),
+    ]);
+    let impacts = dir.path().join("impacts.json");
+    fs::write(&impacts, r#"{"intron_variant": "LOW"}"#).unwrap();
+
+    let bed = convert_with(&dir, &vcf, "impact.bed", &["--extra-columns", "impact"]);
+    assert_eq!(bed, tabs(concat!(
+        "1 99 100 rs1 SNV C T 1 stop_gained HIGH\n",
+        "1 199 200 rs2 SNV A T 3 synonymous_variant LOW\n",
+        "1 299 300 rs3 SNV A T 3 intron_variant MODIFIER\n"
+    )));
+
+    let bed = convert_with(&dir, &vcf, "custom.bed", &["--extra-columns", "impact", "--impacts", impacts.to_str().unwrap()]);
+    assert!(bed.ends_with(&tabs("1 299 300 rs3 SNV A T 3 intron_variant LOW\n")));
+}
+
+#[test]
+fn fixture_output_is_unchanged() {
+    let dir = TempDir::new().unwrap();
+    let bed = convert(&dir, TEST_VCF, "fixture.bed");
+
+    assert_eq!(bed, tabs(concat!(
+        "1 15110140 15110159 rs1356759330 indel TTGTGTATGTGTGTGCATA T,TTGTGTATGTGTGTGCATATGTGTATGTGTGTGCATA 3 intron_variant\n",
+        "chr1 10107 10114 rs1377973775 deletion CAACCCT C 5 upstream_gene_variant\n",
+        "chr13 32357724 32357725 rs754065641 SNV G C 2 splice_polypyrimidine_tract_variant\n",
+        "chr13 57932508 57932508 rs1555257542 insertion T TATATATATACACAC 5 intergenic_variant\n",
+        "chr13 57932508 57932508 rs1555257544 insertion T TATATATATATACACACACACACAC 5 intergenic_variant\n",
+        "chr19 60557 60558 rs1260281513 SNV A G,T 5 downstream_gene_variant\n",
+        "NC_000001.11 10054 10056 rs768019142 indel TA T,TAA 5 upstream_gene_variant\n",
+        "NC_000001.11 63737 63740 rs869033224 substitution ACT CTA 3 non_coding_transcript_exon_variant\n",
+        "2D 2306439 2306441 2D_2306439 sequence_alteration TT T,TG 5 intergenic_variant\n",
+        "KI270521.1 1593 1596 rs1556105830 indel ACT AA,AG 5 intergenic_variant\n",
+        "KI270521.1 1784 1784 rs2088361900 insertion G GTGAT 5 intergenic_variant\n",
+        "GL000214.1 10121 10123 rs71287572 indel GG GTGAGTTT 5 intergenic_variant\n",
+        "GL000214.1 17029 17030 rs4023684 SNV C G,T 5 intergenic_variant\n",
+        "GL000214.1 39434 39434 rs4023573 insertion C CTTT,CTTTTTTTTT 5 intergenic_variant\n",
+    )));
+}
@@ -0,0 +1,41 @@
+/*
+ * See the NOTICE file distributed with this work for additional information
+ * regarding copyright ownership.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Output of a small annotated VCF compared byte for byte with a committed BED file
+//!
+//! The VCF has an SNV, an insertion, a deletion, multiallelic records, a record with two ids and one without CSQ.
+//! After an intended change of the output, regenerate the BED file with `UPDATE_GOLDEN=1 cargo test --test golden`
+//! and review its diff.
+
+use std::{fs, process::Command};
+
+const GOLDEN_VCF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../tests/data/test_vcftobed_golden.vcf.gz");
+const GOLDEN_BED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../tests/data/test_vcftobed_golden.bed");
+const RANK_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../../assets/variation_consequnce_rank.json");
+
+#[test]
+fn output_matches_golden_bed() {
+    let output = Command::new(env!("CARGO_BIN_EXE_vcf_to_bed"))
+        .args(["--input", GOLDEN_VCF, "--output", "-", "--severity", RANK_FILE, "--quiet"])
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(GOLDEN_BED, &output.stdout).unwrap();
+    }
+    let golden = fs::read(GOLDEN_BED).unwrap();
+    assert!(output.stdout == golden, "output differs from {}:\n{}", GOLDEN_BED, String::from_utf8_lossy(&output.stdout));
+}